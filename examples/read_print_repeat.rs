@@ -1,24 +1,28 @@
 use async_std::{io, task};
-use unix_fifo_async::NamedPipePath;
+use unix_fifo_async::{serve::ServeOptions, NamedPipePath};
 
 /// Repeatedly reads from `my_pipe` and prints the result.
 /// Usage:
 /// ```sh
 /// $ cargo run --example read_print_repeat &
-/// Waiting for message...
+/// Waiting for messages...
 /// $ printf "something" > my_pipe
 /// Received message: something
-/// Waiting for message...
 /// ```
 fn main() -> io::Result<()> {
     task::block_on(async {
         let pipe = NamedPipePath::new("./my_pipe");
-        let reader = pipe.open_read();
-        loop {
-            reader.ensure_pipe_exists().unwrap();
-            println!("Waiting for message...");
-            let next_msg = reader.read_string().await?;
-            println!("Received message: {}", next_msg);
-        }
+        pipe.ensure_exists().unwrap();
+        println!("Waiting for messages...");
+        let server = pipe.open_read().serve(
+            |message| async move {
+                let message = String::from_utf8(message)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                println!("Received message: {}", message);
+                Ok(())
+            },
+            ServeOptions::new(),
+        );
+        server.join().await
     })
 }
@@ -0,0 +1,458 @@
+//! A pipe writer owned by a background task, shared through cheap handles.
+//!
+//! [`PipeWriterHandle::spawn`](struct.PipeWriterHandle.html#method.spawn)
+//! starts a task that owns the pipe and sends everything pushed into its
+//! queue as [frames](../frame/index.html). Handles can be cloned into every
+//! part of a program that produces messages:
+//!
+//! ```no_run
+//! # fn main() -> async_std::io::Result<()> { async_std::task::block_on(async {
+//! use async_std::task;
+//! use unix_fifo_async::actor::{PipeWriterHandle, WriterOptions};
+//!
+//! let events = PipeWriterHandle::spawn("./events", WriterOptions::new().queue_capacity(128));
+//! let producer = events.clone();
+//! task::spawn(async move { producer.send(&b"started"[..]).await });
+//! events.send(&b"tick"[..]).await?;
+//! // Before shutting down, wait for the queue to be written
+//! events.flush().await?;
+//! # Ok(())
+//! # })}
+//! ```
+//!
+//! The task opens the pipe when the first message arrives, waiting for a
+//! reader, and reopens it with a backoff whenever the reader goes away. A
+//! message that couldn't be written is sent again once the pipe is
+//! reopened, so messages aren't reordered. Messages that were written but
+//! still sat in the pipe when the reader went away are lost, though; the
+//! task can't tell they were never read. Once all handles are dropped, the
+//! task sends what's left in the queue and exits. It only keeps trying for
+//! the [`drain_timeout`](struct.WriterOptions.html#method.drain_timeout)
+//! though, so a pipe that never gets a reader doesn't keep it around
+//! forever; whatever isn't written by then is dropped.
+//!
+//! Errors that retrying won't fix, like not being allowed to open the pipe,
+//! stop the task and drop the queue. [`flush`](struct.PipeWriterHandle.html#method.flush)
+//! and later sends fail with that error, and
+//! [`last_error`](struct.PipeWriterHandle.html#method.last_error) shows
+//! what the task is running into while it's still retrying.
+use crate::{frame::FramedWriter, util::is_transient, NamedPipePath, ShutdownToken};
+use async_std::{future, io, task};
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::task::{Poll, Waker};
+use std::time::Duration;
+
+/// Initial delay before the task retries after an error.
+const RETRY_MIN: Duration = Duration::from_millis(10);
+/// Upper bound for the exponential backoff between retries.
+const RETRY_MAX: Duration = Duration::from_secs(1);
+/// How long the task keeps sending after all handles are gone, by default.
+const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Settings for the task started by
+/// [`PipeWriterHandle::spawn`](struct.PipeWriterHandle.html#method.spawn).
+#[derive(Clone)]
+pub struct WriterOptions {
+    capacity: usize,
+    chunked: bool,
+    checksum: bool,
+    drain_timeout: Duration,
+    shutdown: ShutdownToken,
+}
+
+impl WriterOptions {
+    /// Creates the default options: a queue of 64 messages, plain frames
+    /// without checksums, and a drain timeout of 5 seconds.
+    pub fn new() -> Self {
+        Self {
+            capacity: 64,
+            chunked: false,
+            checksum: false,
+            drain_timeout: DEFAULT_DRAIN_TIMEOUT,
+            shutdown: ShutdownToken::new(),
+        }
+    }
+    /// Sets how many messages can wait in the queue before
+    /// [`send`](struct.PipeWriterHandle.html#method.send) waits for room.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is 0.
+    pub fn queue_capacity(mut self, capacity: usize) -> Self {
+        assert!(capacity > 0, "queue capacity must be at least 1");
+        self.capacity = capacity;
+        self
+    }
+    /// Splits messages into chunks that are written atomically, so other
+    /// processes can write to the same pipe; see
+    /// [`FramedWriter::chunked`](../frame/struct.FramedWriter.html#method.chunked).
+    pub fn chunked(mut self, chunked: bool) -> Self {
+        self.chunked = chunked;
+        self
+    }
+    /// Appends a CRC32 to every frame, so the reader can detect corruption.
+    pub fn checksum(mut self, checksum: bool) -> Self {
+        self.checksum = checksum;
+        self
+    }
+    /// Sets how long the task keeps trying to send what's left in the queue
+    /// once all handles are dropped, before it drops the rest and exits.
+    pub fn drain_timeout(mut self, timeout: Duration) -> Self {
+        self.drain_timeout = timeout;
+        self
+    }
+    /// Stops the task when `token` fires, dropping whatever is still in the
+    /// queue.
+    pub fn with_shutdown(mut self, token: ShutdownToken) -> Self {
+        self.shutdown = token;
+        self
+    }
+}
+
+impl Default for WriterOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct State {
+    queue: VecDeque<Vec<u8>>,
+    capacity: usize,
+    /// How many messages were ever queued and written, so `flush` can tell
+    /// when the ones queued before it are out.
+    queued: u64,
+    written: u64,
+    handles: usize,
+    /// Fired once the drain timeout passed after the last handle was
+    /// dropped.
+    drain: ShutdownToken,
+    drain_timeout: Duration,
+    stopped: bool,
+    /// The last error the task ran into, cleared once a message is written.
+    error: Option<io::Error>,
+    /// The task, waiting for a message.
+    task: Option<Waker>,
+    /// Handles waiting for room in the queue or for messages to be written.
+    waiters: Vec<Waker>,
+}
+
+impl State {
+    fn push(&mut self, message: Vec<u8>) {
+        self.queue.push_back(message);
+        self.queued += 1;
+        if let Some(waker) = self.task.take() {
+            waker.wake();
+        }
+    }
+    /// Registers `waker` to be woken by `wake_waiters`, unless it already is.
+    fn wait(&mut self, waker: &Waker) {
+        if !self.waiters.iter().any(|w| w.will_wake(waker)) {
+            self.waiters.push(waker.clone());
+        }
+    }
+    /// Returns why the task stopped.
+    fn stopped(&self) -> io::Error {
+        match &self.error {
+            Some(e) => copy_error(e),
+            None => io::Error::new(io::ErrorKind::BrokenPipe, "the writer task has stopped"),
+        }
+    }
+    fn wake_waiters(&mut self) {
+        for waker in self.waiters.drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+type Shared = Arc<Mutex<State>>;
+
+fn copy_error(e: &io::Error) -> io::Error {
+    io::Error::new(e.kind(), e.to_string())
+}
+
+/// A handle for sending messages through a writer task.
+///
+/// Cloning a handle is cheap; all clones feed the same queue.
+pub struct PipeWriterHandle {
+    shared: Shared,
+}
+
+impl PipeWriterHandle {
+    /// Starts a task writing to the pipe at `path` and returns a handle to
+    /// it.
+    pub fn spawn<P: Into<PathBuf>>(path: P, options: WriterOptions) -> Self {
+        let shared = Arc::new(Mutex::new(State {
+            queue: VecDeque::new(),
+            capacity: options.capacity,
+            queued: 0,
+            written: 0,
+            handles: 1,
+            drain: ShutdownToken::new(),
+            drain_timeout: options.drain_timeout,
+            stopped: false,
+            error: None,
+            task: None,
+            waiters: Vec::new(),
+        }));
+        task::spawn(run(NamedPipePath::new(path), options, shared.clone()));
+        Self { shared }
+    }
+    /// Queues `message` to be sent, waiting for room if the queue is full.
+    ///
+    /// Resolves once the message is queued, not once it's written; use
+    /// [`flush`](#method.flush) for that. Fails with `BrokenPipe` if the
+    /// task was shut down, or with the error that stopped it.
+    pub async fn send<T: Into<Vec<u8>>>(&self, message: T) -> io::Result<()> {
+        let mut message = Some(message.into());
+        future::poll_fn(|cx| {
+            let mut state = self.shared.lock().unwrap();
+            if state.stopped {
+                return Poll::Ready(Err(state.stopped()));
+            }
+            if state.queue.len() >= state.capacity {
+                state.wait(cx.waker());
+                return Poll::Pending;
+            }
+            state.push(message.take().unwrap());
+            Poll::Ready(Ok(()))
+        })
+        .await
+    }
+    /// Queues `message` to be sent if there's room in the queue.
+    ///
+    /// Fails with `WouldBlock` if the queue is full, and like
+    /// [`send`](#method.send) if the task stopped.
+    pub fn try_send<T: Into<Vec<u8>>>(&self, message: T) -> io::Result<()> {
+        let mut state = self.shared.lock().unwrap();
+        if state.stopped {
+            return Err(state.stopped());
+        }
+        if state.queue.len() >= state.capacity {
+            return Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                "the writer queue is full",
+            ));
+        }
+        state.push(message.into());
+        Ok(())
+    }
+    /// Waits until every message queued so far, by any handle, was written
+    /// to the pipe.
+    ///
+    /// Fails with `BrokenPipe` if the task was shut down first, or with the
+    /// error that stopped it.
+    pub async fn flush(&self) -> io::Result<()> {
+        let target = self.shared.lock().unwrap().queued;
+        future::poll_fn(|cx| {
+            let mut state = self.shared.lock().unwrap();
+            if state.written >= target {
+                Poll::Ready(Ok(()))
+            } else if state.stopped {
+                Poll::Ready(Err(state.stopped()))
+            } else {
+                state.wait(cx.waker());
+                Poll::Pending
+            }
+        })
+        .await
+    }
+    /// Returns the number of messages waiting in the queue, not counting
+    /// the one being written.
+    pub fn queued(&self) -> usize {
+        self.shared.lock().unwrap().queue.len()
+    }
+    /// Returns the error the task last ran into, if it hasn't written a
+    /// message since, or the one that stopped it.
+    pub fn last_error(&self) -> Option<io::Error> {
+        self.shared.lock().unwrap().error.as_ref().map(copy_error)
+    }
+}
+
+impl Clone for PipeWriterHandle {
+    fn clone(&self) -> Self {
+        self.shared.lock().unwrap().handles += 1;
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl Drop for PipeWriterHandle {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock().unwrap();
+        state.handles -= 1;
+        if state.handles == 0 {
+            // Let the task notice that nothing more is coming
+            if let Some(waker) = state.task.take() {
+                waker.wake();
+            }
+            let drain = state.drain.clone();
+            let timeout = state.drain_timeout;
+            task::spawn(async move {
+                task::sleep(timeout).await;
+                drain.shutdown();
+            });
+        }
+    }
+}
+
+/// Takes the next message out of the queue, or returns `None` once the
+/// queue is empty and all handles are gone.
+async fn next(shared: &Shared) -> Option<Vec<u8>> {
+    future::poll_fn(|cx| {
+        let mut state = shared.lock().unwrap();
+        if let Some(message) = state.queue.pop_front() {
+            state.wake_waiters();
+            Poll::Ready(Some(message))
+        } else if state.handles == 0 {
+            Poll::Ready(None)
+        } else {
+            state.task = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    })
+    .await
+}
+
+/// Sends `message`, (re)opening the pipe as often as it takes, unless an
+/// error comes up that retrying won't fix.
+async fn deliver(
+    pipe: &NamedPipePath,
+    options: &WriterOptions,
+    shared: &Shared,
+    writer: &mut Option<FramedWriter>,
+    message: &[u8],
+) -> io::Result<()> {
+    let mut delay = RETRY_MIN;
+    loop {
+        let result = match writer {
+            Some(open) => open.send(message).await,
+            None => match pipe.open_write().open().await {
+                Ok(open) => {
+                    let framed = FramedWriter::new(open)
+                        .chunked(options.chunked)
+                        .checksum(options.checksum);
+                    writer.get_or_insert(framed).send(message).await
+                }
+                Err(e) => Err(e),
+            },
+        };
+        match result {
+            Ok(()) => return Ok(()),
+            Err(e) if is_transient(&e) => {
+                *writer = None;
+                shared.lock().unwrap().error = Some(e);
+            }
+            Err(e) => return Err(e),
+        }
+        task::sleep(delay).await;
+        delay = (delay * 2).min(RETRY_MAX);
+    }
+}
+
+async fn run(pipe: NamedPipePath, options: WriterOptions, shared: Shared) {
+    let stop = &options.shutdown;
+    let drain = shared.lock().unwrap().drain.clone();
+    let mut writer = None;
+    let mut failed = None;
+    while let Some(Some(message)) = stop.run_until(next(&shared)).await {
+        let sent = drain.run_until(deliver(&pipe, &options, &shared, &mut writer, &message));
+        match stop.run_until(sent).await.flatten() {
+            Some(Ok(())) => {}
+            Some(Err(e)) => {
+                failed = Some(e);
+                break;
+            }
+            None => break,
+        }
+        let mut state = shared.lock().unwrap();
+        state.written += 1;
+        state.error = None;
+        state.wake_waiters();
+    }
+    let mut state = shared.lock().unwrap();
+    // Only a failure explains why it stopped, not a shutdown
+    state.error = failed;
+    state.stopped = true;
+    state.queue.clear();
+    state.wake_waiters();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PipeWriterHandle, WriterOptions};
+    use crate::{frame::FramedReader, NamedPipePath};
+    use async_std::{io, task};
+    use std::time::Duration;
+    #[test]
+    fn queued_writes_survive_reconnects() -> io::Result<()> {
+        task::block_on(async {
+            let pipe = NamedPipePath::new("./test_pipe_51");
+            pipe.ensure_exists().unwrap();
+            let options = WriterOptions::new().queue_capacity(1).checksum(true);
+            let handle = PipeWriterHandle::spawn("./test_pipe_51", options);
+            let other = handle.clone();
+            // There's no reader yet, so the messages wait in the queue
+            handle.send(&b"one"[..]).await?;
+            let t_send = task::spawn(async move { other.send(&b"two"[..]).await });
+
+            let mut reader = FramedReader::new(pipe.open_read().open().await?);
+            t_send.await?;
+            handle.flush().await?;
+            assert_eq!(reader.recv().await?, Some(b"one".to_vec()));
+            assert_eq!(reader.recv().await?, Some(b"two".to_vec()));
+
+            // The reader restarts
+            drop(reader);
+            handle.send(&b"three"[..]).await?;
+            let mut reader = FramedReader::new(pipe.open_read().open().await?);
+            assert_eq!(reader.recv().await?, Some(b"three".to_vec()));
+            // The task exits once the last handle is gone
+            drop(handle);
+            assert_eq!(reader.recv().await?, None);
+            pipe.delete().await
+        })
+    }
+    #[test]
+    fn permanent_error_stops_task() -> io::Result<()> {
+        task::block_on(async {
+            // Can't be opened for writing, no matter how often it's tried
+            let dir = "./test_dir_55";
+            std::fs::create_dir_all(dir)?;
+            let handle = PipeWriterHandle::spawn(dir, WriterOptions::new());
+            handle.send(&b"lost"[..]).await?;
+            let err = handle.flush().await.unwrap_err();
+            assert_ne!(err.kind(), io::ErrorKind::BrokenPipe);
+            assert_eq!(handle.last_error().unwrap().kind(), err.kind());
+            assert_eq!(
+                handle.send(&b"more"[..]).await.unwrap_err().kind(),
+                err.kind()
+            );
+            std::fs::remove_dir(dir)
+        })
+    }
+    #[test]
+    fn give_up_draining_without_reader() -> io::Result<()> {
+        task::block_on(async {
+            let pipe = NamedPipePath::new("./test_pipe_60");
+            pipe.ensure_exists().unwrap();
+            let options = WriterOptions::new().drain_timeout(Duration::from_millis(50));
+            let handle = PipeWriterHandle::spawn("./test_pipe_60", options);
+            handle.send(&b"never read"[..]).await?;
+            let shared = handle.shared.clone();
+            drop(handle);
+            // Nobody ever opens the pipe for reading
+            let stopped = async {
+                while !shared.lock().unwrap().stopped {
+                    task::sleep(Duration::from_millis(10)).await;
+                }
+                Ok(())
+            };
+            io::timeout(Duration::from_secs(1), stopped).await?;
+            assert!(shared.lock().unwrap().queue.is_empty());
+            pipe.delete().await
+        })
+    }
+}
@@ -0,0 +1,88 @@
+use async_std::{io, prelude::*, task};
+use std::process;
+use unix_fifo_async::{frame::FramedReader, NamedPipePath};
+
+const USAGE: &str = "\
+Usage: fifo-recv PATH [--follow] [--frame]
+
+Prints what's written to the named pipe at PATH, creating it if needed.
+
+Options:
+    -f, --follow    keep printing as writers come and go, instead of
+                    stopping when the first writer closes the pipe
+    --frame         read frames written with FramedWriter and print each
+                    message on a line of its own";
+
+struct Args {
+    path: String,
+    follow: bool,
+    frame: bool,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut path = None;
+    let mut follow = false;
+    let mut frame = false;
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "-f" | "--follow" => follow = true,
+            "--frame" => frame = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+            _ if path.is_none() => path = Some(arg),
+            _ => return Err(format!("unexpected argument {}", arg)),
+        }
+    }
+    let path = path.ok_or_else(|| "missing PATH".to_string())?;
+    Ok(Args {
+        path,
+        follow,
+        frame,
+    })
+}
+
+async fn recv(args: Args) -> io::Result<()> {
+    let pipe = NamedPipePath::new(args.path);
+    let reader = pipe.open_read().hold_open(args.follow);
+    reader.ensure_pipe_exists().map_err(io::Error::other)?;
+    let reader = reader.open().await?;
+    let mut stdout = io::stdout();
+    if args.frame {
+        let mut reader = FramedReader::new(reader);
+        while let Some(message) = reader.recv().await? {
+            stdout.write_all(&message).await?;
+            stdout.write_all(b"\n").await?;
+            stdout.flush().await?;
+        }
+    } else {
+        let mut reader = reader;
+        let mut buf = vec![0; 4096];
+        loop {
+            let n = reader.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            stdout.write_all(&buf[..n]).await?;
+            stdout.flush().await?;
+        }
+    }
+    Ok(())
+}
+
+fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("fifo-recv: {}", e);
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(e) = task::block_on(recv(args)) {
+        eprintln!("fifo-recv: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,97 @@
+use async_std::{io, prelude::*, task};
+use std::process;
+use unix_fifo_async::{frame::FramedWriter, NamedPipePath};
+
+const USAGE: &str = "\
+Usage: fifo-send PATH [--frame] [--json]
+
+Writes everything from stdin to the named pipe at PATH, waiting for a reader.
+
+Options:
+    --frame    send stdin as a single frame, for readers using FramedReader
+    --json     check that every line of stdin is valid JSON; with --frame,
+               every line is sent as a frame of its own";
+
+struct Args {
+    path: String,
+    frame: bool,
+    json: bool,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut path = None;
+    let mut frame = false;
+    let mut json = false;
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--frame" => frame = true,
+            "--json" => json = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+            _ if path.is_none() => path = Some(arg),
+            _ => return Err(format!("unexpected argument {}", arg)),
+        }
+    }
+    let path = path.ok_or_else(|| "missing PATH".to_string())?;
+    Ok(Args { path, frame, json })
+}
+
+async fn send(args: Args) -> io::Result<()> {
+    let pipe = NamedPipePath::new(args.path);
+    if !args.json && !args.frame {
+        pipe.open_write().copy_from(io::stdin()).await?;
+        return Ok(());
+    }
+    let mut input = Vec::new();
+    io::stdin().read_to_end(&mut input).await?;
+    let messages: Vec<&[u8]> = if args.json {
+        let text = std::str::from_utf8(&input)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let mut lines = Vec::new();
+        // Number lines before skipping blank ones, so errors point at the
+        // right line
+        for (n, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            serde_json::from_str::<serde_json::Value>(line).map_err(|e| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", n + 1, e))
+            })?;
+            lines.push(line.as_bytes());
+        }
+        lines
+    } else {
+        vec![&input[..]]
+    };
+    let mut writer = pipe.open_write().open().await?;
+    if args.frame {
+        let mut writer = FramedWriter::new(writer);
+        for message in messages {
+            writer.send(message).await?;
+        }
+    } else {
+        for message in messages {
+            writer.write_all(message).await?;
+            writer.write_all(b"\n").await?;
+        }
+    }
+    Ok(())
+}
+
+fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("fifo-send: {}", e);
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(e) = task::block_on(send(args)) {
+        eprintln!("fifo-send: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,342 @@
+use crate::{OpenReader, OpenWriter};
+use async_io::Timer;
+use async_std::{future, io, prelude::*, task};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+/// The default size of the read and write buffers.
+const DEFAULT_CAPACITY: usize = 8 * 1024;
+
+/// Adds buffering to the reading end of a pipe.
+///
+/// Implements `BufRead`, so `read_line`, `lines` and friends from the
+/// async-std prelude work on it, and allows looking at upcoming data with
+/// [`peek`](#method.peek) without consuming it.
+pub struct BufferedPipeReader {
+    inner: OpenReader,
+    buf: Vec<u8>,
+    /// Start of the data in `buf` that hasn't been consumed yet.
+    pos: usize,
+    capacity: usize,
+}
+
+impl BufferedPipeReader {
+    /// Wraps `inner` with a buffer of the default size (8 KiB).
+    pub fn new(inner: OpenReader) -> Self {
+        Self::with_capacity(DEFAULT_CAPACITY, inner)
+    }
+    /// Wraps `inner` with a buffer of at least `capacity` bytes.
+    ///
+    /// The buffer grows beyond that if [`peek`](#method.peek) asks for more.
+    pub fn with_capacity(capacity: usize, inner: OpenReader) -> Self {
+        Self {
+            inner,
+            buf: Vec::with_capacity(capacity),
+            pos: 0,
+            capacity: capacity.max(1),
+        }
+    }
+    /// Gets a reference to the underlying pipe.
+    pub fn get_ref(&self) -> &OpenReader {
+        &self.inner
+    }
+    /// Gets a mutable reference to the underlying pipe.
+    ///
+    /// Reading from it directly skips over anything that's still buffered.
+    pub fn get_mut(&mut self) -> &mut OpenReader {
+        &mut self.inner
+    }
+    /// Returns the data that's currently buffered, without reading more.
+    pub fn buffer(&self) -> &[u8] {
+        &self.buf[self.pos..]
+    }
+    /// Unwraps the underlying pipe, discarding any buffered data.
+    pub fn into_inner(self) -> OpenReader {
+        self.inner
+    }
+    /// Returns the buffered data, reading more from the pipe if the buffer
+    /// is empty.
+    ///
+    /// An empty result means the pipe has reached EOF. Nothing is consumed;
+    /// call [`consume`](#method.consume) to mark data as read.
+    pub async fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        future::poll_fn(|cx| {
+            Pin::new(&mut *self)
+                .poll_fill_buf(cx)
+                .map_ok(|buf| buf.len())
+        })
+        .await?;
+        Ok(self.buffer())
+    }
+    /// Marks `amount` bytes of the buffered data as read.
+    pub fn consume(&mut self, amount: usize) {
+        self.pos = (self.pos + amount).min(self.buf.len());
+    }
+    /// Returns the next `n` bytes from the pipe without consuming them.
+    ///
+    /// Waits until `n` bytes are available; the result is only shorter if
+    /// the pipe reaches EOF first.
+    pub async fn peek(&mut self, n: usize) -> io::Result<&[u8]> {
+        while self.buffer().len() < n {
+            if future::poll_fn(|cx| self.poll_read_more(cx)).await? == 0 {
+                break;
+            }
+        }
+        let available = self.buffer().len().min(n);
+        Ok(&self.buffer()[..available])
+    }
+    /// Reads more data from the pipe, appending it to the buffer.
+    fn poll_read_more(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        if self.pos > 0 {
+            self.buf.drain(..self.pos);
+            self.pos = 0;
+        }
+        let len = self.buf.len();
+        let room = self
+            .capacity
+            .saturating_sub(len)
+            .max(self.capacity / 4)
+            .max(1);
+        self.buf.resize(len + room, 0);
+        let result = Pin::new(&mut self.inner).poll_read(cx, &mut self.buf[len..]);
+        let read = match &result {
+            Poll::Ready(Ok(n)) => *n,
+            _ => 0,
+        };
+        self.buf.truncate(len + read);
+        result
+    }
+}
+
+impl io::Read for BufferedPipeReader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        // Don't bother copying large reads through the buffer.
+        if this.buffer().is_empty() && buf.len() >= this.capacity {
+            return Pin::new(&mut this.inner).poll_read(cx, buf);
+        }
+        let available = task::ready!(Pin::new(&mut *this).poll_fill_buf(cx))?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        this.consume(n);
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl io::BufRead for BufferedPipeReader {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+        if this.buffer().is_empty() {
+            task::ready!(this.poll_read_more(cx))?;
+        }
+        Poll::Ready(Ok(this.buffer()))
+    }
+    fn consume(self: Pin<&mut Self>, amount: usize) {
+        self.get_mut().consume(amount)
+    }
+}
+
+/// Batches small writes to the writing end of a pipe.
+///
+/// Writes are collected in memory and written to the pipe with a single
+/// syscall once the buffer is full, once data has been waiting longer than
+/// the [`flush_interval`](#method.flush_interval), or on `flush`. The
+/// interval is checked on every write; to have data go out on time between
+/// writes, race [`flush_due`](#method.flush_due) against whatever produces
+/// the next one, or call `flush` when there's nothing more to write for a
+/// while.
+///
+/// Dropping the writer silently discards anything that hasn't been
+/// flushed, as there's no way to report errors from `drop`, so `flush` or
+/// `close` it first.
+pub struct BufferedPipeWriter {
+    inner: OpenWriter,
+    buf: Vec<u8>,
+    /// How much of `buf` has been written to the pipe during a flush.
+    written: usize,
+    capacity: usize,
+    interval: Option<Duration>,
+    /// When the oldest data in `buf` was written.
+    oldest: Option<Instant>,
+    timer: Option<Timer>,
+}
+
+impl BufferedPipeWriter {
+    /// Wraps `inner` with a buffer of the default size (8 KiB).
+    pub fn new(inner: OpenWriter) -> Self {
+        Self::with_capacity(DEFAULT_CAPACITY, inner)
+    }
+    /// Wraps `inner` with a buffer of `capacity` bytes.
+    pub fn with_capacity(capacity: usize, inner: OpenWriter) -> Self {
+        Self {
+            inner,
+            buf: Vec::with_capacity(capacity),
+            written: 0,
+            capacity: capacity.max(1),
+            interval: None,
+            oldest: None,
+            timer: None,
+        }
+    }
+    /// Flushes buffered data once it's been waiting for `interval`, on the
+    /// next write or in [`flush_due`](#method.flush_due).
+    pub fn flush_interval(mut self, interval: Duration) -> Self {
+        self.interval = Some(interval);
+        self
+    }
+    /// Waits until the buffered data has been waiting for the
+    /// [`flush_interval`](#method.flush_interval), and writes it to the
+    /// pipe.
+    ///
+    /// Doesn't finish while nothing is buffered or there's no interval, so
+    /// it's meant to be raced against the next write. This is cancel-safe.
+    pub async fn flush_due(&mut self) -> io::Result<()> {
+        future::poll_fn(|cx| self.poll_flush_due(cx)).await
+    }
+    /// Gets a reference to the underlying pipe.
+    pub fn get_ref(&self) -> &OpenWriter {
+        &self.inner
+    }
+    /// Gets a mutable reference to the underlying pipe.
+    ///
+    /// Writing to it directly skips ahead of anything that's still
+    /// buffered.
+    pub fn get_mut(&mut self) -> &mut OpenWriter {
+        &mut self.inner
+    }
+    /// Returns the data that's waiting to be written.
+    pub fn buffer(&self) -> &[u8] {
+        &self.buf[self.written..]
+    }
+    /// Unwraps the underlying pipe, discarding any buffered data.
+    pub fn into_inner(self) -> OpenWriter {
+        self.inner
+    }
+    /// Writes everything in the buffer to the pipe.
+    fn poll_flush_buf(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.written < self.buf.len() {
+            let n =
+                task::ready!(Pin::new(&mut self.inner).poll_write(cx, &self.buf[self.written..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.written += n;
+        }
+        self.buf.clear();
+        self.written = 0;
+        self.oldest = None;
+        Poll::Ready(Ok(()))
+    }
+    fn poll_flush_due(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let deadline = match (self.oldest, self.interval) {
+            (Some(oldest), Some(interval)) => oldest + interval,
+            _ => return Poll::Pending,
+        };
+        let timer = self.timer.get_or_insert_with(|| Timer::at(deadline));
+        timer.set_at(deadline);
+        task::ready!(Pin::new(timer).poll(cx));
+        self.poll_flush_buf(cx)
+    }
+    fn is_due(&self) -> bool {
+        match (self.oldest, self.interval) {
+            (Some(oldest), Some(interval)) => oldest.elapsed() >= interval,
+            _ => false,
+        }
+    }
+}
+
+impl io::Write for BufferedPipeWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.buf.len() + buf.len() > this.capacity || this.is_due() {
+            task::ready!(this.poll_flush_buf(cx))?;
+        }
+        // Don't bother copying large writes through the buffer.
+        if buf.len() >= this.capacity {
+            return Pin::new(&mut this.inner).poll_write(cx, buf);
+        }
+        this.buf.extend_from_slice(buf);
+        this.oldest.get_or_insert_with(Instant::now);
+        Poll::Ready(Ok(buf.len()))
+    }
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        task::ready!(this.poll_flush_buf(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        task::ready!(this.poll_flush_buf(cx))?;
+        Pin::new(&mut this.inner).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BufferedPipeReader, BufferedPipeWriter};
+    use crate::{NamedPipePath, OpenReader, OpenWriter};
+    use async_std::{io, prelude::*, task};
+    use std::time::Duration;
+    #[test]
+    fn peek_and_read_lines() -> io::Result<()> {
+        task::block_on(async {
+            let pipe = NamedPipePath::new("./test_pipe_25");
+            pipe.ensure_exists().unwrap();
+            let reader = pipe.open_read().open().await?;
+            let writer = pipe.open_write();
+            let t_write = task::spawn(async move { writer.write_str("#first\nsecond\n").await });
+            let mut reader = BufferedPipeReader::with_capacity(4, reader);
+            assert_eq!(reader.peek(1).await?, b"#");
+            assert_eq!(reader.peek(6).await?, b"#first");
+            let mut line = String::new();
+            reader.read_line(&mut line).await?;
+            assert_eq!(line, "#first\n");
+            line.clear();
+            reader.read_line(&mut line).await?;
+            assert_eq!(line, "second\n");
+            t_write.await?;
+            assert!(reader.fill_buf().await?.is_empty());
+            assert!(reader.peek(1).await?.is_empty());
+            pipe.delete().await
+        })
+    }
+    #[test]
+    fn coalesce_writes() -> io::Result<()> {
+        task::block_on(async {
+            let (read_fd, write_fd) = nix::unistd::pipe().unwrap();
+            let mut reader = unsafe { OpenReader::from_raw_fd(read_fd)? };
+            let writer = unsafe { OpenWriter::from_raw_fd(write_fd)? };
+            let mut writer = BufferedPipeWriter::with_capacity(8, writer)
+                .flush_interval(Duration::from_millis(10));
+            writer.write_all(b"abc").await?;
+            writer.write_all(b"def").await?;
+            assert_eq!(writer.buffer(), b"abcdef");
+            // Doesn't fit, so the buffered data goes out first
+            writer.write_all(b"ghi").await?;
+            assert_eq!(writer.buffer(), b"ghi");
+            assert_eq!(reader.read_exact(6).await?, b"abcdef");
+            task::sleep(Duration::from_millis(20)).await;
+            writer.write_all(b"j").await?;
+            assert_eq!(writer.buffer(), b"j");
+            assert_eq!(reader.read_exact(3).await?, b"ghi");
+            writer.flush().await?;
+            assert!(writer.buffer().is_empty());
+            assert_eq!(reader.read_exact(1).await?, b"j");
+            // Goes out on time without another write
+            writer.write_all(b"k").await?;
+            writer.flush_due().await?;
+            assert!(writer.buffer().is_empty());
+            assert_eq!(reader.read_exact(1).await?, b"k");
+            Ok(())
+        })
+    }
+}
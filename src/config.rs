@@ -0,0 +1,223 @@
+//! Setting up a set of named pipes from a declarative description.
+//!
+//! ```no_run
+//! # fn main() -> async_std::io::Result<()> { async_std::task::block_on(async {
+//! use unix_fifo_async::config::{PipeConfig, PipeSpec};
+//!
+//! let pipes = PipeConfig::new()
+//!     .pipe(PipeSpec::new("requests", "/run/mydaemon/requests").mode(0o620))
+//!     .pipe(PipeSpec::new("responses", "/run/mydaemon/responses").mode(0o640))
+//!     .apply()
+//!     .await?;
+//! let requests = pipes["requests"].open_read();
+//! # Ok(())
+//! # })}
+//! ```
+//!
+//! With the `serde` feature, the configuration can also be deserialized,
+//! e.g. from a TOML section like this:
+//!
+//! ```toml
+//! [[pipes]]
+//! name = "requests"
+//! path = "/run/mydaemon/requests"
+//! mode = 0o620
+//! group_name = "mydaemon"
+//! ```
+use crate::{
+    util::{self, nix_to_io},
+    NamedPipePath,
+};
+use async_std::io;
+use nix::sys::stat::Mode;
+use nix::unistd::{Gid, Uid};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// Describes a set of named pipes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct PipeConfig {
+    /// The pipes to set up.
+    pub pipes: Vec<PipeSpec>,
+}
+
+/// Describes a single named pipe.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct PipeSpec {
+    /// The key of the pipe in the map returned by `PipeConfig::apply`.
+    pub name: String,
+    /// Where the pipe should be.
+    pub path: PathBuf,
+    /// Permission bits the pipe should have, e.g. `0o620`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub mode: Option<u32>,
+    /// The user that should own the pipe.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub owner: Option<u32>,
+    /// The group that should own the pipe.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub group: Option<u32>,
+    /// The name of the user that should own the pipe, which takes
+    /// precedence over `owner`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub owner_name: Option<String>,
+    /// The name of the group that should own the pipe, which takes
+    /// precedence over `group`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub group_name: Option<String>,
+    /// Permission bits for missing parent directories, which are created
+    /// if this is set.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub parent_mode: Option<u32>,
+}
+
+impl PipeConfig {
+    /// Creates an empty configuration.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Adds a pipe to the configuration.
+    pub fn pipe(mut self, spec: PipeSpec) -> Self {
+        self.pipes.push(spec);
+        self
+    }
+    /// Creates all of the pipes that don't exist yet and makes sure they
+    /// have the configured permissions and owners.
+    ///
+    /// Fails if a path exists but isn't a named pipe, or if two pipes share
+    /// a name. Symbolic links aren't followed when changing permissions,
+    /// so a symlink in place of a pipe with a configured mode is an error
+    /// too. Pipes set up before an error occurred are left in place.
+    pub async fn apply(&self) -> io::Result<HashMap<String, NamedPipePath>> {
+        let mut pipes = HashMap::with_capacity(self.pipes.len());
+        for spec in &self.pipes {
+            if pipes.contains_key(&spec.name) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("duplicate pipe name {:?}", spec.name),
+                ));
+            }
+            let pipe = spec.apply().await?;
+            pipes.insert(spec.name.clone(), pipe);
+        }
+        Ok(pipes)
+    }
+}
+
+impl PipeSpec {
+    /// Describes a pipe called `name` at `path`, with default permissions
+    /// and owner.
+    pub fn new<N: Into<String>, P: Into<PathBuf>>(name: N, path: P) -> Self {
+        Self {
+            name: name.into(),
+            path: path.into(),
+            mode: None,
+            owner: None,
+            group: None,
+            owner_name: None,
+            group_name: None,
+            parent_mode: None,
+        }
+    }
+    /// Sets the permission bits the pipe should have.
+    pub fn mode(mut self, mode: u32) -> Self {
+        self.mode = Some(mode);
+        self
+    }
+    /// Sets the user that should own the pipe.
+    pub fn owner(mut self, uid: u32) -> Self {
+        self.owner = Some(uid);
+        self
+    }
+    /// Sets the group that should own the pipe.
+    pub fn group(mut self, gid: u32) -> Self {
+        self.group = Some(gid);
+        self
+    }
+    /// Sets the user that should own the pipe by name.
+    ///
+    /// The name is looked up when the configuration is applied.
+    pub fn owner_name<S: Into<String>>(mut self, name: S) -> Self {
+        self.owner_name = Some(name.into());
+        self
+    }
+    /// Sets the group that should own the pipe by name.
+    ///
+    /// The name is looked up when the configuration is applied.
+    pub fn group_name<S: Into<String>>(mut self, name: S) -> Self {
+        self.group_name = Some(name.into());
+        self
+    }
+    /// Creates missing parent directories with the permission bits
+    /// `mode`, instead of failing.
+    pub fn create_parents(mut self, mode: u32) -> Self {
+        self.parent_mode = Some(mode);
+        self
+    }
+    async fn apply(&self) -> io::Result<NamedPipePath> {
+        let pipe = NamedPipePath::new(&self.path);
+        if !pipe.exists() {
+            if let Some(mode) = self.parent_mode {
+                pipe.create_parents(Mode::from_bits_truncate(mode as _))?;
+            }
+            // With an explicit mode, start out inaccessible until the
+            // permissions are set below, regardless of the umask.
+            let initial = self.mode.map(|_| Mode::empty());
+            crate::create_pipe(&self.path, initial).map_err(nix_to_io)?;
+        } else if !pipe.is_fifo() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists and isn't a named pipe", self.path.display()),
+            ));
+        }
+        if let Some(mode) = self.mode {
+            pipe.set_mode(Mode::from_bits_truncate(mode as _))?;
+        }
+        let owner = match &self.owner_name {
+            Some(name) => Some(util::user_id(name)?),
+            None => self.owner.map(Uid::from_raw),
+        };
+        let group = match &self.group_name {
+            Some(name) => Some(util::group_id(name)?),
+            None => self.group.map(Gid::from_raw),
+        };
+        if owner.is_some() || group.is_some() {
+            pipe.set_owner(owner, group)?;
+        }
+        Ok(pipe)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PipeConfig, PipeSpec};
+    use async_std::{io, task};
+    use nix::sys::stat::Mode;
+    #[test]
+    fn apply_config() -> io::Result<()> {
+        task::block_on(async {
+            let gid = nix::unistd::getegid().as_raw();
+            let config = PipeConfig::new()
+                .pipe(PipeSpec::new("a", "./test_pipe_31").mode(0o600))
+                .pipe(PipeSpec::new("b", "./test_pipe_32").group(gid));
+            let pipes = config.apply().await?;
+            assert_eq!(pipes.len(), 2);
+            assert_eq!(pipes["a"].permissions()?, Mode::from_bits_truncate(0o600));
+            assert_eq!(pipes["b"].owner()?.1.as_raw(), gid);
+            // Applying again validates the existing pipes
+            let pipes = config.apply().await?;
+            // A symlink in place of a pipe is refused, not followed
+            std::os::unix::fs::symlink("test_pipe_31", "./test_pipe_58")?;
+            let link = PipeConfig::new().pipe(PipeSpec::new("c", "./test_pipe_58").mode(0o666));
+            assert!(link.apply().await.is_err());
+            std::fs::remove_file("./test_pipe_58")?;
+            assert_eq!(pipes["a"].permissions()?, Mode::from_bits_truncate(0o600));
+            for (_, pipe) in pipes {
+                pipe.delete().await?;
+            }
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,260 @@
+//! Spawning child processes wired up to named pipes.
+//!
+//! ```no_run
+//! # fn main() -> async_std::io::Result<()> { async_std::task::block_on(async {
+//! use async_std::prelude::*;
+//! use std::process::Command;
+//! use unix_fifo_async::coprocess::{spawn_with_pipes, CoprocessOptions};
+//!
+//! let mut command = Command::new("tr");
+//! command.args(&["a-z", "A-Z"]);
+//! let mut coprocess = spawn_with_pipes(&mut command, &CoprocessOptions::default()).await?;
+//! let mut stdin = coprocess.stdin.take().unwrap();
+//! stdin.write_all(b"hello").await?;
+//! drop(stdin);
+//! let mut output = String::new();
+//! coprocess.stdout.as_mut().unwrap().read_to_string(&mut output).await?;
+//! assert_eq!(output, "HELLO");
+//! # Ok(())
+//! # })}
+//! ```
+use crate::{util::nix_to_io, NamedPipePath, OpenReader, OpenWriter};
+use async_std::io;
+use nix::libc;
+use std::os::unix::{
+    io::{AsRawFd, RawFd},
+    process::CommandExt,
+};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Counter making pipe names unique within this process.
+static PIPE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Options for [`spawn_with_pipes`](fn.spawn_with_pipes.html).
+#[derive(Clone, Debug)]
+pub struct CoprocessOptions {
+    /// The directory the pipes are created in. Defaults to the system's
+    /// temporary directory.
+    pub dir: PathBuf,
+    /// Whether to feed the child's stdin from a pipe. Defaults to `true`.
+    pub stdin: bool,
+    /// Whether to collect the child's stdout through a pipe. Defaults to `true`.
+    pub stdout: bool,
+}
+
+impl Default for CoprocessOptions {
+    fn default() -> Self {
+        Self {
+            dir: std::env::temp_dir(),
+            stdin: true,
+            stdout: true,
+        }
+    }
+}
+
+/// A child process with its standard streams attached to named pipes.
+///
+/// The pipes are removed from disk when this is dropped; the child keeps
+/// running.
+pub struct Coprocess {
+    /// The spawned child.
+    pub child: Child,
+    /// The writing end of the pipe feeding the child's stdin. Drop it to
+    /// send EOF to the child.
+    pub stdin: Option<OpenWriter>,
+    /// The reading end of the pipe the child's stdout goes to.
+    pub stdout: Option<OpenReader>,
+    files: PipeFiles,
+}
+
+impl Coprocess {
+    /// The path of the pipe feeding the child's stdin, if one was created.
+    pub fn stdin_path(&self) -> Option<&Path> {
+        self.files.stdin.as_ref().map(NamedPipePath::as_path)
+    }
+    /// The path of the pipe the child's stdout goes to, if one was created.
+    pub fn stdout_path(&self) -> Option<&Path> {
+        self.files.stdout.as_ref().map(NamedPipePath::as_path)
+    }
+}
+
+/// Removes the created pipes from disk when dropped.
+#[derive(Default)]
+struct PipeFiles {
+    stdin: Option<NamedPipePath>,
+    stdout: Option<NamedPipePath>,
+}
+
+impl Drop for PipeFiles {
+    fn drop(&mut self) {
+        for pipe in self.stdin.iter().chain(&self.stdout) {
+            let _ = std::fs::remove_file(pipe.as_path());
+        }
+    }
+}
+
+fn create_unique_pipe(dir: &Path, stream: &str) -> io::Result<NamedPipePath> {
+    let name = format!(
+        "unix-fifo-async-{}-{}-{}",
+        std::process::id(),
+        PIPE_COUNTER.fetch_add(1, Ordering::Relaxed),
+        stream
+    );
+    let pipe = NamedPipePath::new(dir.join(name));
+    crate::create_pipe(pipe.as_path(), None).map_err(nix_to_io)?;
+    Ok(pipe)
+}
+
+/// Creates the requested pipes and spawns `command` with its stdin and/or
+/// stdout attached to them.
+///
+/// Both ends of each pipe are opened before the child starts, so the child
+/// never sees a premature EOF and neither side has to wait for the other.
+/// Any stdin/stdout configuration on `command` for the piped streams is
+/// replaced. If anything fails, the created pipes are removed again.
+pub async fn spawn_with_pipes(
+    command: &mut Command,
+    options: &CoprocessOptions,
+) -> io::Result<Coprocess> {
+    let mut files = PipeFiles::default();
+    let mut stdin = None;
+    let mut stdout = None;
+    if options.stdin {
+        let pipe = create_unique_pipe(&options.dir, "stdin")?;
+        files.stdin = Some(pipe.clone());
+        command.stdin(pipe.open_read().open().await?.into_stdio()?);
+        // The child's end is open already, so this doesn't wait.
+        stdin = Some(pipe.open_write().open().await?);
+    }
+    if options.stdout {
+        let pipe = create_unique_pipe(&options.dir, "stdout")?;
+        files.stdout = Some(pipe.clone());
+        stdout = Some(pipe.open_read().open().await?);
+        command.stdout(pipe.open_write().open().await?.into_stdio()?);
+    }
+    let child = command.spawn();
+    // The command keeps the child's pipe ends open until it's reconfigured,
+    // which would keep us from ever seeing EOF on the child's stdout.
+    if options.stdin {
+        command.stdin(Stdio::inherit());
+    }
+    if options.stdout {
+        command.stdout(Stdio::inherit());
+    }
+    Ok(Coprocess {
+        child: child?,
+        stdin,
+        stdout,
+        files,
+    })
+}
+
+/// Passes `pipe` to the child spawned by `command` as file descriptor
+/// `target_fd`, optionally telling it the pipe's path through the
+/// environment variable given in `path_env`.
+///
+/// Pipes opened by this crate are closed on exec, so a child only inherits
+/// the pipes that are passed explicitly. `command` takes ownership of
+/// `pipe` so it stays open for every spawn, and closes it when dropped;
+/// drop `command` after spawning if the child should see EOF once it's
+/// done. The child's descriptor shares its non-blocking mode with `pipe`;
+/// use
+/// [`into_stdio`](../struct.OpenReader.html#method.into_stdio) instead if
+/// the child needs a blocking standard stream.
+///
+/// ```no_run
+/// # fn main() -> async_std::io::Result<()> { async_std::task::block_on(async {
+/// use std::path::Path;
+/// use std::process::Command;
+/// use unix_fifo_async::{coprocess::inherit_pipe, NamedPipePath};
+///
+/// let pipe = NamedPipePath::new("./helper_events");
+/// let writer = pipe.open_write().open().await?;
+/// let mut command = Command::new("./sandboxed-helper");
+/// inherit_pipe(&mut command, writer, 3, Some(("EVENTS_PIPE", pipe.as_path())));
+/// command.spawn()?;
+/// # Ok(())
+/// # })}
+/// ```
+pub fn inherit_pipe<'a, F>(
+    command: &'a mut Command,
+    pipe: F,
+    target_fd: RawFd,
+    path_env: Option<(&str, &Path)>,
+) -> &'a mut Command
+where
+    F: AsRawFd + Send + Sync + 'static,
+{
+    if let Some((key, path)) = path_env {
+        command.env(key, path);
+    }
+    // Safety: the closure only calls dup2 and fcntl, which are
+    // async-signal-safe, and doesn't allocate. It owns `pipe`, so `fd` stays
+    // open for as long as `command` can spawn.
+    unsafe {
+        command.pre_exec(move || {
+            let fd = pipe.as_raw_fd();
+            if fd == target_fd {
+                // dup2 is a no-op here, so clear the flag directly.
+                let flags = libc::fcntl(fd, libc::F_GETFD);
+                if flags < 0 || libc::fcntl(fd, libc::F_SETFD, flags & !libc::FD_CLOEXEC) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            } else if libc::dup2(fd, target_fd) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{spawn_with_pipes, CoprocessOptions};
+    use async_std::{io, prelude::*, task};
+    use std::process::Command;
+    #[test]
+    fn cat_through_pipes() -> io::Result<()> {
+        task::block_on(async {
+            let options = CoprocessOptions {
+                dir: ".".into(),
+                ..Default::default()
+            };
+            let mut coprocess = spawn_with_pipes(&mut Command::new("cat"), &options).await?;
+            let stdin_path = coprocess.stdin_path().unwrap().to_owned();
+            assert!(stdin_path.exists());
+            let mut stdin = coprocess.stdin.take().unwrap();
+            stdin.write_all(b"Hello pipe").await?;
+            drop(stdin);
+            let mut read_result = String::new();
+            let stdout = coprocess.stdout.as_mut().unwrap();
+            stdout.read_to_string(&mut read_result).await?;
+            assert_eq!(read_result, "Hello pipe");
+            assert!(coprocess.child.wait()?.success());
+            drop(coprocess);
+            assert!(!stdin_path.exists());
+            Ok(())
+        })
+    }
+    #[test]
+    fn inherit_pipe_at_fd() -> io::Result<()> {
+        task::block_on(async {
+            let pipe = crate::NamedPipePath::new("./test_pipe_22");
+            pipe.ensure_exists().unwrap();
+            let mut reader = pipe.open_read().open().await?;
+            let writer = pipe.open_write().open().await?;
+            let mut command = Command::new("sh");
+            command.args(["-c", "printf %s \"$PIPE_PATH\" >&5"]);
+            super::inherit_pipe(&mut command, writer, 5, Some(("PIPE_PATH", pipe.as_path())));
+            let mut child = command.spawn()?;
+            drop(command);
+            let mut read_result = String::new();
+            reader.read_to_string(&mut read_result).await?;
+            assert!(child.wait()?.success());
+            assert_eq!(read_result, "./test_pipe_22");
+            pipe.delete().await
+        })
+    }
+}
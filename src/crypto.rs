@@ -0,0 +1,221 @@
+//! Encrypting frames with a pre-shared key, for pipes other users can get
+//! at, e.g. in `/tmp`.
+//!
+//! A [`FramedWriter`](../frame/struct.FramedWriter.html) with
+//! [`encrypt`](../frame/struct.FramedWriter.html#method.encrypt) seals
+//! every message with XChaCha20-Poly1305, and a
+//! [`FramedReader`](../frame/struct.FramedReader.html) with
+//! [`decrypt`](../frame/struct.FramedReader.html#method.decrypt) opens them,
+//! rejecting anything that was tampered with or not encrypted at all:
+//!
+//! ```no_run
+//! # fn main() -> async_std::io::Result<()> { async_std::task::block_on(async {
+//! use unix_fifo_async::crypto::KeyFile;
+//! use unix_fifo_async::frame::{FramedReader, FramedWriter};
+//! use unix_fifo_async::NamedPipePath;
+//!
+//! let pipe = NamedPipePath::new("/tmp/messages");
+//! let mut reader = FramedReader::new(pipe.open_read().open().await?)
+//!     .decrypt(KeyFile::new("/etc/myapp/pipe.key"));
+//! let mut writer = FramedWriter::new(pipe.open_write().open().await?)
+//!     .encrypt(KeyFile::new("/etc/myapp/pipe.key"));
+//! writer.send(b"secret").await?;
+//! assert_eq!(reader.recv().await?, Some(b"secret".to_vec()));
+//! # Ok(())
+//! # })}
+//! ```
+//!
+//! An encrypted payload is a random 24 byte nonce, followed by the
+//! ciphertext and its 16 byte tag. Messages are encrypted before they're
+//! split into chunks, and after they're compressed. Pings aren't
+//! encrypted, and nothing stops someone who can write to the pipe from
+//! sending a message they captured earlier a second time.
+use crate::error::DecryptionFailed;
+use async_std::io;
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    XChaCha20Poly1305, XNonce,
+};
+use std::fmt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+
+/// Length of a key, in bytes.
+pub const KEY_LEN: usize = 32;
+/// Length of the nonce at the start of an encrypted payload.
+const NONCE_LEN: usize = 24;
+
+/// A 256 bit key, shared by the writer and the reader.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Key([u8; KEY_LEN]);
+
+impl Key {
+    /// Uses `bytes` as the key.
+    pub fn from_bytes(bytes: [u8; KEY_LEN]) -> Self {
+        Key(bytes)
+    }
+    /// Uses `bytes` as the key, failing with `InvalidData` unless it's
+    /// exactly [`KEY_LEN`](constant.KEY_LEN.html) bytes long.
+    pub fn from_slice(bytes: &[u8]) -> io::Result<Self> {
+        let mut key = [0; KEY_LEN];
+        if bytes.len() != KEY_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("keys must be {} bytes long", KEY_LEN),
+            ));
+        }
+        key.copy_from_slice(bytes);
+        Ok(Key(key))
+    }
+    /// Generates a random key.
+    pub fn generate() -> Self {
+        Key(XChaCha20Poly1305::generate_key(&mut OsRng).into())
+    }
+    /// Returns the bytes of the key, e.g. to store it.
+    pub fn as_bytes(&self) -> &[u8; KEY_LEN] {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Keep the key out of logs
+        f.write_str("Key(..)")
+    }
+}
+
+/// Provides the key to encrypt or decrypt a message with.
+///
+/// It's asked for the key for every message, so an implementation can pick
+/// up a new key without recreating the reader or writer.
+pub trait KeyProvider: Send + Sync {
+    /// Returns the current key.
+    fn key(&self) -> io::Result<Key>;
+}
+
+impl KeyProvider for Key {
+    fn key(&self) -> io::Result<Key> {
+        Ok(self.clone())
+    }
+}
+
+/// Reads the key from a file holding exactly
+/// [`KEY_LEN`](constant.KEY_LEN.html) bytes.
+///
+/// The file is read for every message, and rejected with
+/// `PermissionDenied` if anyone but its owner can access it.
+#[derive(Debug, Clone)]
+pub struct KeyFile {
+    path: PathBuf,
+}
+
+impl KeyFile {
+    /// Reads the key from the file at `path`.
+    pub fn new<T: Into<PathBuf>>(path: T) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl KeyProvider for KeyFile {
+    fn key(&self) -> io::Result<Key> {
+        let mode = std::fs::metadata(&self.path)?.permissions().mode();
+        if mode & 0o077 != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "key file can be accessed by other users",
+            ));
+        }
+        Key::from_slice(&std::fs::read(&self.path)?)
+    }
+}
+
+fn cipher(keys: &dyn KeyProvider) -> io::Result<XChaCha20Poly1305> {
+    Ok(XChaCha20Poly1305::new(keys.key()?.as_bytes().into()))
+}
+
+/// Encrypts `message`, authenticating `aad` along with it.
+pub(crate) fn seal(keys: &dyn KeyProvider, aad: &[u8], message: &[u8]) -> io::Result<Vec<u8>> {
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher(keys)?
+        .encrypt(&nonce, Payload { msg: message, aad })
+        .map_err(|_| io::Error::other("encryption failed"))?;
+    let mut sealed = Vec::with_capacity(NONCE_LEN + ciphertext.len());
+    sealed.extend_from_slice(&nonce);
+    sealed.extend_from_slice(&ciphertext);
+    Ok(sealed)
+}
+
+/// Decrypts a payload created by `seal` with the same `aad`.
+pub(crate) fn open(keys: &dyn KeyProvider, aad: &[u8], sealed: &[u8]) -> io::Result<Vec<u8>> {
+    if sealed.len() < NONCE_LEN {
+        return Err(DecryptionFailed.into());
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    cipher(keys)?
+        .decrypt(
+            XNonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        .map_err(|_| DecryptionFailed.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Key;
+    use crate::{
+        error::DecryptionFailed,
+        frame::{FramedReader, FramedWriter},
+        OpenReader, OpenWriter,
+    };
+    use async_std::{io, task};
+    fn pipe() -> io::Result<(FramedReader, OpenWriter)> {
+        let (read_fd, write_fd) = nix::unistd::pipe().unwrap();
+        unsafe {
+            Ok((
+                FramedReader::new(OpenReader::from_raw_fd(read_fd)?),
+                OpenWriter::from_raw_fd(write_fd)?,
+            ))
+        }
+    }
+    #[test]
+    fn encrypted_frames() -> io::Result<()> {
+        task::block_on(async {
+            let key = Key::generate();
+            let (reader, writer) = pipe()?;
+            let mut reader = reader.decrypt(key.clone());
+            let mut writer = FramedWriter::new(writer).chunked(true).encrypt(key.clone());
+            let big = vec![b'x'; 3 * nix::libc::PIPE_BUF];
+            let t_write = task::spawn(async move {
+                writer.send(b"secret").await?;
+                writer.send(&big).await?;
+                Ok::<_, io::Error>(big)
+            });
+            assert_eq!(reader.recv().await?, Some(b"secret".to_vec()));
+            let big = t_write.await?;
+            assert_eq!(reader.recv().await?, Some(big));
+
+            // A different key
+            let (reader, writer) = pipe()?;
+            let mut reader = reader.decrypt(Key::generate());
+            FramedWriter::new(writer)
+                .encrypt(key.clone())
+                .send(b"secret")
+                .await?;
+            let err = reader.recv().await.unwrap_err();
+            assert!(err.get_ref().unwrap().is::<DecryptionFailed>());
+
+            // No encryption at all
+            let (reader, writer) = pipe()?;
+            let mut reader = reader.decrypt(key);
+            FramedWriter::new(writer).send(b"plain").await?;
+            assert_eq!(
+                reader.recv().await.unwrap_err().kind(),
+                io::ErrorKind::InvalidData
+            );
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,182 @@
+//! Talking in both directions over a pair of pipes.
+//!
+//! A named pipe only carries data one way, so a two-way conversation needs
+//! one pipe for each direction. [`OpenDuplex`](struct.OpenDuplex.html)
+//! bundles both ends into a single handle that can be read from and written
+//! to, and split into halves that can be moved into separate tasks:
+//!
+//! ```no_run
+//! # fn main() -> async_std::io::Result<()> { async_std::task::block_on(async {
+//! use async_std::{prelude::*, task};
+//! use unix_fifo_async::duplex::OpenDuplex;
+//! use unix_fifo_async::NamedPipePath;
+//!
+//! let requests = NamedPipePath::new("./requests");
+//! let responses = NamedPipePath::new("./responses");
+//! // The other side opens the same pipes the other way round
+//! let duplex = OpenDuplex::open(&responses, &requests).await?;
+//! let (mut read, mut write) = duplex.into_split();
+//! task::spawn(async move { write.write_all(b"ping\n").await });
+//! let mut response = String::new();
+//! read.read_to_string(&mut response).await?;
+//! # Ok(())
+//! # })}
+//! ```
+use crate::{NamedPipePath, OpenReader, OpenWriter};
+use async_std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// The reading end of one pipe and the writing end of another, used as a
+/// single two-way channel.
+pub struct OpenDuplex {
+    read: OpenReader,
+    write: OpenWriter,
+}
+
+impl OpenDuplex {
+    /// Combines the ends of two pipes.
+    pub fn new(read: OpenReader, write: OpenWriter) -> Self {
+        Self { read, write }
+    }
+    /// Opens the reading end of `incoming` and the writing end of
+    /// `outgoing`, waiting for the other side to open `outgoing` for
+    /// reading.
+    ///
+    /// The reading end is opened first, so two processes opening the same
+    /// pair of pipes the other way round don't wait for each other forever.
+    pub async fn open(incoming: &NamedPipePath, outgoing: &NamedPipePath) -> io::Result<Self> {
+        let read = incoming.open_read().open().await?;
+        let write = outgoing.open_write().open().await?;
+        Ok(Self { read, write })
+    }
+    /// Borrows both ends at once, e.g. to read and write concurrently
+    /// within a single task.
+    pub fn split(&mut self) -> (&mut OpenReader, &mut OpenWriter) {
+        (&mut self.read, &mut self.write)
+    }
+    /// Splits the handle into halves that can be moved into different
+    /// tasks.
+    pub fn into_split(self) -> (ReadHalf, WriteHalf) {
+        (ReadHalf(self.read), WriteHalf(self.write))
+    }
+    /// Unwraps both ends.
+    pub fn into_inner(self) -> (OpenReader, OpenWriter) {
+        (self.read, self.write)
+    }
+}
+
+impl io::Read for OpenDuplex {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().read).poll_read(cx, buf)
+    }
+}
+
+impl io::Write for OpenDuplex {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().write).poll_write(cx, buf)
+    }
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().write).poll_flush(cx)
+    }
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().write).poll_close(cx)
+    }
+}
+
+/// The reading half of an [`OpenDuplex`](struct.OpenDuplex.html), created by
+/// [`into_split`](struct.OpenDuplex.html#method.into_split).
+pub struct ReadHalf(OpenReader);
+
+impl ReadHalf {
+    /// Puts the duplex handle back together.
+    pub fn reunite(self, write: WriteHalf) -> OpenDuplex {
+        OpenDuplex::new(self.0, write.0)
+    }
+    /// Unwraps the reading end.
+    pub fn into_inner(self) -> OpenReader {
+        self.0
+    }
+}
+
+impl io::Read for ReadHalf {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().0).poll_read(cx, buf)
+    }
+}
+
+/// The writing half of an [`OpenDuplex`](struct.OpenDuplex.html), created by
+/// [`into_split`](struct.OpenDuplex.html#method.into_split).
+///
+/// Dropping it closes the pipe, so the other side reads EOF.
+pub struct WriteHalf(OpenWriter);
+
+impl WriteHalf {
+    /// Puts the duplex handle back together.
+    pub fn reunite(self, read: ReadHalf) -> OpenDuplex {
+        read.reunite(self)
+    }
+    /// Unwraps the writing end.
+    pub fn into_inner(self) -> OpenWriter {
+        self.0
+    }
+}
+
+impl io::Write for WriteHalf {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().0).poll_write(cx, buf)
+    }
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().0).poll_flush(cx)
+    }
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().0).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OpenDuplex;
+    use crate::NamedPipePath;
+    use async_std::{io, prelude::*, task};
+    #[test]
+    fn split_into_tasks() -> io::Result<()> {
+        task::block_on(async {
+            let a = NamedPipePath::new("./test_pipe_37");
+            let b = NamedPipePath::new("./test_pipe_38");
+            a.ensure_exists().unwrap();
+            b.ensure_exists().unwrap();
+            let (peer_a, peer_b) = (a.clone(), b.clone());
+            let t_echo = task::spawn(async move {
+                let mut peer = OpenDuplex::open(&peer_b, &peer_a).await?;
+                let (read, write) = peer.split();
+                io::copy(read, write).await
+            });
+            let (mut read, mut write) = OpenDuplex::open(&a, &b).await?.into_split();
+            let t_write = task::spawn(async move { write.write_all(b"Hello pipe").await });
+            t_write.await?;
+            let mut echoed = String::new();
+            read.read_to_string(&mut echoed).await?;
+            assert_eq!(echoed, "Hello pipe");
+            assert_eq!(t_echo.await?, 10);
+            a.delete().await?;
+            b.delete().await
+        })
+    }
+}
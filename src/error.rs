@@ -0,0 +1,166 @@
+//! Errors specific to this crate.
+//!
+//! These are returned wrapped in an `io::Error`, so they fit into the usual
+//! `io::Result` signatures; use `io::Error::get_ref` and `downcast_ref` to
+//! tell them apart from other errors:
+//!
+//! ```
+//! use async_std::io;
+//! use unix_fifo_async::error::MessageTooLarge;
+//!
+//! fn is_too_large(e: &io::Error) -> bool {
+//!     e.get_ref()
+//!         .and_then(|e| e.downcast_ref::<MessageTooLarge>())
+//!         .is_some()
+//! }
+//! # assert!(is_too_large(&MessageTooLarge { limit: 16 }.into()));
+//! ```
+
+use nix::{sys::stat::Mode, unistd::Uid};
+use std::{error::Error, fmt, io};
+
+/// A message read from a pipe exceeded the reader's `max_message_size`.
+///
+/// Wrapped in an `io::Error` of kind `InvalidData`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageTooLarge {
+    /// The configured limit, in bytes.
+    pub limit: usize,
+}
+
+impl fmt::Display for MessageTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "message exceeds the limit of {} bytes", self.limit)
+    }
+}
+
+impl Error for MessageTooLarge {}
+
+impl From<MessageTooLarge> for io::Error {
+    fn from(e: MessageTooLarge) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    }
+}
+
+/// A reader dropped an incomplete chunked message to make room for a newer
+/// one, since it was already putting back together as many as it keeps.
+///
+/// Wrapped in an `io::Error` of kind `InvalidData`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageEvicted {
+    /// The ID of the dropped message.
+    pub id: u64,
+}
+
+impl fmt::Display for MessageEvicted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "incomplete message {:#018x} was dropped", self.id)
+    }
+}
+
+impl Error for MessageEvicted {}
+
+impl From<MessageEvicted> for io::Error {
+    fn from(e: MessageEvicted) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    }
+}
+
+/// A frame read from a pipe didn't match its checksum.
+///
+/// Wrapped in an `io::Error` of kind `InvalidData`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChecksumMismatch {
+    /// The checksum sent along with the frame.
+    pub expected: u32,
+    /// The checksum of the data that was actually received.
+    pub actual: u32,
+}
+
+impl fmt::Display for ChecksumMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "frame checksum mismatch: expected {:#010x}, got {:#010x}",
+            self.expected, self.actual
+        )
+    }
+}
+
+impl Error for ChecksumMismatch {}
+
+impl From<ChecksumMismatch> for io::Error {
+    fn from(e: ChecksumMismatch) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    }
+}
+
+/// An encrypted frame couldn't be decrypted: it was tampered with,
+/// corrupted, or encrypted with a different key.
+///
+/// Wrapped in an `io::Error` of kind `InvalidData`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecryptionFailed;
+
+impl fmt::Display for DecryptionFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("frame failed to decrypt")
+    }
+}
+
+impl Error for DecryptionFailed {}
+
+impl From<DecryptionFailed> for io::Error {
+    fn from(e: DecryptionFailed) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    }
+}
+
+/// A pipe opened in strict mode failed a security check; see
+/// [`NamedPipePath::strict`](../struct.NamedPipePath.html#method.strict).
+///
+/// Wrapped in an `io::Error` of kind `PermissionDenied`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InsecurePipe {
+    /// The path is a symbolic link.
+    Symlink,
+    /// The path isn't a named pipe.
+    NotAFifo,
+    /// The pipe is owned by another user.
+    WrongOwner {
+        /// The user that should own the pipe.
+        expected: Uid,
+        /// The user that actually owns it.
+        actual: Uid,
+    },
+    /// Anyone can write to the pipe.
+    WorldWritable {
+        /// The pipe's permission bits.
+        mode: Mode,
+    },
+}
+
+impl fmt::Display for InsecurePipe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InsecurePipe::Symlink => f.write_str("pipe path is a symbolic link"),
+            InsecurePipe::NotAFifo => f.write_str("pipe path isn't a named pipe"),
+            InsecurePipe::WrongOwner { expected, actual } => write!(
+                f,
+                "pipe is owned by user {} instead of {}",
+                actual, expected
+            ),
+            InsecurePipe::WorldWritable { mode } => {
+                write!(f, "pipe is writable by anyone (mode {:o})", mode.bits())
+            }
+        }
+    }
+}
+
+impl Error for InsecurePipe {}
+
+impl From<InsecurePipe> for io::Error {
+    fn from(e: InsecurePipe) -> Self {
+        io::Error::new(io::ErrorKind::PermissionDenied, e)
+    }
+}
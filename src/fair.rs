@@ -0,0 +1,113 @@
+use async_std::{
+    future,
+    sync::{Mutex, MutexGuard},
+};
+use std::collections::{HashMap, HashSet};
+use std::ops::{Deref, DerefMut};
+use std::task::{Poll, Waker};
+
+/// The order in which tasks get their turn with a `FairMutex`.
+///
+/// Every task draws a ticket and waits until it's being served, like at a
+/// deli counter.
+#[derive(Default)]
+struct Queue {
+    next_ticket: u64,
+    serving: u64,
+    /// Tickets whose tasks stopped waiting before their turn.
+    abandoned: HashSet<u64>,
+    wakers: HashMap<u64, Waker>,
+}
+
+impl Queue {
+    fn advance(&mut self) {
+        self.serving += 1;
+        while self.abandoned.remove(&self.serving) {
+            self.serving += 1;
+        }
+        if let Some(waker) = self.wakers.remove(&self.serving) {
+            waker.wake();
+        }
+    }
+}
+
+/// An async mutex that hands out the lock in the order tasks asked for it.
+pub(crate) struct FairMutex<T> {
+    queue: std::sync::Mutex<Queue>,
+    value: Mutex<T>,
+}
+
+impl<T> FairMutex<T> {
+    pub(crate) fn new(value: T) -> Self {
+        Self {
+            queue: Default::default(),
+            value: Mutex::new(value),
+        }
+    }
+    /// Waits for this task's turn and locks the mutex.
+    pub(crate) async fn lock(&self) -> FairMutexGuard<'_, T> {
+        let ticket = {
+            let mut queue = self.queue.lock().unwrap();
+            queue.next_ticket += 1;
+            queue.next_ticket - 1
+        };
+        // Gives up the ticket if this future is dropped while waiting
+        let turn = Turn {
+            queue: &self.queue,
+            ticket,
+        };
+        future::poll_fn(|cx| {
+            let mut queue = self.queue.lock().unwrap();
+            if queue.serving == ticket {
+                Poll::Ready(())
+            } else {
+                queue.wakers.insert(ticket, cx.waker().clone());
+                Poll::Pending
+            }
+        })
+        .await;
+        // Nobody else is past the queue, so this never waits
+        let value = self.value.lock().await;
+        FairMutexGuard { value, _turn: turn }
+    }
+}
+
+/// A task's place in the queue of a `FairMutex`.
+struct Turn<'a> {
+    queue: &'a std::sync::Mutex<Queue>,
+    ticket: u64,
+}
+
+impl Drop for Turn<'_> {
+    fn drop(&mut self) {
+        let mut queue = self.queue.lock().unwrap();
+        if queue.serving == self.ticket {
+            queue.advance();
+        } else {
+            queue.wakers.remove(&self.ticket);
+            queue.abandoned.insert(self.ticket);
+        }
+    }
+}
+
+/// The lock on a `FairMutex`; the next task in line gets its turn when this
+/// is dropped.
+pub(crate) struct FairMutexGuard<'a, T> {
+    // Declared first so the value is unlocked before the turn passes on
+    value: MutexGuard<'a, T>,
+    _turn: Turn<'a>,
+}
+
+impl<T> Deref for FairMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for FairMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
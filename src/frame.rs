@@ -0,0 +1,669 @@
+//! Sending discrete messages over a single open pipe.
+//!
+//! Every message is sent as a frame: a five byte header, holding flags
+//! (`u8`) and the length of the payload (`u32`, little-endian), followed
+//! by the payload. Unlike whole-pipe reads, this keeps message boundaries
+//! intact while the pipe stays open.
+//!
+//! Writers can append a CRC32 of the header and payload (`u32`,
+//! little-endian) to each frame, which readers verify to detect corruption,
+//! e.g. from interleaved writes. Since the payload has to be buffered
+//! before it can be verified, a corrupted length can still make the reader
+//! wait for a lot of data; set
+//! [`max_message_size`](struct.FramedReader.html#method.max_message_size)
+//! to bound that.
+//!
+//! Writes of up to `PIPE_BUF` bytes are atomic, so several writers can
+//! share a pipe as long as every frame fits. In chunked mode, writers split
+//! larger messages into frames of that size, each carrying a chunk header:
+//! a message ID (`u64`), the chunk's sequence number and the total number
+//! of chunks (both `u32`), all little-endian. Readers put the messages back
+//! together, even if chunks from different writers are interleaved.
+//!
+//! With the `compression` feature, writers can compress payloads with zstd;
+//! readers decompress them transparently, based on the frame's flags. With
+//! the `crypto` feature, messages can be [encrypted](../crypto/index.html).
+//!
+//! ```no_run
+//! # fn main() -> async_std::io::Result<()> { async_std::task::block_on(async {
+//! use unix_fifo_async::frame::{FramedReader, FramedWriter};
+//! use unix_fifo_async::NamedPipePath;
+//!
+//! let pipe = NamedPipePath::new("./messages");
+//! let mut reader = FramedReader::new(pipe.open_read().open().await?);
+//! let mut writer = FramedWriter::new(pipe.open_write().open().await?);
+//! writer.send(b"first").await?;
+//! writer.send(b"second").await?;
+//! drop(writer);
+//! while let Some(message) = reader.recv().await? {
+//!     println!("{}", String::from_utf8_lossy(&message));
+//! }
+//! # Ok(())
+//! # })}
+//! ```
+#[cfg(feature = "crypto")]
+use crate::crypto::{self, KeyProvider};
+use crate::{
+    error::{ChecksumMismatch, MessageEvicted, MessageTooLarge},
+    OpenReader, OpenWriter,
+};
+use async_std::io;
+use nix::libc::PIPE_BUF;
+#[cfg(any(feature = "compression", feature = "crypto"))]
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryInto;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Instant;
+
+/// Length of the frame header.
+pub(crate) const HEADER_LEN: usize = 5;
+/// The payload is compressed with zstd.
+const FLAG_COMPRESSED: u8 = 0b0000_0001;
+/// The payload is followed by a CRC32 of the header and payload.
+const FLAG_CHECKSUM: u8 = 0b0000_0010;
+/// The payload is one chunk of a larger message.
+const FLAG_CHUNKED: u8 = 0b0000_0100;
+/// The frame is a sign of life without a message.
+const FLAG_PING: u8 = 0b0000_1000;
+/// The payload is encrypted.
+const FLAG_ENCRYPTED: u8 = 0b0001_0000;
+/// All flags this version understands.
+const KNOWN_FLAGS: u8 = FLAG_COMPRESSED | FLAG_CHECKSUM | FLAG_CHUNKED | FLAG_PING | FLAG_ENCRYPTED;
+/// Length of the chunk header at the start of a chunk's payload.
+const CHUNK_HEADER_LEN: usize = 16;
+/// Length of the checksum after the payload.
+const CHECKSUM_LEN: usize = 4;
+/// How much larger encrypting makes a message: the nonce and the tag.
+const ENCRYPTION_OVERHEAD: usize = 40;
+/// How many chunked messages a reader puts back together at once by
+/// default.
+const DEFAULT_MAX_INCOMPLETE: usize = 64;
+
+/// Counts chunked messages sent by this process.
+static MESSAGE_COUNTER: AtomicU32 = AtomicU32::new(0);
+
+/// Returns an ID for a chunked message that is unique among all processes
+/// writing to the same pipe (until the counter wraps around).
+fn next_message_id() -> u64 {
+    let pid = nix::unistd::getpid().as_raw() as u32;
+    let counter = MESSAGE_COUNTER.fetch_add(1, Ordering::Relaxed);
+    u64::from(pid) << 32 | u64::from(counter)
+}
+
+/// Writes messages to a pipe as frames.
+pub struct FramedWriter {
+    inner: OpenWriter,
+    checksum: bool,
+    chunked: bool,
+    #[cfg(feature = "compression")]
+    compression: Option<i32>,
+    #[cfg(feature = "crypto")]
+    keys: Option<Box<dyn KeyProvider>>,
+}
+
+impl FramedWriter {
+    /// Wraps the writing end of a pipe.
+    pub fn new(inner: OpenWriter) -> Self {
+        Self {
+            inner,
+            checksum: false,
+            chunked: false,
+            #[cfg(feature = "compression")]
+            compression: None,
+            #[cfg(feature = "crypto")]
+            keys: None,
+        }
+    }
+    /// Appends a CRC32 of the payload to every frame, so the reader can
+    /// detect corrupted frames.
+    pub fn checksum(mut self, checksum: bool) -> Self {
+        self.checksum = checksum;
+        self
+    }
+    /// Splits messages that don't fit into a single atomic write into
+    /// chunks, so other writers on the same pipe can't corrupt them.
+    pub fn chunked(mut self, chunked: bool) -> Self {
+        self.chunked = chunked;
+        self
+    }
+    /// Compresses payloads with zstd at the given level (1-22, 0 for the
+    /// default).
+    ///
+    /// Payloads that don't get smaller are sent uncompressed.
+    #[cfg(feature = "compression")]
+    pub fn compress(mut self, level: i32) -> Self {
+        self.compression = Some(level);
+        self
+    }
+    /// Encrypts messages with the key from `keys`.
+    ///
+    /// See [`crypto`](../crypto/index.html) for details.
+    #[cfg(feature = "crypto")]
+    pub fn encrypt<K: KeyProvider + 'static>(mut self, keys: K) -> Self {
+        self.keys = Some(Box::new(keys));
+        self
+    }
+    /// Sends `message` as a single frame, or as a series of chunks in
+    /// [chunked](#method.chunked) mode.
+    pub async fn send(&mut self, message: &[u8]) -> io::Result<()> {
+        #[cfg(any(feature = "compression", feature = "crypto"))]
+        {
+            #[allow(unused_mut)]
+            let mut flags = 0;
+            #[allow(unused_mut)]
+            let mut payload = Cow::Borrowed(message);
+            #[cfg(feature = "compression")]
+            {
+                if let Some(level) = self.compression {
+                    let compressed = zstd::stream::encode_all(message, level)?;
+                    if compressed.len() < message.len() {
+                        flags |= FLAG_COMPRESSED;
+                        payload = Cow::Owned(compressed);
+                    }
+                }
+            }
+            #[cfg(feature = "crypto")]
+            {
+                if let Some(keys) = &self.keys {
+                    // The compression flag changes how the message is read,
+                    // so it's authenticated along with it
+                    payload = Cow::Owned(crypto::seal(keys.as_ref(), &[flags], &payload)?);
+                    flags |= FLAG_ENCRYPTED;
+                }
+            }
+            self.send_payload(flags, &payload).await
+        }
+        #[cfg(not(any(feature = "compression", feature = "crypto")))]
+        {
+            self.send_payload(0, message).await
+        }
+    }
+    async fn send_payload(&mut self, flags: u8, payload: &[u8]) -> io::Result<()> {
+        let checksum_len = if self.checksum { CHECKSUM_LEN } else { 0 };
+        let max_payload = PIPE_BUF - HEADER_LEN - checksum_len;
+        if !self.chunked || payload.len() <= max_payload {
+            return self.send_frame(flags, payload).await;
+        }
+        let chunk_len = max_payload - CHUNK_HEADER_LEN;
+        let count: u32 = payload
+            .len()
+            .div_ceil(chunk_len)
+            .try_into()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "message too large"))?;
+        let id = next_message_id();
+        let mut frame = Vec::with_capacity(max_payload);
+        for (seq, chunk) in (0..count).zip(payload.chunks(chunk_len)) {
+            frame.clear();
+            frame.extend_from_slice(&id.to_le_bytes());
+            frame.extend_from_slice(&seq.to_le_bytes());
+            frame.extend_from_slice(&count.to_le_bytes());
+            frame.extend_from_slice(chunk);
+            self.send_frame(flags | FLAG_CHUNKED, &frame).await?;
+        }
+        Ok(())
+    }
+    async fn send_frame(&mut self, mut flags: u8, payload: &[u8]) -> io::Result<()> {
+        let len: u32 = payload.len().try_into().map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidInput, "message too large for a frame")
+        })?;
+        if self.checksum {
+            flags |= FLAG_CHECKSUM;
+        }
+        let mut header = [0; HEADER_LEN];
+        header[0] = flags;
+        header[1..].copy_from_slice(&len.to_le_bytes());
+        let mut trailer = [0; CHECKSUM_LEN];
+        let mut trailer_len = 0;
+        if self.checksum {
+            let mut hasher = crc32fast::Hasher::new();
+            hasher.update(&header);
+            hasher.update(payload);
+            trailer = hasher.finalize().to_le_bytes();
+            trailer_len = trailer.len();
+        }
+        let mut bufs = [
+            io::IoSlice::new(&header),
+            io::IoSlice::new(payload),
+            io::IoSlice::new(&trailer[..trailer_len]),
+        ];
+        self.inner.write_all_vectored(&mut bufs).await
+    }
+    /// Sends a ping, an empty frame the reader skips, to show the writer
+    /// is still alive.
+    ///
+    /// See [`heartbeat`](../heartbeat/index.html) for sending them
+    /// periodically.
+    pub async fn ping(&mut self) -> io::Result<()> {
+        self.send_frame(FLAG_PING, &[]).await
+    }
+    /// Gets a reference to the underlying pipe.
+    pub fn get_ref(&self) -> &OpenWriter {
+        &self.inner
+    }
+    /// Unwraps the underlying pipe.
+    pub fn into_inner(self) -> OpenWriter {
+        self.inner
+    }
+}
+
+/// A chunked message that's missing chunks.
+struct Partial {
+    /// The sequence number of the next chunk.
+    next: u32,
+    message: Vec<u8>,
+    started: Instant,
+}
+
+/// Reads frames written by a [`FramedWriter`](struct.FramedWriter.html).
+pub struct FramedReader {
+    inner: OpenReader,
+    max_message_size: Option<usize>,
+    /// Chunked messages being put back together, by message ID.
+    partial: HashMap<u64, Partial>,
+    max_incomplete: usize,
+    /// The IDs of messages dropped to make room, whose remaining chunks
+    /// are skipped.
+    evicted: VecDeque<u64>,
+    last_seen: Option<Instant>,
+    #[cfg(feature = "crypto")]
+    keys: Option<Box<dyn KeyProvider>>,
+}
+
+impl FramedReader {
+    /// Wraps the reading end of a pipe.
+    pub fn new(inner: OpenReader) -> Self {
+        Self {
+            inner,
+            max_message_size: None,
+            partial: HashMap::new(),
+            max_incomplete: DEFAULT_MAX_INCOMPLETE,
+            evicted: VecDeque::new(),
+            last_seen: None,
+            #[cfg(feature = "crypto")]
+            keys: None,
+        }
+    }
+    /// Returns when the last frame, including pings, was received.
+    pub fn last_seen(&self) -> Option<Instant> {
+        self.last_seen
+    }
+    /// Rejects messages larger than `limit` bytes with an
+    /// [`error::MessageTooLarge`](../error/struct.MessageTooLarge.html),
+    /// before reading (or decompressing) them.
+    pub fn max_message_size(mut self, limit: usize) -> Self {
+        self.max_message_size = Some(limit);
+        self
+    }
+    /// Sets how many chunked messages are put back together at once, i.e.
+    /// how many writers can send chunked messages concurrently. Defaults
+    /// to 64.
+    ///
+    /// When another message starts, the oldest incomplete one is dropped,
+    /// which is reported as an
+    /// [`error::MessageEvicted`](../error/struct.MessageEvicted.html).
+    pub fn max_incomplete(mut self, limit: usize) -> Self {
+        self.max_incomplete = limit.max(1);
+        self
+    }
+    /// Decrypts messages with the key from `keys`, and rejects messages
+    /// that aren't encrypted.
+    ///
+    /// See [`crypto`](../crypto/index.html) for details.
+    #[cfg(feature = "crypto")]
+    pub fn decrypt<K: KeyProvider + 'static>(mut self, keys: K) -> Self {
+        self.keys = Some(Box::new(keys));
+        self
+    }
+    /// Receives the next message.
+    ///
+    /// Returns `None` once the writer closes the pipe between two messages.
+    /// A pipe closing in the middle of a message is an `UnexpectedEof`
+    /// error; the incomplete messages are dropped then, so the next call
+    /// returns `None`. Beyond [`max_incomplete`](#method.max_incomplete)
+    /// chunked messages in progress, the oldest is dropped with an
+    /// [`error::MessageEvicted`](../error/struct.MessageEvicted.html) and
+    /// its remaining chunks are skipped; the next call carries on with the
+    /// other messages. A frame that doesn't match its checksum fails with an
+    /// [`error::ChecksumMismatch`](../error/struct.ChecksumMismatch.html).
+    /// With [`decrypt`](#method.decrypt), messages that fail to decrypt are
+    /// an [`error::DecryptionFailed`](../error/struct.DecryptionFailed.html),
+    /// and unencrypted ones are rejected with `InvalidData`.
+    ///
+    /// This is cancel-safe: a frame is only consumed once it has been read
+    /// completely, and chunks of a message are collected in the reader.
+    pub async fn recv(&mut self) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            let (flags, payload) = match self.recv_frame().await? {
+                Some(frame) => frame,
+                None if self.partial.is_empty() => return Ok(None),
+                None => {
+                    // Report the cut-off messages once, not on every call
+                    self.partial.clear();
+                    self.evicted.clear();
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+            };
+            if flags & FLAG_PING != 0 {
+                continue;
+            }
+            let payload = if flags & FLAG_CHUNKED != 0 {
+                match self.reassemble(flags, payload)? {
+                    Some(payload) => payload,
+                    None => continue,
+                }
+            } else {
+                payload
+            };
+            let payload = self.decrypt_payload(flags, payload)?;
+            if flags & FLAG_COMPRESSED != 0 {
+                return self.decompress(&payload).map(Some);
+            }
+            return Ok(Some(payload));
+        }
+    }
+    /// Reads the next frame, returning its flags and verified payload.
+    pub(crate) async fn recv_frame(&mut self) -> io::Result<Option<(u8, Vec<u8>)>> {
+        // Nothing is consumed before the whole frame is buffered, so a
+        // dropped future never leaves the reader in the middle of a frame.
+        // A clean EOF is one that happens before the first byte of a frame.
+        match self.inner.fill_to(HEADER_LEN).await? {
+            0 => return Ok(None),
+            n if n < HEADER_LEN => return Err(io::ErrorKind::UnexpectedEof.into()),
+            _ => {}
+        }
+        let header = &self.inner.buffered()[..HEADER_LEN];
+        let flags = header[0];
+        let len = u32::from_le_bytes(header[1..].try_into().unwrap()) as usize;
+        if flags & !KNOWN_FLAGS != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unknown frame flags {:#010b}", flags),
+            ));
+        }
+        if flags & FLAG_CHUNKED != 0 && len > PIPE_BUF - HEADER_LEN {
+            // Chunks are written atomically, so they always fit
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "chunk larger than PIPE_BUF",
+            ));
+        }
+        if let Some(limit) = self.max_message_size {
+            // Compressed payloads are checked again after decompressing
+            if len > limit.saturating_add(payload_overhead(flags)) {
+                return Err(MessageTooLarge { limit }.into());
+            }
+        }
+        let checksum_len = if flags & FLAG_CHECKSUM != 0 {
+            CHECKSUM_LEN
+        } else {
+            0
+        };
+        let frame_len = HEADER_LEN + len + checksum_len;
+        if self.inner.fill_to(frame_len).await? < frame_len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let mut payload = self.inner.take_buffered(frame_len);
+        if checksum_len > 0 {
+            let end = HEADER_LEN + len;
+            let expected = u32::from_le_bytes(payload[end..].try_into().unwrap());
+            // Covers the header too, so a corrupted length is caught
+            let actual = crc32fast::hash(&payload[..end]);
+            if expected != actual {
+                return Err(ChecksumMismatch { expected, actual }.into());
+            }
+            payload.truncate(end);
+        }
+        payload.drain(..HEADER_LEN);
+        self.last_seen = Some(Instant::now());
+        Ok(Some((flags, payload)))
+    }
+    /// Adds a chunk to its message, returning the message once it's
+    /// complete.
+    fn reassemble(&mut self, flags: u8, chunk: Vec<u8>) -> io::Result<Option<Vec<u8>>> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg);
+        if chunk.len() < CHUNK_HEADER_LEN {
+            return Err(invalid("truncated chunk header"));
+        }
+        let id = u64::from_le_bytes(chunk[..8].try_into().unwrap());
+        let seq = u32::from_le_bytes(chunk[8..12].try_into().unwrap());
+        let count = u32::from_le_bytes(chunk[12..16].try_into().unwrap());
+        if let Some(i) = self.evicted.iter().position(|&evicted| evicted == id) {
+            if seq.saturating_add(1) >= count {
+                self.evicted.remove(i);
+            }
+            return Ok(None);
+        }
+        let partial = self.partial.entry(id).or_insert_with(|| Partial {
+            next: 0,
+            message: Vec::new(),
+            started: Instant::now(),
+        });
+        if seq != partial.next || seq >= count {
+            self.partial.remove(&id);
+            return Err(invalid("chunk out of sequence"));
+        }
+        partial.next += 1;
+        partial
+            .message
+            .extend_from_slice(&chunk[CHUNK_HEADER_LEN..]);
+        if let Some(limit) = self.max_message_size {
+            if partial.message.len() > limit.saturating_add(payload_overhead(flags)) {
+                self.partial.remove(&id);
+                return Err(MessageTooLarge { limit }.into());
+            }
+        }
+        if partial.next == count {
+            return Ok(self.partial.remove(&id).map(|partial| partial.message));
+        }
+        if self.partial.len() > self.max_incomplete {
+            // Most likely abandoned by a writer that went away
+            let oldest = self
+                .partial
+                .iter()
+                .filter(|(&other, _)| other != id)
+                .min_by_key(|(_, p)| p.started)
+                .map(|(&id, _)| id);
+            if let Some(oldest) = oldest {
+                self.partial.remove(&oldest);
+                if self.evicted.len() >= self.max_incomplete {
+                    self.evicted.pop_front();
+                }
+                self.evicted.push_back(oldest);
+                return Err(MessageEvicted { id: oldest }.into());
+            }
+        }
+        Ok(None)
+    }
+    #[cfg(feature = "crypto")]
+    fn decrypt_payload(&self, flags: u8, payload: Vec<u8>) -> io::Result<Vec<u8>> {
+        match (&self.keys, flags & FLAG_ENCRYPTED != 0) {
+            (Some(keys), true) => crypto::open(keys.as_ref(), &[flags & FLAG_COMPRESSED], &payload),
+            (Some(_), false) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "received an unencrypted frame",
+            )),
+            (None, true) => Err(no_key()),
+            (None, false) => Ok(payload),
+        }
+    }
+    #[cfg(not(feature = "crypto"))]
+    fn decrypt_payload(&self, flags: u8, payload: Vec<u8>) -> io::Result<Vec<u8>> {
+        if flags & FLAG_ENCRYPTED != 0 {
+            return Err(no_key());
+        }
+        Ok(payload)
+    }
+    #[cfg(feature = "compression")]
+    fn decompress(&self, payload: &[u8]) -> io::Result<Vec<u8>> {
+        use std::io::Read;
+        let decoder = zstd::stream::read::Decoder::with_buffer(payload)?;
+        let mut message = Vec::new();
+        match self.max_message_size {
+            Some(limit) => {
+                decoder.take(limit as u64 + 1).read_to_end(&mut message)?;
+                if message.len() > limit {
+                    return Err(MessageTooLarge { limit }.into());
+                }
+            }
+            None => {
+                let mut decoder = decoder;
+                decoder.read_to_end(&mut message)?;
+            }
+        }
+        Ok(message)
+    }
+    #[cfg(not(feature = "compression"))]
+    fn decompress(&self, _payload: &[u8]) -> io::Result<Vec<u8>> {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "received a compressed frame without the `compression` feature",
+        ))
+    }
+    /// Unwraps the underlying pipe.
+    pub fn into_inner(self) -> OpenReader {
+        self.inner
+    }
+}
+
+/// How much larger than the message a frame's payload may be.
+fn payload_overhead(flags: u8) -> usize {
+    if flags & FLAG_ENCRYPTED != 0 {
+        ENCRYPTION_OVERHEAD
+    } else {
+        0
+    }
+}
+
+fn no_key() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "received an encrypted frame without a key to decrypt it",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FramedReader, FramedWriter};
+    use crate::NamedPipePath;
+    use async_std::{io, task};
+    #[test]
+    fn send_and_recv_frames() -> io::Result<()> {
+        task::block_on(async {
+            let pipe = NamedPipePath::new("./test_pipe_33");
+            pipe.ensure_exists().unwrap();
+            let mut reader = FramedReader::new(pipe.open_read().open().await?);
+            let writer = pipe.open_write().open().await?;
+            let t_write = task::spawn(async move {
+                let mut writer = FramedWriter::new(writer);
+                writer.send(b"Hello").await?;
+                writer.ping().await?;
+                let mut writer = writer.checksum(true);
+                writer.send(b"").await?;
+                #[cfg(feature = "compression")]
+                let mut writer = writer.compress(0);
+                writer.send(&[b'x'; 4096]).await
+            });
+            assert_eq!(reader.recv().await?.unwrap(), b"Hello");
+            assert_eq!(reader.recv().await?.unwrap(), b"");
+            assert_eq!(reader.recv().await?.unwrap(), vec![b'x'; 4096]);
+            assert_eq!(reader.recv().await?, None);
+            t_write.await?;
+            pipe.delete().await
+        })
+    }
+    #[test]
+    fn interleaved_chunks() -> io::Result<()> {
+        use crate::{OpenReader, OpenWriter};
+        task::block_on(async {
+            let (read_fd, write_fd) = nix::unistd::pipe().unwrap();
+            let second_fd = nix::unistd::dup(write_fd).unwrap();
+            let mut reader = FramedReader::new(unsafe { OpenReader::from_raw_fd(read_fd)? });
+            let mut tasks = Vec::new();
+            for &(fd, byte) in &[(write_fd, b'a'), (second_fd, b'b')] {
+                let writer = unsafe { OpenWriter::from_raw_fd(fd)? };
+                tasks.push(task::spawn(async move {
+                    let mut writer = FramedWriter::new(writer).chunked(true);
+                    writer.send(&vec![byte; 100_000]).await
+                }));
+            }
+            let mut messages = vec![reader.recv().await?.unwrap(), reader.recv().await?.unwrap()];
+            messages.sort();
+            assert_eq!(messages, vec![vec![b'a'; 100_000], vec![b'b'; 100_000]]);
+            assert_eq!(reader.recv().await?, None);
+            for t in tasks {
+                t.await?;
+            }
+            Ok(())
+        })
+    }
+    #[test]
+    fn detect_corrupted_frame() -> io::Result<()> {
+        use crate::{error::ChecksumMismatch, OpenReader, OpenWriter};
+        use async_std::prelude::*;
+        task::block_on(async {
+            let (read_fd, write_fd) = nix::unistd::pipe().unwrap();
+            let reader = unsafe { OpenReader::from_raw_fd(read_fd)? };
+            let mut writer = unsafe { OpenWriter::from_raw_fd(write_fd)? };
+            // A frame with a checksum, but a flipped bit in the payload
+            let mut frame = vec![0b10, 5, 0, 0, 0];
+            let checksum = crc32fast::hash(&[&frame[..], &b"Hello"[..]].concat());
+            frame.extend_from_slice(b"Hellp");
+            frame.extend_from_slice(&checksum.to_le_bytes());
+            // One with a flipped bit in the length, but a matching payload
+            let mut short = vec![0b10, 4, 0, 0, 0];
+            short.extend_from_slice(b"Hell");
+            short.extend_from_slice(&crc32fast::hash(b"\x02\x05\0\0\0Hell").to_le_bytes());
+            writer.write_all(&frame).await?;
+            writer.write_all(&short).await?;
+            let mut reader = FramedReader::new(reader);
+            for _ in 0..2 {
+                let err = reader.recv().await.unwrap_err();
+                assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+                assert!(err.get_ref().unwrap().is::<ChecksumMismatch>());
+            }
+            Ok(())
+        })
+    }
+    #[test]
+    fn writer_dies_mid_message() -> io::Result<()> {
+        use super::{DEFAULT_MAX_INCOMPLETE, FLAG_CHUNKED};
+        use crate::error::MessageEvicted;
+        use crate::{OpenReader, OpenWriter};
+        use async_std::prelude::*;
+        task::block_on(async {
+            let (read_fd, write_fd) = nix::unistd::pipe().unwrap();
+            let reader = unsafe { OpenReader::from_raw_fd(read_fd)? };
+            let mut writer = unsafe { OpenWriter::from_raw_fd(write_fd)? };
+            let chunk = |id: u64, seq: u32| {
+                let mut frame = vec![FLAG_CHUNKED, 17, 0, 0, 0];
+                frame.extend_from_slice(&id.to_le_bytes());
+                frame.extend_from_slice(&seq.to_le_bytes());
+                frame.extend_from_slice(&2u32.to_le_bytes());
+                frame.push(b'x');
+                frame
+            };
+            // First chunks of more messages than the reader keeps, which
+            // never get finished
+            for id in 0..=DEFAULT_MAX_INCOMPLETE as u64 {
+                writer.write_all(&chunk(id, 0)).await?;
+            }
+            // The rest of the dropped message is skipped
+            writer.write_all(&chunk(0, 1)).await?;
+            let mut writer = FramedWriter::new(writer);
+            writer.send(b"complete").await?;
+            drop(writer);
+            let mut reader = FramedReader::new(reader);
+            let err = reader.recv().await.unwrap_err();
+            let evicted = err.get_ref().unwrap().downcast_ref::<MessageEvicted>();
+            assert_eq!(evicted, Some(&MessageEvicted { id: 0 }));
+            assert_eq!(reader.recv().await?, Some(b"complete".to_vec()));
+            assert_eq!(reader.partial.len(), DEFAULT_MAX_INCOMPLETE);
+            assert!(reader.evicted.is_empty());
+            let err = reader.recv().await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+            assert_eq!(reader.recv().await?, None);
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,921 @@
+use crate::{
+    error::MessageTooLarge,
+    hangup::hangup,
+    probe::Probe,
+    util::{bytes_available, nix_to_io},
+    NamedPipePath, SharedReader,
+};
+use async_io::{Async, ReadableOwned, WritableOwned};
+use async_std::{future, io, prelude::*, task};
+use nix::fcntl::{fcntl, FcntlArg, FdFlag, OFlag};
+use nix::libc;
+use std::fs::File;
+use std::future::Future;
+use std::io::{Read as _, Write as _};
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+use std::pin::Pin;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+/// How much to read at once when looking for a message boundary.
+const READ_CHUNK: usize = 4096;
+/// How long to wait before retrying to open a pipe that has no reader yet.
+const OPEN_RETRY_MIN: Duration = Duration::from_millis(1);
+/// Upper bound for the exponential backoff between open attempts.
+const OPEN_RETRY_MAX: Duration = Duration::from_millis(50);
+
+/// The reading end of a named pipe, opened in non-blocking mode.
+///
+/// The file descriptor is registered with the async reactor, so waiting for
+/// a writer or for more data doesn't occupy any thread.
+///
+/// # Cancel safety
+///
+/// Reads through the `async_std::io::Read` traits, as well as
+/// [`read_exact`](#method.read_exact) and
+/// [`read_until`](#method.read_until), can be dropped before they complete
+/// (e.g. when losing a `select!`) without losing data: whatever was already
+/// pulled from the pipe stays buffered in the handle and is returned by the
+/// next read. The same holds for
+/// [`FramedReader::recv`](frame/struct.FramedReader.html#method.recv).
+/// Helpers that consume a handle or read into a caller-provided buffer
+/// across several awaits, like `read_to_end` or `copy`, aren't cancel-safe.
+pub struct OpenReader {
+    inner: Arc<Async<File>>,
+    /// The wait for the pipe to turn readable, kept across polls.
+    ready: Option<ReadableOwned<File>>,
+    attached: bool,
+    pub(crate) probe: Probe,
+    /// Data read past a boundary by `read_exact` or `read_until`, handed out
+    /// before anything else.
+    buf: Vec<u8>,
+    max_message_size: Option<usize>,
+}
+
+impl OpenReader {
+    /// Opens the reading end of `pipe`.
+    ///
+    /// This never waits for a writer; reads on the returned handle will.
+    /// With `hold_open`, the pipe is also opened for writing, so it never
+    /// reports EOF.
+    pub(crate) fn open(pipe: &NamedPipePath, hold_open: bool) -> io::Result<Self> {
+        let access = if hold_open {
+            OFlag::O_RDWR
+        } else {
+            OFlag::O_RDONLY
+        };
+        let start = Instant::now();
+        let file = pipe.open_file(access | OFlag::O_NONBLOCK)?;
+        let probe = Probe::new(pipe.as_path());
+        probe.opened("read", start.elapsed());
+        Ok(Self {
+            inner: Arc::new(Async::new(file)?),
+            ready: None,
+            attached: false,
+            probe,
+            buf: Vec::new(),
+            max_message_size: None,
+        })
+    }
+    /// Adopts an open file descriptor as the reading end of a pipe.
+    ///
+    /// The descriptor is switched to non-blocking mode and registered with
+    /// the async reactor. It doesn't have to belong to a named pipe; the
+    /// read end of an anonymous pipe works just as well.
+    ///
+    /// # Safety
+    ///
+    /// `fd` must be an open file descriptor that isn't owned by anything
+    /// else; the returned handle closes it on drop.
+    pub unsafe fn from_raw_fd(fd: RawFd) -> io::Result<Self> {
+        Ok(Self {
+            inner: Arc::new(Async::new(File::from_raw_fd(fd))?),
+            ready: None,
+            attached: false,
+            probe: Probe::default(),
+            buf: Vec::new(),
+            max_message_size: None,
+        })
+    }
+    /// Turns this handle into one that can be cloned and shared between
+    /// tasks, which take turns reading from it.
+    pub fn into_shared(self) -> SharedReader {
+        SharedReader::new(self)
+    }
+    /// Limits how many bytes [`read_exact`](#method.read_exact) and
+    /// [`read_until`](#method.read_until) return at once.
+    ///
+    /// Asking for more fails with an
+    /// [`error::MessageTooLarge`](error/struct.MessageTooLarge.html), so a
+    /// misbehaving writer can't make the reader allocate without bound.
+    /// Handles opened by a
+    /// [`NamedPipeReader`](struct.NamedPipeReader.html) take its limit.
+    /// Reads through the `async_std::io::Read` traits are bounded by their
+    /// buffer and aren't affected.
+    pub fn max_message_size(mut self, limit: usize) -> Self {
+        self.max_message_size = Some(limit);
+        self
+    }
+    /// Reads exactly `n` bytes from the pipe.
+    ///
+    /// Anything read past the `n`th byte is kept and returned by the next
+    /// read on this handle. Fails with `UnexpectedEof` if the pipe reaches
+    /// EOF first; the bytes read so far stay buffered in that case. If `n`
+    /// exceeds the [`max_message_size`](#method.max_message_size), fails
+    /// without reading anything.
+    ///
+    /// This is cancel-safe: if the future is dropped, the bytes read so far
+    /// stay buffered as well.
+    pub async fn read_exact(&mut self, n: usize) -> io::Result<Vec<u8>> {
+        self.check_size(n)?;
+        if self.fill_to(n).await? < n {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(self.take_buffered(n))
+    }
+    /// Reads from the pipe up to and including the next `delimiter`.
+    ///
+    /// Anything read past the delimiter is kept and returned by the next
+    /// read on this handle. If the pipe reaches EOF first, returns all of
+    /// the remaining data without a delimiter, which is empty once the pipe
+    /// has been read to the end. If more than the
+    /// [`max_message_size`](#method.max_message_size) comes before the
+    /// delimiter, fails with the bytes read so far still buffered.
+    ///
+    /// This is cancel-safe: if the future is dropped, the bytes read so far
+    /// stay buffered.
+    pub async fn read_until(&mut self, delimiter: u8) -> io::Result<Vec<u8>> {
+        let mut searched = 0;
+        loop {
+            if let Some(pos) = self.buf[searched..].iter().position(|&b| b == delimiter) {
+                self.check_size(searched + pos + 1)?;
+                return Ok(self.take_buffered(searched + pos + 1));
+            }
+            searched = self.buf.len();
+            self.check_size(searched)?;
+            if self.fill_buf().await? == 0 {
+                return Ok(std::mem::take(&mut self.buf));
+            }
+        }
+    }
+    /// Takes everything that can be read from the pipe right now, without
+    /// waiting for more.
+    ///
+    /// Returns the data buffered in this handle followed by whatever is in
+    /// the pipe, which may be nothing. Unlike reading to the end, this
+    /// returns right away even while writers are still attached: data
+    /// written while draining is left for the next read. If there's more
+    /// than the [`max_message_size`](#method.max_message_size), fails
+    /// without reading anything.
+    pub fn drain(&mut self) -> io::Result<Vec<u8>> {
+        let mut remaining = bytes_available(self.as_raw_fd())?;
+        self.check_size(self.buf.len() + remaining)?;
+        let mut data = std::mem::take(&mut self.buf);
+        let mut chunk = [0; READ_CHUNK];
+        while remaining > 0 {
+            let len = remaining.min(READ_CHUNK);
+            match self.inner.get_ref().read(&mut chunk[..len]) {
+                // EOF, or no writer attached yet
+                Ok(0) => break,
+                Ok(n) => {
+                    self.probe.bytes_read(n);
+                    data.extend_from_slice(&chunk[..n]);
+                    remaining -= n;
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    // Keep what was read so far for the next read
+                    self.buf = data;
+                    return Err(e);
+                }
+            }
+        }
+        Ok(data)
+    }
+    /// Returns how many bytes can be read right now without waiting,
+    /// including the data buffered in this handle.
+    pub fn bytes_available(&self) -> io::Result<usize> {
+        Ok(self.buf.len() + bytes_available(self.as_raw_fd())?)
+    }
+    /// Fails if a message of `n` bytes exceeds the limit.
+    fn check_size(&self, n: usize) -> io::Result<()> {
+        match self.max_message_size {
+            Some(limit) if n > limit => Err(MessageTooLarge { limit }.into()),
+            _ => Ok(()),
+        }
+    }
+    /// Reads from the pipe until at least `n` bytes are buffered, returning
+    /// how many are, which is less than `n` only at EOF.
+    pub(crate) async fn fill_to(&mut self, n: usize) -> io::Result<usize> {
+        while self.buf.len() < n {
+            if self.fill_buf().await? == 0 {
+                break;
+            }
+        }
+        Ok(self.buf.len())
+    }
+    /// Returns the data read from the pipe but not handed out yet.
+    pub(crate) fn buffered(&self) -> &[u8] {
+        &self.buf
+    }
+    /// Removes the first `n` buffered bytes and returns them.
+    pub(crate) fn take_buffered(&mut self, n: usize) -> Vec<u8> {
+        let rest = self.buf.split_off(n);
+        std::mem::replace(&mut self.buf, rest)
+    }
+    /// Reads more data from the pipe into the internal buffer, returning
+    /// how much was added.
+    async fn fill_buf(&mut self) -> io::Result<usize> {
+        // Data only ever moves into `buf` within a single poll, so dropping
+        // this future in between can't lose anything.
+        future::poll_fn(|cx| {
+            let mut chunk = [0; READ_CHUNK];
+            let n = task::ready!(self.poll_read_pipe(cx, |mut file| file.read(&mut chunk)))?;
+            self.buf.extend_from_slice(&chunk[..n]);
+            Poll::Ready(Ok(n))
+        })
+        .await
+    }
+    /// Polls until a read on the pipe would make progress, without reading
+    /// anything.
+    ///
+    /// The pipe is ready when it has data, when the last writer closed it,
+    /// or when data is buffered in this handle. Readiness can be spurious,
+    /// so a read afterwards may still return `Pending`. Use this to build
+    /// custom futures; [`readable`](#method.readable) is the async version.
+    pub fn poll_read_ready(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if !self.buf.is_empty() {
+            return Poll::Ready(Ok(()));
+        }
+        if !self.attached {
+            return self.poll_attached(cx);
+        }
+        poll_wait(&self.inner, &mut self.ready, Async::readable_owned, cx)
+    }
+    /// Waits until a read on the pipe would make progress, without reading
+    /// anything.
+    ///
+    /// See [`poll_read_ready`](#method.poll_read_ready) for details.
+    pub async fn readable(&mut self) -> io::Result<()> {
+        future::poll_fn(|cx| self.poll_read_ready(cx)).await
+    }
+    /// Waits until the last writer closed the pipe.
+    ///
+    /// Unlike an empty read, this doesn't consume anything, so whatever is
+    /// still in the pipe can be read afterwards. The pipe only counts as
+    /// closed once a writer attached and went away again, and never if it
+    /// was opened with `hold_open`, since this handle is a writer then. It
+    /// stays closed until another writer attaches.
+    pub async fn closed(&self) -> io::Result<()> {
+        hangup(self.as_raw_fd()).await
+    }
+    /// Waits for the first writer to attach to the pipe.
+    fn poll_attached(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        // A non-blocking read on a pipe without writers reports EOF right
+        // away, so wait for the first writer to show up before reading.
+        // (Linux doesn't signal a hangup for writers that attached before
+        // this end was opened, so the pipe only turns readable once a writer
+        // sends data or closes.)
+        if !self.attached {
+            task::ready!(poll_wait(
+                &self.inner,
+                &mut self.ready,
+                Async::readable_owned,
+                cx
+            ))?;
+            self.attached = true;
+            self.probe.peer_attached("read");
+        }
+        Poll::Ready(Ok(()))
+    }
+    /// Reads from the pipe once a writer is attached, waiting for data if
+    /// there is none yet.
+    fn poll_read_pipe(
+        &mut self,
+        cx: &mut Context<'_>,
+        read: impl FnMut(&File) -> io::Result<usize>,
+    ) -> Poll<io::Result<usize>> {
+        task::ready!(self.poll_attached(cx))?;
+        let n = task::ready!(poll_io(
+            &self.inner,
+            &mut self.ready,
+            Async::readable_owned,
+            cx,
+            read
+        ))?;
+        self.probe.bytes_read(n);
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl OpenReader {
+    /// Checks whether the pipe is closed automatically when the process
+    /// execs another program (`FD_CLOEXEC`).
+    ///
+    /// Pipes are always opened with `O_CLOEXEC`, so this is `true` unless
+    /// changed with [`set_cloexec`](#method.set_cloexec).
+    pub fn is_cloexec(&self) -> io::Result<bool> {
+        is_cloexec(self.as_raw_fd())
+    }
+    /// Sets whether the pipe is closed automatically when the process
+    /// execs another program.
+    ///
+    /// Clearing this leaks the pipe into *every* program spawned afterwards;
+    /// to pass it to a single child, use
+    /// [`coprocess::inherit_pipe`](coprocess/fn.inherit_pipe.html) instead.
+    pub fn set_cloexec(&self, cloexec: bool) -> io::Result<()> {
+        set_cloexec(self.as_raw_fd(), cloexec)
+    }
+    /// Converts this handle into a `Stdio` for a child process.
+    ///
+    /// The pipe is switched back to blocking mode, which is what most
+    /// programs expect on their standard streams. Works with
+    /// `std::process::Command` as well as `async_std::process::Command`.
+    ///
+    /// A child reading from a pipe without a writer sees EOF immediately,
+    /// so open the writing end before spawning the child:
+    ///
+    /// ```no_run
+    /// # fn main() -> async_std::io::Result<()> { async_std::task::block_on(async {
+    /// use async_std::prelude::*;
+    /// use std::process::Command;
+    /// use unix_fifo_async::NamedPipePath;
+    ///
+    /// let pipe = NamedPipePath::new("./ffmpeg_input");
+    /// pipe.ensure_exists().unwrap();
+    /// let stdin = pipe.open_read().open().await?.into_stdio()?;
+    /// // There's a reader now, so this doesn't wait.
+    /// let mut writer = pipe.open_write().open().await?;
+    /// let child = Command::new("ffmpeg").args(&["-i", "-"]).stdin(stdin).spawn()?;
+    /// writer.write_all(b"...").await?;
+    /// # Ok(())
+    /// # })}
+    /// ```
+    ///
+    /// Data buffered by [`read_exact`](#method.read_exact) or
+    /// [`read_until`](#method.read_until) is lost.
+    pub fn into_stdio(self) -> io::Result<Stdio> {
+        drop(self.ready);
+        into_stdio(into_async(self.inner))
+    }
+}
+
+impl AsRawFd for OpenReader {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
+
+impl IntoRawFd for OpenReader {
+    /// Deregisters the file descriptor from the reactor and hands it over.
+    ///
+    /// The descriptor stays in non-blocking mode.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the reactor fails to deregister the descriptor.
+    fn into_raw_fd(self) -> RawFd {
+        drop(self.ready);
+        into_raw_fd(into_async(self.inner))
+    }
+}
+
+impl io::Read for OpenReader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if !this.buf.is_empty() {
+            let n = this.buf.len().min(buf.len());
+            buf[..n].copy_from_slice(&this.buf[..n]);
+            this.buf.drain(..n);
+            return Poll::Ready(Ok(n));
+        }
+        this.poll_read_pipe(cx, |mut file| file.read(buf))
+    }
+    fn poll_read_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &mut [io::IoSliceMut<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if !this.buf.is_empty() {
+            let mut n = 0;
+            for buf in bufs.iter_mut() {
+                let len = (this.buf.len() - n).min(buf.len());
+                buf[..len].copy_from_slice(&this.buf[n..n + len]);
+                n += len;
+            }
+            this.buf.drain(..n);
+            return Poll::Ready(Ok(n));
+        }
+        this.poll_read_pipe(cx, |mut file| file.read_vectored(bufs))
+    }
+}
+
+/// The writing end of a named pipe, opened in non-blocking mode.
+///
+/// The file descriptor is registered with the async reactor, so waiting for
+/// the reader to make room in the pipe doesn't occupy any thread.
+pub struct OpenWriter {
+    inner: Arc<Async<File>>,
+    /// The wait for the pipe to turn writable, kept across polls.
+    ready: Option<WritableOwned<File>>,
+    pub(crate) probe: Probe,
+}
+
+impl OpenWriter {
+    /// Opens the writing end of `pipe`.
+    ///
+    /// The pipe can't be opened for writing without a reader and there's no
+    /// way to get notified when one shows up, so this retries with a short
+    /// backoff until a reader is present.
+    pub(crate) async fn open(pipe: &NamedPipePath) -> io::Result<Self> {
+        let start = Instant::now();
+        let mut delay = OPEN_RETRY_MIN;
+        loop {
+            match pipe.open_file(OFlag::O_WRONLY | OFlag::O_NONBLOCK) {
+                Ok(file) => {
+                    let probe = Probe::new(pipe.as_path());
+                    probe.opened("write", start.elapsed());
+                    if delay > OPEN_RETRY_MIN {
+                        probe.peer_waited("write", start.elapsed());
+                    }
+                    return Ok(Self {
+                        inner: Arc::new(Async::new(file)?),
+                        ready: None,
+                        probe,
+                    });
+                }
+                Err(e) if e.raw_os_error() == Some(libc::ENXIO) => {
+                    task::sleep(delay).await;
+                    delay = (delay * 2).min(OPEN_RETRY_MAX);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+    /// Adopts an open file descriptor as the writing end of a pipe.
+    ///
+    /// The descriptor is switched to non-blocking mode and registered with
+    /// the async reactor. It doesn't have to belong to a named pipe; the
+    /// write end of an anonymous pipe works just as well.
+    ///
+    /// # Safety
+    ///
+    /// `fd` must be an open file descriptor that isn't owned by anything
+    /// else; the returned handle closes it on drop.
+    pub unsafe fn from_raw_fd(fd: RawFd) -> io::Result<Self> {
+        Ok(Self {
+            inner: Arc::new(Async::new(File::from_raw_fd(fd))?),
+            ready: None,
+            probe: Probe::default(),
+        })
+    }
+    /// Polls until a write to the pipe would make progress, without writing
+    /// anything.
+    ///
+    /// The pipe is ready when it has room for more data, or when the reader
+    /// closed it, in which case the next write fails. Readiness can be
+    /// spurious, so a write afterwards may still return `Pending`. Use this
+    /// to build custom futures; [`writable`](#method.writable) is the async
+    /// version.
+    pub fn poll_write_ready(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        poll_wait(&self.inner, &mut self.ready, Async::writable_owned, cx)
+    }
+    /// Waits until a write to the pipe would make progress, without writing
+    /// anything.
+    ///
+    /// See [`poll_write_ready`](#method.poll_write_ready) for details.
+    pub async fn writable(&self) -> io::Result<()> {
+        self.inner.writable().await
+    }
+    /// Waits until the reader closed the pipe, after which every write
+    /// fails with `BrokenPipe`.
+    ///
+    /// Unlike a failed write, this notices the reader going away even while
+    /// there's nothing to send, e.g. to start reconnecting right away.
+    pub async fn closed(&self) -> io::Result<()> {
+        hangup(self.as_raw_fd()).await
+    }
+    /// Returns how many bytes were written to the pipe, by any writer, and
+    /// haven't been read yet.
+    ///
+    /// This is how far the reader is behind, e.g. to pick the least busy of
+    /// several pipes.
+    pub fn bytes_available(&self) -> io::Result<usize> {
+        bytes_available(self.as_raw_fd())
+    }
+    /// Writes all of the given buffers to the pipe, in order.
+    ///
+    /// Uses vectored writes, so e.g. a header and its payload usually end up
+    /// in the pipe with a single syscall, without concatenating them first.
+    pub async fn write_all_vectored(&mut self, mut bufs: &mut [io::IoSlice<'_>]) -> io::Result<()> {
+        io::IoSlice::advance_slices(&mut bufs, 0);
+        while !bufs.is_empty() {
+            match self.write_vectored(bufs).await {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => io::IoSlice::advance_slices(&mut bufs, n),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl OpenWriter {
+    /// Checks whether the pipe is closed automatically when the process
+    /// execs another program (`FD_CLOEXEC`).
+    ///
+    /// Pipes are always opened with `O_CLOEXEC`, so this is `true` unless
+    /// changed with [`set_cloexec`](#method.set_cloexec).
+    pub fn is_cloexec(&self) -> io::Result<bool> {
+        is_cloexec(self.as_raw_fd())
+    }
+    /// Sets whether the pipe is closed automatically when the process
+    /// execs another program.
+    ///
+    /// Clearing this leaks the pipe into *every* program spawned afterwards;
+    /// to pass it to a single child, use
+    /// [`coprocess::inherit_pipe`](coprocess/fn.inherit_pipe.html) instead.
+    pub fn set_cloexec(&self, cloexec: bool) -> io::Result<()> {
+        set_cloexec(self.as_raw_fd(), cloexec)
+    }
+    /// Converts this handle into a `Stdio` for a child process.
+    ///
+    /// The pipe is switched back to blocking mode, which is what most
+    /// programs expect on their standard streams. Works with
+    /// `std::process::Command` as well as `async_std::process::Command`.
+    pub fn into_stdio(self) -> io::Result<Stdio> {
+        drop(self.ready);
+        into_stdio(into_async(self.inner))
+    }
+    /// Writes to the pipe, waiting for room if it's full.
+    fn poll_write_pipe(
+        &mut self,
+        cx: &mut Context<'_>,
+        write: impl FnMut(&File) -> io::Result<usize>,
+    ) -> Poll<io::Result<usize>> {
+        let n = task::ready!(poll_io(
+            &self.inner,
+            &mut self.ready,
+            Async::writable_owned,
+            cx,
+            write
+        ))?;
+        self.probe.bytes_written(n);
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl AsRawFd for OpenWriter {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
+
+impl IntoRawFd for OpenWriter {
+    /// Deregisters the file descriptor from the reactor and hands it over.
+    ///
+    /// The descriptor stays in non-blocking mode.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the reactor fails to deregister the descriptor.
+    fn into_raw_fd(self) -> RawFd {
+        drop(self.ready);
+        into_raw_fd(into_async(self.inner))
+    }
+}
+
+/// Runs a non-blocking operation on a registered pipe, waiting for the
+/// reactor to report the pipe ready whenever the operation would block.
+fn poll_io<F, T>(
+    inner: &Arc<Async<File>>,
+    ready: &mut Option<F>,
+    wait: fn(Arc<Async<File>>) -> F,
+    cx: &mut Context<'_>,
+    mut op: impl FnMut(&File) -> io::Result<T>,
+) -> Poll<io::Result<T>>
+where
+    F: Future<Output = io::Result<()>> + Unpin,
+{
+    loop {
+        match op(inner.get_ref()) {
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                task::ready!(poll_wait(inner, ready, wait, cx))?;
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            result => {
+                *ready = None;
+                return Poll::Ready(result);
+            }
+        }
+    }
+}
+
+/// Polls until the reactor reports a registered pipe ready.
+///
+/// The wait is kept in `ready` until it completes. `Async::poll_readable`
+/// and `poll_writable` start over on every poll and only count events from
+/// reactor ticks that began afterwards, so while the reactor thread keeps
+/// ticking, they can miss a pipe that's been ready all along and wake the
+/// task forever without ever returning.
+fn poll_wait<F>(
+    inner: &Arc<Async<File>>,
+    ready: &mut Option<F>,
+    wait: fn(Arc<Async<File>>) -> F,
+    cx: &mut Context<'_>,
+) -> Poll<io::Result<()>>
+where
+    F: Future<Output = io::Result<()>> + Unpin,
+{
+    let pending = ready.get_or_insert_with(|| wait(inner.clone()));
+    let result = task::ready!(Pin::new(pending).poll(cx));
+    *ready = None;
+    Poll::Ready(result)
+}
+
+/// Takes the registered pipe out of a handle whose pending wait is gone.
+fn into_async(inner: Arc<Async<File>>) -> Async<File> {
+    match Arc::try_unwrap(inner) {
+        Ok(inner) => inner,
+        Err(_) => panic!("pipe still referenced by a pending wait"),
+    }
+}
+
+fn into_raw_fd(inner: Async<File>) -> RawFd {
+    inner
+        .into_inner()
+        .expect("failed to deregister pipe from the reactor")
+        .into_raw_fd()
+}
+
+fn is_cloexec(fd: RawFd) -> io::Result<bool> {
+    let flags = fcntl(fd, FcntlArg::F_GETFD).map_err(nix_to_io)?;
+    Ok(FdFlag::from_bits_truncate(flags).contains(FdFlag::FD_CLOEXEC))
+}
+
+fn set_cloexec(fd: RawFd, cloexec: bool) -> io::Result<()> {
+    let mut flags = FdFlag::from_bits_truncate(fcntl(fd, FcntlArg::F_GETFD).map_err(nix_to_io)?);
+    flags.set(FdFlag::FD_CLOEXEC, cloexec);
+    fcntl(fd, FcntlArg::F_SETFD(flags))
+        .map(drop)
+        .map_err(nix_to_io)
+}
+
+/// Turns a registered pipe end into a blocking `Stdio` for a child process.
+fn into_stdio(inner: Async<File>) -> io::Result<Stdio> {
+    let file = inner.into_inner()?;
+    let flags = fcntl(file.as_raw_fd(), FcntlArg::F_GETFL).map_err(nix_to_io)?;
+    let flags = OFlag::from_bits_truncate(flags) & !OFlag::O_NONBLOCK;
+    fcntl(file.as_raw_fd(), FcntlArg::F_SETFL(flags)).map_err(nix_to_io)?;
+    Ok(Stdio::from(file))
+}
+
+impl io::Write for OpenWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut()
+            .poll_write_pipe(cx, |mut file| file.write(buf))
+    }
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut()
+            .poll_write_pipe(cx, |mut file| file.write_vectored(bufs))
+    }
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(self.inner.get_ref().flush())
+    }
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_flush(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{OpenReader, OpenWriter};
+    use crate::NamedPipePath;
+    use async_std::{io, prelude::*, task};
+    use std::time::Duration;
+    #[test]
+    fn writer_waits_for_reader() -> io::Result<()> {
+        task::block_on(async {
+            let pipe = NamedPipePath::new("./test_pipe_8");
+            pipe.ensure_exists().unwrap();
+            let write_pipe = pipe.clone();
+            let t_write = task::spawn(async move {
+                let mut writer = OpenWriter::open(&write_pipe).await?;
+                writer.write_all(b"Hello pipe").await
+            });
+            task::sleep(Duration::from_millis(20)).await;
+            let mut reader = OpenReader::open(&pipe, false)?;
+            let mut read_result = String::new();
+            reader.read_to_string(&mut read_result).await?;
+            t_write.await?;
+            assert_eq!(read_result, "Hello pipe");
+            pipe.delete().await
+        })
+    }
+    #[test]
+    fn reader_waits_for_writer() -> io::Result<()> {
+        task::block_on(async {
+            let pipe = NamedPipePath::new("./test_pipe_9");
+            pipe.ensure_exists().unwrap();
+            let mut reader = OpenReader::open(&pipe, false)?;
+            let t_read = task::spawn(async move {
+                let mut read_result = String::new();
+                reader.read_to_string(&mut read_result).await?;
+                io::Result::Ok(read_result)
+            });
+            task::sleep(Duration::from_millis(20)).await;
+            let mut writer = OpenWriter::open(&pipe).await?;
+            writer.write_all(b"Hello pipe").await?;
+            drop(writer);
+            assert_eq!(t_read.await?, "Hello pipe");
+            pipe.delete().await
+        })
+    }
+    #[test]
+    fn vectored_io() -> io::Result<()> {
+        task::block_on(async {
+            let pipe = NamedPipePath::new("./test_pipe_10");
+            pipe.ensure_exists().unwrap();
+            let mut reader = OpenReader::open(&pipe, false)?;
+            let write_pipe = pipe.clone();
+            let t_write = task::spawn(async move {
+                let mut writer = OpenWriter::open(&write_pipe).await?;
+                let mut bufs = [io::IoSlice::new(b"head"), io::IoSlice::new(b"payload")];
+                writer.write_all_vectored(&mut bufs).await
+            });
+            let mut read_result = Vec::new();
+            reader.read_to_end(&mut read_result).await?;
+            t_write.await?;
+            assert_eq!(read_result, b"headpayload");
+            pipe.delete().await
+        })
+    }
+    #[test]
+    fn raw_fd_round_trip() -> io::Result<()> {
+        use std::os::unix::io::{AsRawFd, IntoRawFd};
+        task::block_on(async {
+            let (read_fd, write_fd) = nix::unistd::pipe().unwrap();
+            let reader = unsafe { OpenReader::from_raw_fd(read_fd)? };
+            let writer = unsafe { OpenWriter::from_raw_fd(write_fd)? };
+            assert_eq!(reader.as_raw_fd(), read_fd);
+            let mut reader = unsafe { OpenReader::from_raw_fd(reader.into_raw_fd())? };
+            let mut writer = unsafe { OpenWriter::from_raw_fd(writer.into_raw_fd())? };
+            writer.write_all(b"Hello pipe").await?;
+            drop(writer);
+            let mut read_result = String::new();
+            reader.read_to_string(&mut read_result).await?;
+            assert_eq!(read_result, "Hello pipe");
+            Ok(())
+        })
+    }
+    #[test]
+    fn child_stdin_from_pipe() -> io::Result<()> {
+        use std::process::{Command, Stdio};
+        task::block_on(async {
+            let pipe = NamedPipePath::new("./test_pipe_21");
+            pipe.ensure_exists().unwrap();
+            let stdin = OpenReader::open(&pipe, false)?.into_stdio()?;
+            let mut writer = OpenWriter::open(&pipe).await?;
+            let child = Command::new("cat")
+                .stdin(stdin)
+                .stdout(Stdio::piped())
+                .spawn()?;
+            writer.write_all(b"Hello pipe").await?;
+            drop(writer);
+            let output = child.wait_with_output()?;
+            assert_eq!(output.stdout, b"Hello pipe");
+            pipe.delete().await
+        })
+    }
+    #[test]
+    fn read_at_boundaries() -> io::Result<()> {
+        task::block_on(async {
+            let (read_fd, write_fd) = nix::unistd::pipe().unwrap();
+            let mut reader = unsafe { OpenReader::from_raw_fd(read_fd)? };
+            let mut writer = unsafe { OpenWriter::from_raw_fd(write_fd)? };
+            writer.write_all(b"\x00\x05hello\nworld\nrest").await?;
+            drop(writer);
+            assert_eq!(reader.read_exact(2).await?, b"\x00\x05");
+            assert_eq!(reader.read_until(b'\n').await?, b"hello\n");
+            assert_eq!(reader.read_until(b'\n').await?, b"world\n");
+            let mut buf = [0; 2];
+            reader.read(&mut buf).await?;
+            assert_eq!(&buf, b"re");
+            let err = reader.read_exact(3).await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+            assert_eq!(reader.read_until(b'\n').await?, b"st");
+            assert!(reader.read_until(b'\n').await?.is_empty());
+            Ok(())
+        })
+    }
+    #[test]
+    fn max_message_size() -> io::Result<()> {
+        use crate::error::MessageTooLarge;
+        task::block_on(async {
+            let (read_fd, write_fd) = nix::unistd::pipe().unwrap();
+            let reader = unsafe { OpenReader::from_raw_fd(read_fd)? };
+            let mut reader = reader.max_message_size(4);
+            let mut writer = unsafe { OpenWriter::from_raw_fd(write_fd)? };
+            writer.write_all(b"abc\ntoo long").await?;
+            drop(writer);
+            assert_eq!(reader.read_until(b'\n').await?, b"abc\n");
+            let too_large = |err: io::Error| {
+                let err = err.get_ref().unwrap().downcast_ref::<MessageTooLarge>();
+                assert_eq!(err, Some(&MessageTooLarge { limit: 4 }));
+            };
+            too_large(reader.read_until(b'\n').await.unwrap_err());
+            too_large(reader.read_exact(5).await.unwrap_err());
+            assert_eq!(reader.read_exact(4).await?, b"too ");
+            assert_eq!(reader.read_until(b'\n').await?, b"long");
+            Ok(())
+        })
+    }
+    #[test]
+    fn cancelled_read_keeps_data() -> io::Result<()> {
+        task::block_on(async {
+            let (read_fd, write_fd) = nix::unistd::pipe().unwrap();
+            let mut reader = unsafe { OpenReader::from_raw_fd(read_fd)? };
+            let mut writer = unsafe { OpenWriter::from_raw_fd(write_fd)? };
+            writer.write_all(b"Hello ").await?;
+            // Times out waiting for the rest, dropping the read
+            let timeout = Duration::from_millis(20);
+            assert!(io::timeout(timeout, reader.read_exact(10)).await.is_err());
+            writer.write_all(b"pipe").await?;
+            assert_eq!(reader.read_exact(10).await?, b"Hello pipe");
+            Ok(())
+        })
+    }
+    #[test]
+    fn readiness() -> io::Result<()> {
+        task::block_on(async {
+            let (read_fd, write_fd) = nix::unistd::pipe().unwrap();
+            let mut reader = unsafe { OpenReader::from_raw_fd(read_fd)? };
+            let mut writer = unsafe { OpenWriter::from_raw_fd(write_fd)? };
+            writer.writable().await?;
+            let timeout = Duration::from_millis(20);
+            assert!(io::timeout(timeout, reader.readable()).await.is_err());
+            writer.write_all(b"Hello pipe").await?;
+            reader.readable().await?;
+            assert_eq!(reader.read_exact(5).await?, b"Hello");
+            // Buffered data counts as readable
+            reader.readable().await?;
+            Ok(())
+        })
+    }
+    #[test]
+    fn peer_closed() -> io::Result<()> {
+        task::block_on(async {
+            let (read_fd, write_fd) = nix::unistd::pipe().unwrap();
+            let mut reader = unsafe { OpenReader::from_raw_fd(read_fd)? };
+            let mut writer = unsafe { OpenWriter::from_raw_fd(write_fd)? };
+            writer.write_all(b"last words").await?;
+            let timeout = Duration::from_millis(20);
+            assert!(io::timeout(timeout, reader.closed()).await.is_err());
+            drop(writer);
+            reader.closed().await?;
+            // Nothing was consumed
+            assert_eq!(reader.read_exact(10).await?, b"last words");
+
+            let (read_fd, write_fd) = nix::unistd::pipe().unwrap();
+            let reader = unsafe { OpenReader::from_raw_fd(read_fd)? };
+            let mut writer = unsafe { OpenWriter::from_raw_fd(write_fd)? };
+            let t_closed = task::spawn(async move {
+                writer.closed().await?;
+                writer.write_all(b"anyone?").await
+            });
+            task::sleep(timeout).await;
+            drop(reader);
+            let err = t_closed.await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+            Ok(())
+        })
+    }
+    #[test]
+    fn cloexec_flag() -> io::Result<()> {
+        let (read_fd, write_fd) = nix::unistd::pipe().unwrap();
+        let reader = unsafe { OpenReader::from_raw_fd(read_fd)? };
+        let writer = unsafe { OpenWriter::from_raw_fd(write_fd)? };
+        writer.set_cloexec(true)?;
+        assert!(writer.is_cloexec()?);
+        reader.set_cloexec(false)?;
+        assert!(!reader.is_cloexec()?);
+        Ok(())
+    }
+}
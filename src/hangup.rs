@@ -0,0 +1,86 @@
+use async_std::io;
+use std::os::unix::io::RawFd;
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+mod epoll {
+    use crate::util::nix_to_io;
+    use async_io::Async;
+    use async_std::io;
+    use nix::sys::epoll::{
+        epoll_create1, epoll_ctl, epoll_wait, EpollCreateFlags, EpollEvent, EpollFlags, EpollOp,
+    };
+    use std::os::unix::io::{AsRawFd, RawFd};
+
+    /// An epoll instance that's closed on drop.
+    struct Instance(RawFd);
+
+    impl AsRawFd for Instance {
+        fn as_raw_fd(&self) -> RawFd {
+            self.0
+        }
+    }
+
+    impl Drop for Instance {
+        fn drop(&mut self) {
+            let _ = nix::unistd::close(self.0);
+        }
+    }
+
+    pub(super) async fn hangup(fd: RawFd) -> io::Result<()> {
+        let instance = Instance(epoll_create1(EpollCreateFlags::EPOLL_CLOEXEC).map_err(nix_to_io)?);
+        // Hangups and errors are always reported, so no other events are
+        // needed
+        let mut event = EpollEvent::new(EpollFlags::empty(), 0);
+        epoll_ctl(instance.0, EpollOp::EpollCtlAdd, fd, &mut event).map_err(nix_to_io)?;
+        let instance = Async::new(instance)?;
+        let mut events = [EpollEvent::empty()];
+        loop {
+            if epoll_wait(instance.as_raw_fd(), &mut events, 0).map_err(nix_to_io)? > 0 {
+                return Ok(());
+            }
+            instance.readable().await?;
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "android", target_os = "linux")))]
+mod poll {
+    use crate::util::nix_to_io;
+    use async_std::{io, task};
+    use nix::poll::{poll, PollFd, PollFlags};
+    use std::os::unix::io::RawFd;
+    use std::time::Duration;
+
+    const POLL_MIN: Duration = Duration::from_millis(1);
+    const POLL_MAX: Duration = Duration::from_millis(100);
+
+    pub(super) async fn hangup(fd: RawFd) -> io::Result<()> {
+        let mut delay = POLL_MIN;
+        loop {
+            let mut fds = [PollFd::new(fd, PollFlags::empty())];
+            poll(&mut fds, 0).map_err(nix_to_io)?;
+            let closed = PollFlags::POLLHUP | PollFlags::POLLERR;
+            if fds[0].revents().map_or(false, |r| r.intersects(closed)) {
+                return Ok(());
+            }
+            task::sleep(delay).await;
+            delay = (delay * 2).min(POLL_MAX);
+        }
+    }
+}
+
+/// Waits until the pipe end `fd` reports a hangup (all writers are gone) or
+/// an error (the reader is gone).
+///
+/// Where there's no way to get notified about this, the pipe is checked
+/// with a short backoff instead.
+pub(crate) async fn hangup(fd: RawFd) -> io::Result<()> {
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    {
+        epoll::hangup(fd).await
+    }
+    #[cfg(not(any(target_os = "android", target_os = "linux")))]
+    {
+        poll::hangup(fd).await
+    }
+}
@@ -0,0 +1,298 @@
+//! Telling whether the process on the other end of a pipe is still alive.
+//!
+//! A [`Heartbeat`](struct.Heartbeat.html) sends a ping frame over a pipe at
+//! a fixed interval; a [`HeartbeatMonitor`](struct.HeartbeatMonitor.html)
+//! on the other end considers the sender dead once no frame arrived for a
+//! while.
+//!
+//! ```no_run
+//! # fn main() -> async_std::io::Result<()> { async_std::task::block_on(async {
+//! use async_std::prelude::*;
+//! use std::time::Duration;
+//! use unix_fifo_async::heartbeat::{HeartbeatMonitor, Liveness};
+//! use unix_fifo_async::NamedPipePath;
+//!
+//! // Meanwhile, the helper process runs
+//! // `Heartbeat::new("./helper_alive", Duration::from_secs(1)).spawn()`
+//! let monitor = HeartbeatMonitor::spawn(
+//!     NamedPipePath::new("./helper_alive").open_read(),
+//!     Duration::from_secs(3),
+//! );
+//! let mut transitions = monitor.transitions();
+//! while let Some(liveness) = transitions.next().await {
+//!     if liveness == Liveness::Dead {
+//!         eprintln!("helper stopped responding");
+//!     }
+//! }
+//! # Ok(())
+//! # })}
+//! ```
+use crate::{
+    frame::{FramedReader, FramedWriter},
+    util::is_transient,
+    NamedPipePath, NamedPipeReader, ShutdownToken,
+};
+use async_io::Timer;
+use async_std::{
+    io,
+    stream::Stream,
+    task::{self, JoinHandle},
+};
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+/// Delay before reopening the pipe after an error.
+const RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Periodically sends ping frames over a pipe.
+///
+/// The pipe is (re)opened whenever it has no reader, so the heartbeat
+/// survives the monitoring side restarting. Errors that reopening won't
+/// fix stop it; see
+/// [`HeartbeatHandle::shutdown`](struct.HeartbeatHandle.html#method.shutdown).
+pub struct Heartbeat {
+    pipe: NamedPipePath,
+    interval: Duration,
+    shutdown: ShutdownToken,
+}
+
+impl Heartbeat {
+    /// Creates a heartbeat sending a ping over the pipe at `path` every
+    /// `interval`.
+    pub fn new<P: Into<PathBuf>>(path: P, interval: Duration) -> Self {
+        Self {
+            pipe: NamedPipePath::new(path),
+            interval,
+            shutdown: ShutdownToken::new(),
+        }
+    }
+    /// Stops the heartbeat when `token` fires, in addition to
+    /// [`HeartbeatHandle::shutdown`](struct.HeartbeatHandle.html#method.shutdown).
+    pub fn with_shutdown(mut self, token: ShutdownToken) -> Self {
+        self.shutdown = token;
+        self
+    }
+    /// Starts sending pings in a new task.
+    pub fn spawn(self) -> HeartbeatHandle {
+        let shutdown = self.shutdown.clone();
+        let task = task::spawn(self.run());
+        HeartbeatHandle { shutdown, task }
+    }
+    async fn beat(&self) -> io::Result<()> {
+        let mut writer = FramedWriter::new(self.pipe.open_write().open().await?);
+        loop {
+            writer.ping().await?;
+            task::sleep(self.interval).await;
+        }
+    }
+    async fn run(self) -> io::Result<()> {
+        let stop = &self.shutdown;
+        // `beat` only returns on errors, e.g. the reader going away
+        while let Some(Err(e)) = stop.run_until(self.beat()).await {
+            if !is_transient(&e) {
+                return Err(e);
+            }
+            if stop.run_until(task::sleep(RETRY_DELAY)).await.is_none() {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A handle to a running [`Heartbeat`](struct.Heartbeat.html).
+pub struct HeartbeatHandle {
+    shutdown: ShutdownToken,
+    task: JoinHandle<io::Result<()>>,
+}
+
+impl HeartbeatHandle {
+    /// Stops sending pings.
+    ///
+    /// Returns the error the heartbeat stopped at before, if any.
+    pub async fn shutdown(self) -> io::Result<()> {
+        self.shutdown.shutdown();
+        self.task.await
+    }
+}
+
+/// Whether the other side of a monitored pipe is alive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Liveness {
+    /// A frame arrived within the timeout.
+    Alive,
+    /// No frame arrived within the timeout.
+    Dead,
+}
+
+#[derive(Default)]
+struct Shared {
+    last_seen: Mutex<Option<Instant>>,
+    wakers: Mutex<Vec<Waker>>,
+}
+
+impl Shared {
+    fn seen(&self) {
+        *self.last_seen.lock().unwrap() = Some(Instant::now());
+        for waker in self.wakers.lock().unwrap().drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+/// Watches a pipe for pings (or any other frames) in a background task.
+///
+/// Frames are consumed by the monitor, so the pipe should be dedicated to
+/// the heartbeat. The monitor stops when it's dropped.
+pub struct HeartbeatMonitor {
+    shared: Arc<Shared>,
+    timeout: Duration,
+    shutdown: ShutdownToken,
+}
+
+impl HeartbeatMonitor {
+    /// Starts reading frames from `source`, considering the writer dead
+    /// when nothing arrived for `timeout`.
+    pub fn spawn(source: NamedPipeReader, timeout: Duration) -> Self {
+        let shared = Arc::new(Shared::default());
+        let shutdown = ShutdownToken::new();
+        task::spawn(watch(source, shared.clone(), shutdown.clone()));
+        Self {
+            shared,
+            timeout,
+            shutdown,
+        }
+    }
+    /// Returns when the last frame arrived, if any did.
+    pub fn last_seen(&self) -> Option<Instant> {
+        *self.shared.last_seen.lock().unwrap()
+    }
+    /// Checks whether a frame arrived within the timeout.
+    pub fn is_alive(&self) -> bool {
+        self.liveness() == Liveness::Alive
+    }
+    fn liveness(&self) -> Liveness {
+        match self.last_seen() {
+            Some(seen) if seen.elapsed() < self.timeout => Liveness::Alive,
+            _ => Liveness::Dead,
+        }
+    }
+    /// Returns a stream that yields every time the liveness changes.
+    ///
+    /// The first item is the liveness at the time of the call.
+    pub fn transitions(&self) -> Transitions {
+        Transitions {
+            shared: self.shared.clone(),
+            timeout: self.timeout,
+            reported: None,
+            timer: None,
+        }
+    }
+}
+
+impl Drop for HeartbeatMonitor {
+    fn drop(&mut self) {
+        self.shutdown.shutdown();
+    }
+}
+
+async fn watch(source: NamedPipeReader, shared: Arc<Shared>, stop: ShutdownToken) {
+    // Keep reopening the pipe, as the writer may come and go
+    while let Some(result) = stop.run_until(read_frames(&source, &shared)).await {
+        if result.is_err() && stop.run_until(task::sleep(RETRY_DELAY)).await.is_none() {
+            return;
+        }
+    }
+}
+
+async fn read_frames(source: &NamedPipeReader, shared: &Shared) -> io::Result<()> {
+    let mut reader = FramedReader::new(source.open().await?);
+    while reader.recv_frame().await?.is_some() {
+        shared.seen();
+    }
+    Ok(())
+}
+
+/// The liveness transitions of a
+/// [`HeartbeatMonitor`](struct.HeartbeatMonitor.html).
+pub struct Transitions {
+    shared: Arc<Shared>,
+    timeout: Duration,
+    reported: Option<Liveness>,
+    timer: Option<Timer>,
+}
+
+impl Stream for Transitions {
+    type Item = Liveness;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Liveness>> {
+        let this = self.get_mut();
+        // Register first, so a frame arriving after the check below wakes us
+        let mut wakers = this.shared.wakers.lock().unwrap();
+        if !wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+            wakers.push(cx.waker().clone());
+        }
+        drop(wakers);
+        let deadline = this
+            .shared
+            .last_seen
+            .lock()
+            .unwrap()
+            .map(|seen| seen + this.timeout);
+        let now = match deadline {
+            Some(deadline) if deadline > Instant::now() => Liveness::Alive,
+            _ => Liveness::Dead,
+        };
+        if this.reported != Some(now) {
+            this.reported = Some(now);
+            this.timer = None;
+            return Poll::Ready(Some(now));
+        }
+        if let (Liveness::Alive, Some(deadline)) = (now, deadline) {
+            let timer = this.timer.get_or_insert_with(|| Timer::at(deadline));
+            timer.set_at(deadline);
+            if Pin::new(timer).poll(cx).is_ready() {
+                // The deadline passed in the meantime
+                cx.waker().wake_by_ref();
+            }
+        }
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Heartbeat, HeartbeatMonitor, Liveness};
+    use crate::NamedPipePath;
+    use async_std::{future, io, prelude::*, task};
+    use std::time::Duration;
+    #[test]
+    fn detect_liveness() -> io::Result<()> {
+        task::block_on(async {
+            let pipe = NamedPipePath::new("./test_pipe_34");
+            pipe.ensure_exists().unwrap();
+            let monitor = HeartbeatMonitor::spawn(pipe.open_read(), Duration::from_millis(200));
+            let mut transitions = monitor.transitions();
+            assert_eq!(transitions.next().await, Some(Liveness::Dead));
+            for _ in 0..3 {
+                let next = future::timeout(Duration::from_millis(1), transitions.next());
+                assert!(next.await.is_err());
+            }
+            // Polling again doesn't register the same task again
+            assert_eq!(monitor.shared.wakers.lock().unwrap().len(), 1);
+            let heartbeat = Heartbeat::new("./test_pipe_34", Duration::from_millis(50)).spawn();
+            assert_eq!(transitions.next().await, Some(Liveness::Alive));
+            assert!(monitor.is_alive());
+            assert!(monitor.last_seen().is_some());
+            heartbeat.shutdown().await?;
+            assert_eq!(transitions.next().await, Some(Liveness::Dead));
+            assert!(!monitor.is_alive());
+            drop(monitor);
+            pipe.delete().await
+        })
+    }
+}
@@ -5,6 +5,9 @@ Because of the way this works currently, there's no real way to get a
 lock on the pipe, but there are convenience methods on both `NamedPipePath`
 and `NamedPipeReader`/`NamedPipeWriter` to ensure the pipe exists.
 
+Pipes are opened in non-blocking mode and driven by the async reactor, so
+a task waiting for the other end of a pipe doesn't tie up a thread.
+
 # Example
 
 Create a pipe, write to it in one async task and read from it in another:
@@ -46,10 +49,72 @@ pipe.delete().await?;
 
 Note that in practice, you'll probably want to read the pipe from a different
 process or have it read by an entirely different program.
+
+# Optional features
+
+- `blocking`: synchronous `read_blocking`/`write_blocking` methods for
+  programs without an async runtime.
+- `bytes`: reading into `bytes::Bytes`/`bytes::BytesMut` buffers.
+- `cli`: the `fifo-send` and `fifo-recv` command line tools, for writing
+  stdin to a pipe and printing what's written to one.
+- `compression`: zstd compression for `frame::FramedWriter`.
+- `crypto`: encrypting frames with XChaCha20-Poly1305 and a pre-shared key.
+- `encoding`: decoding strings in encodings other than UTF-8 with
+  `encoding_rs`.
+- `metrics`: counters and histograms for traffic, open latency and time
+  spent waiting for the other end of each pipe, recorded through the
+  `metrics` facade and labelled with the pipe path.
+- `serde`: deserializing `config::PipeConfig`.
+- `signals`: firing `ShutdownToken`s on Unix signals and reads that give up
+  on a signal.
+- `tracing`: spans for opening, reading, writing and deleting pipes, and
+  events for the other end attaching and EOF.
 */
 
+mod buffered;
+mod fair;
+mod handle;
+mod hangup;
 mod named_pipe;
+mod probe;
+mod select;
+mod shared;
+mod shutdown;
+#[cfg(feature = "signals")]
+mod signals;
+mod stream;
+mod throttle;
+mod watch;
 
+pub mod actor;
+pub mod config;
+pub mod coprocess;
+#[cfg(feature = "crypto")]
+pub mod crypto;
+pub mod duplex;
+pub mod error;
+pub mod frame;
+pub mod heartbeat;
+pub mod mpsc;
+pub mod mux;
+pub mod pool;
+pub mod pubsub;
+pub mod record;
+pub mod relay;
+pub mod retry;
+pub mod serve;
+pub mod spool;
+pub mod systemd;
+pub mod transport;
 pub mod util;
+pub use buffered::{BufferedPipeReader, BufferedPipeWriter};
+pub use handle::{OpenReader, OpenWriter};
 pub use named_pipe::{NamedPipePath, NamedPipeReader, NamedPipeWriter};
+pub use select::PrioritySelect;
+pub use shared::{SharedReader, SharedReaderGuard};
+pub use shutdown::ShutdownToken;
+#[cfg(feature = "signals")]
+pub use signals::SignalTrigger;
+pub use stream::{Chunks, EofPolicy};
+pub use throttle::ThrottledWriter;
 pub use util::{create_pipe, remove_pipe};
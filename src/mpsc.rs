@@ -0,0 +1,202 @@
+//! Many producers sending whole messages to a single consumer over one pipe.
+//!
+//! Every [`Sender`](struct.Sender.html) writes chunked frames (see
+//! [`frame`](../frame/index.html)) that fit into a single atomic write, so
+//! messages from different producers can't corrupt each other, no matter
+//! how large they are. The [`Receiver`](struct.Receiver.html) puts them back
+//! together and keeps the pipe open while producers come and go.
+//!
+//! ```no_run
+//! # fn main() -> async_std::io::Result<()> { async_std::task::block_on(async {
+//! use unix_fifo_async::mpsc::{Receiver, Sender};
+//! use unix_fifo_async::NamedPipePath;
+//!
+//! let pipe = NamedPipePath::new("./events");
+//! pipe.ensure_exists().unwrap();
+//! // In the consumer:
+//! let mut receiver = Receiver::open(&pipe)?;
+//! // In every producer:
+//! let mut sender = Sender::open(&pipe).await?.embed_pid(true);
+//! sender.send(b"started").await?;
+//! // Back in the consumer:
+//! let message = receiver.recv().await?;
+//! println!("{:?} says {:?}", message.sender, message.data);
+//! # Ok(())
+//! # })}
+//! ```
+//!
+//! Each message starts with the process ID of its sender (`u32`,
+//! little-endian), which is 0 unless the sender embeds it.
+use crate::{
+    frame::{FramedReader, FramedWriter},
+    NamedPipePath,
+};
+use async_std::{io, stream::Stream, sync::Mutex};
+use std::convert::TryInto;
+use std::pin::Pin;
+use std::sync::Arc;
+
+/// Length of the sender ID at the start of every message.
+const SENDER_LEN: usize = 4;
+
+/// A message received by a [`Receiver`](struct.Receiver.html).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    /// The process ID of the sender, if it embedded it.
+    pub sender: Option<u32>,
+    /// The message itself.
+    pub data: Vec<u8>,
+}
+
+/// One of possibly many producers writing to a pipe.
+pub struct Sender {
+    writer: FramedWriter,
+    pid: u32,
+}
+
+impl Sender {
+    /// Opens the writing end of `pipe`, waiting for the consumer if it
+    /// hasn't opened the pipe yet.
+    pub async fn open(pipe: &NamedPipePath) -> io::Result<Self> {
+        let writer = pipe.open_write().open().await?;
+        Ok(Self {
+            writer: FramedWriter::new(writer).chunked(true),
+            pid: 0,
+        })
+    }
+    /// Includes the ID of this process in every message.
+    pub fn embed_pid(mut self, embed: bool) -> Self {
+        self.pid = if embed { std::process::id() } else { 0 };
+        self
+    }
+    /// Appends a CRC32 to every frame, so the consumer can detect
+    /// corruption.
+    pub fn checksum(mut self, checksum: bool) -> Self {
+        self.writer = self.writer.checksum(checksum);
+        self
+    }
+    /// Sends `message` to the consumer.
+    ///
+    /// Messages larger than `PIPE_BUF` are sent in several chunks, which
+    /// may be interleaved with other producers' messages.
+    pub async fn send(&mut self, message: &[u8]) -> io::Result<()> {
+        let mut envelope = Vec::with_capacity(SENDER_LEN + message.len());
+        envelope.extend_from_slice(&self.pid.to_le_bytes());
+        envelope.extend_from_slice(message);
+        self.writer.send(&envelope).await
+    }
+}
+
+/// The single consumer of a pipe written to by [`Sender`](struct.Sender.html)s.
+///
+/// The pipe is held open for writing as well, so the receiver doesn't see
+/// EOF when the last producer goes away, and new producers can open the
+/// pipe at any time.
+pub struct Receiver {
+    reader: FramedReader,
+}
+
+impl Receiver {
+    /// Opens the reading end of `pipe`.
+    pub fn open(pipe: &NamedPipePath) -> io::Result<Self> {
+        let reader = pipe.open_read().hold_open(true).open_sync()?;
+        Ok(Self {
+            reader: FramedReader::new(reader),
+        })
+    }
+    /// Rejects messages larger than `limit` bytes with an
+    /// [`error::MessageTooLarge`](../error/struct.MessageTooLarge.html).
+    pub fn max_message_size(mut self, limit: usize) -> Self {
+        self.reader = self
+            .reader
+            .max_message_size(limit.saturating_add(SENDER_LEN));
+        self
+    }
+    /// Receives the next message from any of the producers.
+    ///
+    /// This is cancel-safe, like
+    /// [`FramedReader::recv`](../frame/struct.FramedReader.html#method.recv).
+    pub async fn recv(&mut self) -> io::Result<Message> {
+        // The pipe is held open, so it never reports EOF
+        let mut data = self
+            .reader
+            .recv()
+            .await?
+            .ok_or(io::ErrorKind::UnexpectedEof)?;
+        if data.len() < SENDER_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "message without a sender ID",
+            ));
+        }
+        let pid = u32::from_le_bytes(data[..SENDER_LEN].try_into().unwrap());
+        data.drain(..SENDER_LEN);
+        Ok(Message {
+            sender: if pid == 0 { None } else { Some(pid) },
+            data,
+        })
+    }
+    /// Turns the receiver into a never-ending stream of messages.
+    pub fn into_stream(self) -> Pin<Box<dyn Stream<Item = io::Result<Message>> + Send>> {
+        // The stream only ever polls one of these futures at a time, so the
+        // lock is never contended.
+        let receiver = Arc::new(Mutex::new(self));
+        Box::pin(async_std::stream::from_fn(move || {
+            let receiver = receiver.clone();
+            async move { Some(receiver.lock().await.recv().await) }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Receiver, Sender};
+    use crate::NamedPipePath;
+    use async_std::{io, prelude::*, task};
+    #[test]
+    fn messages_from_many_senders() -> io::Result<()> {
+        task::block_on(async {
+            let pipe = NamedPipePath::new("./test_pipe_36");
+            pipe.ensure_exists().unwrap();
+            let mut receiver = Receiver::open(&pipe)?;
+            let big = vec![b'x'; 3 * nix::libc::PIPE_BUF];
+            let mut senders = Vec::new();
+            for i in 0..4u8 {
+                let pipe = pipe.clone();
+                let big = big.clone();
+                senders.push(task::spawn(async move {
+                    let mut sender = Sender::open(&pipe).await?.embed_pid(i % 2 == 0);
+                    sender.send(&[i]).await?;
+                    sender.send(&big).await
+                }));
+            }
+            let mut small = Vec::new();
+            for _ in 0..8 {
+                let message = receiver.recv().await?;
+                if message.data.len() == 1 {
+                    let i = message.data[0];
+                    let expected = if i % 2 == 0 {
+                        Some(std::process::id())
+                    } else {
+                        None
+                    };
+                    assert_eq!(message.sender, expected);
+                    small.push(i);
+                } else {
+                    assert_eq!(message.data, big);
+                }
+            }
+            for sender in senders {
+                sender.await?;
+            }
+            small.sort_unstable();
+            assert_eq!(small, [0, 1, 2, 3]);
+
+            let mut sender = Sender::open(&pipe).await?;
+            sender.send(b"streamed").await?;
+            let mut messages = receiver.into_stream();
+            assert_eq!(messages.next().await.unwrap()?.data, b"streamed");
+            pipe.delete().await
+        })
+    }
+}
@@ -0,0 +1,468 @@
+//! Several independent channels over a single pipe.
+//!
+//! A [`Mux`](struct.Mux.html) hands out a [`Sender`](struct.Sender.html)
+//! per channel on the writing end of a pipe, and a
+//! [`Demux`](struct.Demux.html) hands out the matching
+//! [`Receiver`](struct.Receiver.html)s on the reading end:
+//!
+//! ```no_run
+//! # fn main() -> async_std::io::Result<()> { async_std::task::block_on(async {
+//! use unix_fifo_async::mux::{Demux, Mux};
+//! use unix_fifo_async::NamedPipePath;
+//!
+//! let pipe = NamedPipePath::new("./shared");
+//! let demux = Demux::spawn(pipe.open_read().open().await?);
+//! let mut logs = demux.channel(1).unwrap();
+//! let mut metrics = demux.channel(2).unwrap();
+//!
+//! // In the writing process:
+//! let mux = Mux::new(pipe.open_write().open().await?);
+//! mux.channel(1).unwrap().send(b"started").await?;
+//! mux.channel(2).unwrap().send(b"uptime=0").await?;
+//!
+//! assert_eq!(logs.recv().await?, Some(b"started".to_vec()));
+//! assert_eq!(metrics.recv().await?, Some(b"uptime=0".to_vec()));
+//! # Ok(())
+//! # })}
+//! ```
+//!
+//! Messages are cut into pieces that are sent as [frames](../frame/index.html),
+//! one piece at a time, so a large message on one channel doesn't hold up
+//! the others: senders take turns in the order they're ready. Each piece
+//! starts with the channel ID (`u16`, little-endian) and a byte that is 1
+//! for the last piece of a message and 0 otherwise. Frames are small enough
+//! to be written atomically, so several processes can share a pipe as long
+//! as they use different channels.
+use crate::{
+    error::MessageTooLarge,
+    fair::FairMutex,
+    frame::{self, FramedReader, FramedWriter},
+    OpenReader, OpenWriter, ShutdownToken,
+};
+use async_std::{future, io, task};
+use nix::libc::PIPE_BUF;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::convert::TryInto;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+/// Length of the channel header at the start of every piece.
+const PIECE_HEADER_LEN: usize = 3;
+/// Upper bound for the data in a single piece, so frames stay atomic.
+const MAX_PIECE: usize = PIPE_BUF - frame::HEADER_LEN - PIECE_HEADER_LEN;
+/// How many messages a channel queues up by default before the
+/// [`Demux`](struct.Demux.html) stops reading.
+const DEFAULT_MAX_QUEUED: usize = 64;
+/// The largest message a channel takes by default.
+const DEFAULT_MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+/// The state of a [`Mux`](struct.Mux.html), shared with its senders.
+struct MuxShared {
+    writer: FairMutex<FramedWriter>,
+    /// Channels that currently have a sender.
+    taken: Mutex<HashSet<u16>>,
+}
+
+/// The writing end of a pipe, split into channels.
+pub struct Mux {
+    shared: Arc<MuxShared>,
+}
+
+impl Mux {
+    /// Wraps the writing end of a pipe.
+    pub fn new(writer: OpenWriter) -> Self {
+        Self {
+            shared: Arc::new(MuxShared {
+                writer: FairMutex::new(FramedWriter::new(writer)),
+                taken: Mutex::new(HashSet::new()),
+            }),
+        }
+    }
+    /// Creates the sender for channel `id`.
+    ///
+    /// Returns `None` if the channel already has a sender; there can only
+    /// be one at a time, so messages on a channel can't get mixed up.
+    pub fn channel(&self, id: u16) -> Option<Sender> {
+        if !self.shared.taken.lock().unwrap().insert(id) {
+            return None;
+        }
+        Some(Sender {
+            id,
+            shared: self.shared.clone(),
+        })
+    }
+}
+
+/// Sends messages on one channel of a [`Mux`](struct.Mux.html).
+///
+/// The channel becomes available again when this is dropped.
+pub struct Sender {
+    id: u16,
+    shared: Arc<MuxShared>,
+}
+
+impl Sender {
+    /// Returns the ID of the channel.
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+    /// Sends `message` on this channel.
+    ///
+    /// This isn't cancel-safe: dropping the future after part of the
+    /// message went out corrupts the next message on this channel.
+    pub async fn send(&mut self, message: &[u8]) -> io::Result<()> {
+        let mut pieces = message.chunks(MAX_PIECE).peekable();
+        let mut frame = Vec::with_capacity(PIECE_HEADER_LEN + MAX_PIECE.min(message.len()));
+        loop {
+            // An empty message is sent as a single empty piece
+            let piece = pieces.next().unwrap_or(&[]);
+            let last = pieces.peek().is_none();
+            frame.clear();
+            frame.extend_from_slice(&self.id.to_le_bytes());
+            frame.push(last as u8);
+            frame.extend_from_slice(piece);
+            // Lock for every piece, so other channels get their turn
+            self.shared.writer.lock().await.send(&frame).await?;
+            if last {
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl Drop for Sender {
+    fn drop(&mut self) {
+        self.shared.taken.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// A channel that has a receiver.
+#[derive(Default)]
+struct Channel {
+    messages: VecDeque<io::Result<Vec<u8>>>,
+    /// The pieces of the message that's currently coming in.
+    partial: Vec<u8>,
+    /// Set while dropping the rest of a message, because it's too large or
+    /// started before the receiver was created.
+    skipping: bool,
+    waker: Option<Waker>,
+}
+
+/// How the pipe feeding a [`Demux`](struct.Demux.html) ended.
+enum End {
+    Eof,
+    Error(io::ErrorKind, String),
+}
+
+struct DemuxState {
+    channels: HashMap<u16, Channel>,
+    /// Channels without a receiver that are in the middle of a message.
+    unfinished: HashSet<u16>,
+    max_queued: usize,
+    max_message_size: usize,
+    /// The background task, waiting for room in a channel's queue.
+    pump: Option<Waker>,
+    end: Option<End>,
+}
+
+impl DemuxState {
+    /// Hands a piece to its channel, once there's room for another message
+    /// in the channel's queue.
+    fn poll_dispatch(&mut self, piece: &[u8], cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if piece.len() < PIECE_HEADER_LEN {
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "truncated channel header",
+            )));
+        }
+        let id = u16::from_le_bytes(piece[..2].try_into().unwrap());
+        let last = piece[2] != 0;
+        let data = &piece[PIECE_HEADER_LEN..];
+        let channel = match self.channels.get_mut(&id) {
+            Some(channel) => channel,
+            None => {
+                // Nobody is listening, so drop it
+                if last {
+                    self.unfinished.remove(&id);
+                } else {
+                    self.unfinished.insert(id);
+                }
+                return Poll::Ready(Ok(()));
+            }
+        };
+        if channel.messages.len() >= self.max_queued {
+            self.pump = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        if channel.skipping {
+            channel.skipping = !last;
+            return Poll::Ready(Ok(()));
+        }
+        if channel.partial.len() + data.len() > self.max_message_size {
+            let limit = self.max_message_size;
+            channel.partial = Vec::new();
+            channel.skipping = !last;
+            channel
+                .messages
+                .push_back(Err(MessageTooLarge { limit }.into()));
+        } else {
+            channel.partial.extend_from_slice(data);
+            if !last {
+                return Poll::Ready(Ok(()));
+            }
+            let message = std::mem::take(&mut channel.partial);
+            channel.messages.push_back(Ok(message));
+        }
+        if let Some(waker) = channel.waker.take() {
+            waker.wake();
+        }
+        Poll::Ready(Ok(()))
+    }
+    fn finish(&mut self, end: End) {
+        self.end = Some(end);
+        for channel in self.channels.values_mut() {
+            if let Some(waker) = channel.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+/// The reading end of a pipe, split into channels.
+///
+/// Reads from the pipe in a background task and queues up messages per
+/// channel. Messages on channels that don't have a receiver are dropped,
+/// so create the receivers before anything is sent on them. Once a
+/// channel has [`max_queued`](#method.max_queued) messages waiting, the
+/// task stops reading until the receiver takes one, which holds up the
+/// other channels too. The task stops when the `Demux` is dropped, which
+/// ends all of its receivers.
+pub struct Demux {
+    state: Arc<Mutex<DemuxState>>,
+    shutdown: ShutdownToken,
+}
+
+impl Demux {
+    /// Starts reading from `reader`.
+    pub fn spawn(reader: OpenReader) -> Self {
+        let state = Arc::new(Mutex::new(DemuxState {
+            channels: HashMap::new(),
+            unfinished: HashSet::new(),
+            max_queued: DEFAULT_MAX_QUEUED,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            pump: None,
+            end: None,
+        }));
+        let shutdown = ShutdownToken::new();
+        task::spawn(pump(
+            FramedReader::new(reader),
+            state.clone(),
+            shutdown.clone(),
+        ));
+        Self { state, shutdown }
+    }
+    /// Sets how many messages each channel queues up before reading from
+    /// the pipe pauses. Defaults to 64.
+    pub fn max_queued(self, limit: usize) -> Self {
+        self.state.lock().unwrap().max_queued = limit.max(1);
+        self
+    }
+    /// Sets the largest message a channel takes, in bytes. Defaults to
+    /// 16 MiB.
+    ///
+    /// A larger message is dropped, and the receiver gets an
+    /// [`error::MessageTooLarge`](../error/struct.MessageTooLarge.html) in
+    /// its place.
+    pub fn max_message_size(self, limit: usize) -> Self {
+        self.state.lock().unwrap().max_message_size = limit;
+        self
+    }
+    /// Creates the receiver for channel `id`.
+    ///
+    /// Returns `None` if the channel already has a receiver. If a message
+    /// on the channel is coming in already, the receiver starts with the
+    /// next one.
+    pub fn channel(&self, id: u16) -> Option<Receiver> {
+        let mut state = self.state.lock().unwrap();
+        if state.channels.contains_key(&id) {
+            return None;
+        }
+        let skipping = state.unfinished.remove(&id);
+        state.channels.insert(
+            id,
+            Channel {
+                skipping,
+                ..Channel::default()
+            },
+        );
+        Some(Receiver {
+            id,
+            state: self.state.clone(),
+        })
+    }
+}
+
+impl Drop for Demux {
+    fn drop(&mut self) {
+        self.shutdown.shutdown();
+    }
+}
+
+async fn pump(mut reader: FramedReader, state: Arc<Mutex<DemuxState>>, stop: ShutdownToken) {
+    let result = stop
+        .run_until(read_pieces(&mut reader, &state))
+        .await
+        .unwrap_or(Ok(()));
+    let end = match result {
+        Ok(()) => End::Eof,
+        Err(e) => End::Error(e.kind(), e.to_string()),
+    };
+    state.lock().unwrap().finish(end);
+}
+
+async fn read_pieces(reader: &mut FramedReader, state: &Mutex<DemuxState>) -> io::Result<()> {
+    while let Some(piece) = reader.recv().await? {
+        future::poll_fn(|cx| state.lock().unwrap().poll_dispatch(&piece, cx)).await?;
+    }
+    Ok(())
+}
+
+/// Receives messages on one channel of a [`Demux`](struct.Demux.html).
+///
+/// The channel becomes available again when this is dropped.
+pub struct Receiver {
+    id: u16,
+    state: Arc<Mutex<DemuxState>>,
+}
+
+impl Receiver {
+    /// Returns the ID of the channel.
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+    /// Receives the next message on this channel.
+    ///
+    /// Returns `None` once the pipe reached EOF and all messages have been
+    /// received. If reading from the pipe failed, the error is returned
+    /// on every channel. A message larger than the
+    /// [limit](struct.Demux.html#method.max_message_size) is an
+    /// [`error::MessageTooLarge`](../error/struct.MessageTooLarge.html),
+    /// after which the next message can be received. This is cancel-safe.
+    pub async fn recv(&mut self) -> io::Result<Option<Vec<u8>>> {
+        future::poll_fn(|cx| {
+            let mut state = self.state.lock().unwrap();
+            let state = &mut *state;
+            let channel = state.channels.get_mut(&self.id).unwrap();
+            if let Some(message) = channel.messages.pop_front() {
+                if let Some(pump) = state.pump.take() {
+                    pump.wake();
+                }
+                return Poll::Ready(message.map(Some));
+            }
+            match &state.end {
+                Some(End::Eof) => Poll::Ready(Ok(None)),
+                Some(End::Error(kind, msg)) => Poll::Ready(Err(io::Error::new(*kind, msg.clone()))),
+                None => {
+                    channel.waker = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            }
+        })
+        .await
+    }
+}
+
+impl Drop for Receiver {
+    fn drop(&mut self) {
+        let mut state = self.state.lock().unwrap();
+        // Anything still queued is dropped along with the channel
+        if let Some(channel) = state.channels.remove(&self.id) {
+            if channel.skipping || !channel.partial.is_empty() {
+                state.unfinished.insert(self.id);
+            }
+        }
+        if let Some(pump) = state.pump.take() {
+            pump.wake();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Demux, Mux, MAX_PIECE};
+    use crate::{error::MessageTooLarge, OpenReader, OpenWriter};
+    use async_std::{io, task};
+    use std::time::Duration;
+    #[test]
+    fn channels_interleave() -> io::Result<()> {
+        task::block_on(async {
+            let (read_fd, write_fd) = nix::unistd::pipe().unwrap();
+            let reader = unsafe { OpenReader::from_raw_fd(read_fd)? };
+            let writer = unsafe { OpenWriter::from_raw_fd(write_fd)? };
+            let demux = Demux::spawn(reader);
+            let mut big_rx = demux.channel(1).unwrap();
+            let mut small_rx = demux.channel(2).unwrap();
+            assert!(demux.channel(2).is_none());
+
+            let mux = Mux::new(writer);
+            let mut big_tx = mux.channel(1).unwrap();
+            let mut small_tx = mux.channel(2).unwrap();
+            assert!(mux.channel(1).is_none());
+            let big = vec![b'x'; 100 * MAX_PIECE];
+            let t_big = task::spawn({
+                let big = big.clone();
+                async move { big_tx.send(&big).await }
+            });
+            // The small message doesn't wait for the whole big one
+            small_tx.send(b"").await?;
+            small_tx.send(b"small").await?;
+            assert_eq!(small_rx.recv().await?, Some(Vec::new()));
+            assert_eq!(small_rx.recv().await?, Some(b"small".to_vec()));
+            t_big.await?;
+            assert_eq!(big_rx.recv().await?, Some(big));
+            drop((mux, small_tx));
+            assert_eq!(big_rx.recv().await?, None);
+            Ok(())
+        })
+    }
+    #[test]
+    fn limits() -> io::Result<()> {
+        task::block_on(async {
+            let (read_fd, write_fd) = nix::unistd::pipe().unwrap();
+            let reader = unsafe { OpenReader::from_raw_fd(read_fd)? };
+            let writer = unsafe { OpenWriter::from_raw_fd(write_fd)? };
+            let demux = Demux::spawn(reader).max_queued(1).max_message_size(10);
+            let mut first_rx = demux.channel(1).unwrap();
+            let mut second_rx = demux.channel(2).unwrap();
+
+            let mux = Mux::new(writer);
+            let mut first_tx = mux.channel(1).unwrap();
+            let mut second_tx = mux.channel(2).unwrap();
+            let mut unknown_tx = mux.channel(3).unwrap();
+            // Nobody listens on channel 3, so this is dropped
+            unknown_tx.send(&[0; 4 * MAX_PIECE]).await?;
+            first_tx.send(b"way too large").await?;
+            first_tx.send(b"fits").await?;
+            second_tx.send(b"second").await?;
+            // Channel 1 has a message queued, so channel 2 waits for it
+            let waiting = io::timeout(Duration::from_millis(100), second_rx.recv()).await;
+            assert_eq!(waiting.unwrap_err().kind(), io::ErrorKind::TimedOut);
+            let err = first_rx.recv().await.unwrap_err();
+            assert_eq!(
+                err.into_inner()
+                    .unwrap()
+                    .downcast::<MessageTooLarge>()
+                    .unwrap()
+                    .limit,
+                10
+            );
+            assert_eq!(first_rx.recv().await?, Some(b"fits".to_vec()));
+            assert_eq!(second_rx.recv().await?, Some(b"second".to_vec()));
+            // A receiver created now doesn't see what was dropped
+            let mut unknown_rx = demux.channel(3).unwrap();
+            drop((mux, first_tx, second_tx, unknown_tx));
+            assert_eq!(unknown_rx.recv().await?, None);
+            Ok(())
+        })
+    }
+}
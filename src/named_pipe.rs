@@ -1,5 +1,91 @@
-use async_std::{fs, io};
-use std::path::PathBuf;
+use crate::{
+    error::{InsecurePipe, MessageTooLarge},
+    probe::{record_bytes, traced},
+    retry::{Retry, RetryPolicy},
+    util::{is_fifo, nix_to_io},
+    watch::DirWatch,
+    Chunks, EofPolicy, OpenReader, OpenWriter, ShutdownToken,
+};
+use async_std::{io, prelude::*, task};
+use nix::errno::Errno;
+use nix::fcntl::{self, AtFlags, FcntlArg, FdFlag, OFlag};
+use nix::sys::stat::{self, FchmodatFlags, FileStat, Mode, SFlag};
+use nix::unistd::{FchownatFlags, Gid, Uid};
+use std::fs::File;
+use std::os::unix::{
+    fs::DirBuilderExt,
+    io::{AsRawFd, FromRawFd},
+};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How long to wait before checking again if a pipe exists, when that can't
+/// be watched.
+const EXISTS_POLL_MIN: Duration = Duration::from_millis(1);
+/// Upper bound for the exponential backoff between checks.
+const EXISTS_POLL_MAX: Duration = Duration::from_millis(100);
+
+/// Finds the directory for per-user runtime files like pipes and sockets.
+fn runtime_dir() -> io::Result<PathBuf> {
+    if let Some(dir) = std::env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from) {
+        if dir.is_absolute() {
+            return Ok(dir);
+        }
+    }
+    let uid = nix::unistd::getuid();
+    let run_user = PathBuf::from(format!("/run/user/{}", uid));
+    if run_user.is_dir() {
+        return Ok(run_user);
+    }
+    // Anyone can create directories in the temporary directory, so make sure
+    // nobody else prepared this one for us.
+    let dir = std::env::temp_dir().join(format!("runtime-{}", uid));
+    match std::fs::DirBuilder::new().mode(0o700).create(&dir) {
+        Ok(()) => {}
+        Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+        Err(e) => return Err(e),
+    }
+    let stat = stat::lstat(&dir).map_err(nix_to_io)?;
+    let is_dir = SFlag::from_bits_truncate(stat.st_mode) & SFlag::S_IFMT == SFlag::S_IFDIR;
+    if !is_dir || stat.st_uid != uid.as_raw() || stat.st_mode & 0o077 != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{} isn't a private directory owned by us", dir.display()),
+        ));
+    }
+    Ok(dir)
+}
+
+/// Checks a pipe opened in strict mode.
+fn check_strict(stat: &FileStat, owner: Uid) -> Result<(), InsecurePipe> {
+    let kind = SFlag::from_bits_truncate(stat.st_mode) & SFlag::S_IFMT;
+    if kind == SFlag::S_IFLNK {
+        return Err(InsecurePipe::Symlink);
+    }
+    if !is_fifo(stat) {
+        return Err(InsecurePipe::NotAFifo);
+    }
+    if stat.st_uid != owner.as_raw() {
+        return Err(InsecurePipe::WrongOwner {
+            expected: owner,
+            actual: Uid::from_raw(stat.st_uid),
+        });
+    }
+    let mode = Mode::from_bits_truncate(stat.st_mode);
+    if mode.contains(Mode::S_IWOTH) {
+        return Err(InsecurePipe::WorldWritable { mode });
+    }
+    Ok(())
+}
+
+/// Makes sure a duplicated directory handle isn't leaked into child processes.
+fn set_cloexec(file: &File) -> io::Result<()> {
+    fcntl::fcntl(file.as_raw_fd(), FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))
+        .map(drop)
+        .map_err(nix_to_io)
+}
 
 /// Represents a path to a Unix named pipe (FIFO).
 ///
@@ -8,33 +94,379 @@ use std::path::PathBuf;
 #[derive(Clone)]
 pub struct NamedPipePath {
     inner: PathBuf,
+    dir: Option<Arc<File>>,
+    /// The user the pipe has to belong to, in strict mode.
+    strict_owner: Option<Uid>,
 }
 
 impl NamedPipePath {
     /// Wraps a given path in a `NamedPipePath`.
     pub fn new<T: Into<PathBuf>>(path: T) -> Self {
-        Self { inner: path.into() }
+        Self {
+            inner: path.into(),
+            dir: None,
+            strict_owner: None,
+        }
+    }
+    /// Wraps a path relative to an open directory in a `NamedPipePath`.
+    ///
+    /// All operations on the returned path go through `mkfifoat`, `openat`
+    /// and friends, so they keep working in sandboxes that don't allow
+    /// resolving absolute paths. `dir` is duplicated, so the caller can
+    /// close their own handle to it.
+    pub fn at<D: AsRawFd, T: Into<PathBuf>>(dir: &D, path: T) -> io::Result<Self> {
+        let fd = nix::unistd::dup(dir.as_raw_fd()).map_err(nix_to_io)?;
+        // Safety: `fd` was just created by `dup` and isn't owned by anything else.
+        let dir = unsafe { File::from_raw_fd(fd) };
+        set_cloexec(&dir)?;
+        Ok(Self {
+            inner: path.into(),
+            dir: Some(Arc::new(dir)),
+            strict_owner: None,
+        })
+    }
+    /// Resolves `path` against the user's runtime directory, creating any
+    /// missing parent directories with mode `0700`.
+    ///
+    /// The runtime directory is `$XDG_RUNTIME_DIR` if that is set to an
+    /// absolute path, then `/run/user/<uid>` if it exists, and otherwise a
+    /// private `runtime-<uid>` directory in the system's temporary directory.
+    /// The pipe itself isn't created; use `ensure_exists` for that.
+    ///
+    /// ```no_run
+    /// # fn main() -> async_std::io::Result<()> {
+    /// use unix_fifo_async::NamedPipePath;
+    ///
+    /// // e.g. /run/user/1000/myapp/control
+    /// let pipe = NamedPipePath::in_runtime_dir("myapp/control")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn in_runtime_dir<T: AsRef<Path>>(path: T) -> io::Result<Self> {
+        let path = runtime_dir()?.join(path);
+        if let Some(parent) = path.parent() {
+            std::fs::DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(parent)?;
+        }
+        Ok(Self::new(path))
+    }
+    /// Checks the pipe every time it's opened, for pipes in directories
+    /// other users can write to, like `/tmp`.
+    ///
+    /// Symbolic links aren't followed, and the pipe has to be a named pipe
+    /// that belongs to the current user and that not everyone can write
+    /// to; otherwise, opening it fails with an
+    /// [`error::InsecurePipe`](error/enum.InsecurePipe.html). The checks
+    /// are done on the opened pipe, so it can't be swapped out in between.
+    /// This only covers the pipe itself, not the directories leading to
+    /// it.
+    ///
+    /// The other operations don't follow symbolic links either:
+    /// [`ensure_exists`](#method.ensure_exists) counts a symlink as
+    /// existing (opening it fails then), [`delete`](#method.delete) and
+    /// [`replace_atomic`](#method.replace_atomic) remove or replace the
+    /// link itself, and [`set_owner`](#method.set_owner) only changes
+    /// pipes that pass the checks above.
+    pub fn strict(self) -> Self {
+        self.strict_owner(nix::unistd::geteuid())
+    }
+    /// Like [`strict`](#method.strict), but the pipe has to belong to
+    /// `owner`, e.g. the user of a service that creates it.
+    pub fn strict_owner(mut self, owner: Uid) -> Self {
+        self.strict_owner = Some(owner);
+        self
+    }
+    /// Returns the wrapped path.
+    ///
+    /// For paths created with [`at`](#method.at), this is relative to the
+    /// directory they were created with.
+    pub fn as_path(&self) -> &Path {
+        &self.inner
+    }
+    /// Opens the pipe with the given flags, relative to the directory if there is one.
+    pub(crate) fn open_file(&self, flags: OFlag) -> io::Result<File> {
+        let mut flags = flags | OFlag::O_CLOEXEC;
+        if let Some(owner) = self.strict_owner {
+            // Don't even open anything that isn't a pipe, e.g. a device
+            check_strict(&self.lstat().map_err(nix_to_io)?, owner)?;
+            flags |= OFlag::O_NOFOLLOW;
+        }
+        let fd = match &self.dir {
+            Some(dir) => fcntl::openat(dir.as_raw_fd(), &self.inner, flags, Mode::empty()),
+            None => fcntl::open(&self.inner, flags, Mode::empty()),
+        }
+        .map_err(|e| match e {
+            // Replaced by a symlink after the check above
+            nix::Error::Sys(Errno::ELOOP) if self.strict_owner.is_some() => {
+                InsecurePipe::Symlink.into()
+            }
+            e => nix_to_io(e),
+        })?;
+        // Safety: `fd` was just opened and isn't owned by anything else.
+        let file = unsafe { File::from_raw_fd(fd) };
+        if let Some(owner) = self.strict_owner {
+            check_strict(&stat::fstat(fd).map_err(nix_to_io)?, owner)?;
+        }
+        Ok(file)
     }
     /// Checks if the path exists.
     pub fn exists(&self) -> bool {
-        self.inner.exists()
+        match &self.dir {
+            Some(_) => self.stat().is_ok(),
+            None => self.inner.exists(),
+        }
+    }
+    /// Like [`exists`](#method.exists), but in strict mode a symlink counts
+    /// as existing, wherever it points.
+    fn exists_here(&self) -> bool {
+        match self.strict_owner {
+            Some(_) => self.lstat().is_ok(),
+            None => self.exists(),
+        }
+    }
+    /// Waits until something exists at the path, e.g. for another process
+    /// to create the pipe.
+    ///
+    /// Uses inotify to notice the pipe the moment it's created. Where that
+    /// isn't possible, like on other systems, for paths created with
+    /// [`at`](#method.at) or when the parent directory doesn't exist
+    /// either, this polls with an exponential backoff instead.
+    pub async fn wait_until_exists(&self) -> io::Result<()> {
+        if self.exists() {
+            return Ok(());
+        }
+        let watch = match self.inner.parent() {
+            Some(parent) if self.dir.is_none() => {
+                let parent = if parent.as_os_str().is_empty() {
+                    Path::new(".")
+                } else {
+                    parent
+                };
+                DirWatch::new(parent).ok()
+            }
+            _ => None,
+        };
+        let mut delay = EXISTS_POLL_MIN;
+        // Checked again after starting to watch, in case the pipe was
+        // created in between
+        while !self.exists() {
+            match &watch {
+                Some(watch) => watch.changed().await?,
+                None => {
+                    task::sleep(delay).await;
+                    delay = (delay * 2).min(EXISTS_POLL_MAX);
+                }
+            }
+        }
+        Ok(())
+    }
+    /// Calls `stat` on the path, relative to the directory if there is one.
+    pub(crate) fn stat(&self) -> nix::Result<FileStat> {
+        match &self.dir {
+            Some(dir) => stat::fstatat(dir.as_raw_fd(), &self.inner, AtFlags::empty()),
+            None => stat::stat(&self.inner),
+        }
+    }
+    /// Like [`stat`](#method.stat), but doesn't follow symlinks.
+    fn lstat(&self) -> nix::Result<FileStat> {
+        match &self.dir {
+            Some(dir) => stat::fstatat(dir.as_raw_fd(), &self.inner, AtFlags::AT_SYMLINK_NOFOLLOW),
+            None => stat::lstat(&self.inner),
+        }
+    }
+    /// Queries the metadata of the path with `stat`, following symlinks.
+    ///
+    /// This never opens the pipe, so it doesn't disturb its readers or
+    /// writers.
+    pub fn metadata(&self) -> io::Result<FileStat> {
+        self.stat().map_err(nix_to_io)
+    }
+    /// Checks if the path exists and is a named pipe.
+    pub fn is_fifo(&self) -> bool {
+        self.stat().map(|st| is_fifo(&st)).unwrap_or(false)
+    }
+    /// Queries the permission bits of the path, e.g. to refuse pipes other
+    /// users can write to.
+    pub fn permissions(&self) -> io::Result<Mode> {
+        let st = self.stat().map_err(nix_to_io)?;
+        Ok(Mode::from_bits_truncate(st.st_mode))
+    }
+    /// Queries the user and group owning the path.
+    pub fn owner(&self) -> io::Result<(Uid, Gid)> {
+        let st = self.stat().map_err(nix_to_io)?;
+        Ok((Uid::from_raw(st.st_uid), Gid::from_raw(st.st_gid)))
+    }
+    /// Changes the user and/or group owning the path, e.g. to hand a pipe
+    /// created by a privileged process to an unprivileged one.
+    ///
+    /// `None` leaves the respective owner unchanged. Look up IDs by name
+    /// with [`util::user_id`](util/fn.user_id.html) and
+    /// [`util::group_id`](util/fn.group_id.html).
+    ///
+    /// Symbolic links aren't followed; if the path is one, this fails with
+    /// [`InsecurePipe::Symlink`](error/enum.InsecurePipe.html) instead of
+    /// changing whatever it points to.
+    pub fn set_owner(&self, owner: Option<Uid>, group: Option<Gid>) -> io::Result<()> {
+        let stat = self.lstat().map_err(nix_to_io)?;
+        match self.strict_owner {
+            Some(strict_owner) => check_strict(&stat, strict_owner)?,
+            None if SFlag::from_bits_truncate(stat.st_mode) & SFlag::S_IFMT == SFlag::S_IFLNK => {
+                return Err(InsecurePipe::Symlink.into())
+            }
+            None => {}
+        }
+        // Should it turn into a symlink in the meantime, only the link
+        // itself changes owner
+        let dirfd = self.dir.as_ref().map(|dir| dir.as_raw_fd());
+        let flags = FchownatFlags::NoFollowSymlink;
+        nix::unistd::fchownat(dirfd, &self.inner, owner, group, flags).map_err(nix_to_io)
+    }
+    /// Sets the permission bits of the pipe, regardless of the umask.
+    ///
+    /// Symbolic links aren't followed; if the path is one, this fails
+    /// instead of changing whatever it points to.
+    pub(crate) fn set_mode(&self, mode: Mode) -> io::Result<()> {
+        let dirfd = self.dir.as_ref().map(|dir| dir.as_raw_fd());
+        stat::fchmodat(dirfd, &self.inner, mode, FchmodatFlags::NoFollowSymlink).map_err(nix_to_io)
     }
     /// Ensures the path exists, creating a named pipe in its place if it doesn't.
     pub fn ensure_exists(&self) -> nix::Result<()> {
-        if !self.exists() {
-            crate::create_pipe(&self.inner, None)
+        if !self.exists_here() {
+            self.create(None)
         } else {
             Ok(())
         }
     }
+    /// Like [`ensure_exists`](#method.ensure_exists), but also creates any
+    /// missing parent directories with the permission bits `dir_mode`
+    /// (minus the umask).
+    pub fn ensure_exists_with_parents(&self, dir_mode: Mode) -> io::Result<()> {
+        if self.exists_here() {
+            return Ok(());
+        }
+        self.create_parents(dir_mode)?;
+        self.create(None).map_err(nix_to_io)
+    }
+    /// Creates the missing parent directories of the path.
+    pub(crate) fn create_parents(&self, mode: Mode) -> io::Result<()> {
+        let parent = match self.inner.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => return Ok(()),
+        };
+        let dir = match &self.dir {
+            Some(dir) => dir,
+            None => {
+                return std::fs::DirBuilder::new()
+                    .recursive(true)
+                    .mode(mode.bits())
+                    .create(parent)
+            }
+        };
+        let mut path = PathBuf::new();
+        for component in parent.components() {
+            path.push(component);
+            match stat::mkdirat(dir.as_raw_fd(), &path, mode) {
+                Ok(()) | Err(nix::Error::Sys(Errno::EEXIST)) => {}
+                Err(e) => return Err(nix_to_io(e)),
+            }
+        }
+        Ok(())
+    }
+    /// Creates a named pipe at the path, relative to the directory if there is one.
+    fn create(&self, mode: Option<Mode>) -> nix::Result<()> {
+        match &self.dir {
+            Some(dir) => crate::util::create_pipe_at(dir.as_raw_fd(), &self.inner, mode),
+            None => crate::create_pipe(&self.inner, mode),
+        }
+    }
     /// Tries to delete the pipe from disk and consumes the `NamedPipe`.
     pub async fn delete(self) -> io::Result<()> {
-        if self.inner.exists() {
-            crate::remove_pipe(&self.inner).await
-        } else {
-            Ok(())
+        self.delete_in_place().await
+    }
+    /// Tries to delete the pipe from disk, keeping the `NamedPipePath`
+    /// around, e.g. to create the pipe again later.
+    pub async fn delete_in_place(&self) -> io::Result<()> {
+        traced("delete", &self.inner, self._delete()).await
+    }
+    async fn _delete(&self) -> io::Result<()> {
+        if !self.exists_here() {
+            return Ok(());
+        }
+        match &self.dir {
+            Some(dir) => nix::unistd::unlinkat(
+                Some(dir.as_raw_fd()),
+                &self.inner,
+                nix::unistd::UnlinkatFlags::NoRemoveDir,
+            )
+            .map_err(nix_to_io),
+            None => crate::remove_pipe(&self.inner).await,
         }
     }
+    /// Deletes the pipe and creates a fresh one in its place, e.g. to reset
+    /// a pipe that's wedged.
+    ///
+    /// Handles that already have the old pipe open keep using it, and won't
+    /// see anything written to the new one. Readers and writers like
+    /// `NamedPipeReader` open the path for every operation, so they pick up
+    /// the new pipe.
+    pub async fn recreate(&self) -> io::Result<()> {
+        self.delete_in_place().await?;
+        self.create(None).map_err(nix_to_io)
+    }
+    /// Atomically replaces whatever is at the path with a new named pipe
+    /// that has exactly the permission bits `mode`, regardless of the umask.
+    ///
+    /// The pipe is created under a temporary name in the same directory and
+    /// renamed over the path, so nobody ever sees the path missing or a
+    /// pipe with the wrong permissions. As with
+    /// [`recreate`](#method.recreate), handles that already have the old
+    /// pipe open keep using it.
+    pub async fn replace_atomic(&self, mode: Mode) -> io::Result<()> {
+        let tmp = self.temp_sibling()?;
+        let dirfd = self.dir.as_ref().map(|dir| dir.as_raw_fd());
+        // Start out readable only by us, so the mode can be set through an
+        // open handle rather than the guessable temporary name
+        let result = tmp
+            .create(Some(Mode::S_IRUSR))
+            .map_err(nix_to_io)
+            .and_then(|()| tmp.open_file(OFlag::O_RDONLY | OFlag::O_NONBLOCK | OFlag::O_NOFOLLOW))
+            .and_then(|file| {
+                if !is_fifo(&stat::fstat(file.as_raw_fd()).map_err(nix_to_io)?) {
+                    return Err(InsecurePipe::NotAFifo.into());
+                }
+                stat::fchmod(file.as_raw_fd(), mode).map_err(nix_to_io)
+            })
+            .and_then(|()| {
+                fcntl::renameat(dirfd, &tmp.inner, dirfd, &self.inner).map_err(nix_to_io)
+            });
+        if result.is_err() {
+            // Don't leave the temporary pipe behind
+            let _ = tmp._delete().await;
+        }
+        result
+    }
+    /// Returns a path in the same directory that nobody else uses.
+    fn temp_sibling(&self) -> io::Result<Self> {
+        static COUNTER: AtomicU32 = AtomicU32::new(0);
+        let name = self
+            .inner
+            .file_name()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+        let mut tmp_name = std::ffi::OsString::from(".");
+        tmp_name.push(name);
+        tmp_name.push(format!(
+            ".{}.{}.tmp",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        Ok(Self {
+            inner: self.inner.with_file_name(tmp_name),
+            dir: self.dir.clone(),
+            strict_owner: self.strict_owner,
+        })
+    }
 
     /// Creates a reader for this named pipe.
     pub fn open_read(&self) -> NamedPipeReader {
@@ -47,31 +479,335 @@ impl NamedPipePath {
 }
 
 /// A convenience wrapper for reading from Unix named pipes.
+///
+/// Every read opens the pipe anew, so dropping a read before it completes
+/// loses whatever it had read so far. Read from an
+/// [`OpenReader`](struct.OpenReader.html) instead where reads may be
+/// cancelled, e.g. in a `select!`.
+#[derive(Clone)]
 pub struct NamedPipeReader {
     path: NamedPipePath,
+    hold_open: bool,
+    max_message_size: Option<usize>,
+    pub(crate) eof_policy: EofPolicy,
+    pub(crate) shutdown: Option<ShutdownToken>,
 }
 
 impl NamedPipeReader {
+    /// Opens the pipe for reading, which never waits for a writer.
+    pub(crate) fn open_sync(&self) -> io::Result<OpenReader> {
+        let reader = OpenReader::open(&self.path, self.hold_open)?;
+        Ok(match self.max_message_size {
+            Some(limit) => reader.max_message_size(limit),
+            None => reader,
+        })
+    }
     /// Creates a new reader, cloning the given NamedPipePath.
     pub fn from_path(source: &NamedPipePath) -> Self {
         Self {
             path: source.clone(),
+            hold_open: false,
+            max_message_size: None,
+            eof_policy: EofPolicy::default(),
+            shutdown: None,
         }
     }
+    /// Keeps the pipe open for writing while reading from it.
+    ///
+    /// The pipe is opened read-write, so it never reports EOF when the last
+    /// writer closes it; reads just wait for the next writer to send data.
+    /// This is meant for persistent readers like `chunks` or `copy_to`;
+    /// `read` and `read_string` never resolve in this mode.
+    pub fn hold_open(mut self, hold_open: bool) -> Self {
+        self.hold_open = hold_open;
+        self
+    }
+    /// Limits how many bytes reads that collect a whole message, like
+    /// `read` and `read_string`, accept from the pipe.
+    ///
+    /// Reading more than `limit` bytes fails with an
+    /// [`error::MessageTooLarge`](error/struct.MessageTooLarge.html), so a
+    /// misbehaving writer can't make the reader allocate without bound;
+    /// whatever was read of the message is discarded. Handles from
+    /// [`open`](#method.open) apply the limit to their
+    /// [`read_exact`](struct.OpenReader.html#method.read_exact) and
+    /// [`read_until`](struct.OpenReader.html#method.read_until).
+    /// Streaming readers like `chunks` and `copy_to`, and reads into a
+    /// fixed-size buffer like `read_buf`, aren't affected.
+    pub fn max_message_size(mut self, limit: usize) -> Self {
+        self.max_message_size = Some(limit);
+        self
+    }
+    /// Sets what streaming readers like `chunks` do when the last writer
+    /// closes the pipe.
+    pub fn eof_policy(mut self, policy: EofPolicy) -> Self {
+        self.eof_policy = policy;
+        self
+    }
+    /// Makes streaming readers like `chunks` end as soon as `token` fires.
+    ///
+    /// For one-shot reads, use `ShutdownToken::run_until` instead.
+    pub fn with_shutdown(mut self, token: ShutdownToken) -> Self {
+        self.shutdown = Some(token);
+        self
+    }
+    /// Retries operations that fail with transient errors, like the pipe
+    /// not existing yet, according to `policy`.
+    pub fn with_retry(&self, policy: RetryPolicy) -> Retry<'_, Self> {
+        Retry::new(self, policy)
+    }
     /// Checks if the named pipe actually exists and tries to create it if it doesn't.
     pub fn ensure_pipe_exists(&self) -> nix::Result<&Self> {
         self.path.ensure_exists()?;
         Ok(self)
     }
+    /// Opens the reading end of the pipe.
+    ///
+    /// The returned handle can be used with any `async_std::io::Read` API.
+    pub async fn open(&self) -> io::Result<OpenReader> {
+        traced("open_read", self.path.as_path(), async { self.open_sync() }).await
+    }
     /// Reads all bytes from the pipe.
     /// The returned Future will resolve when something is written to the pipe.
     pub async fn read(&self) -> io::Result<Vec<u8>> {
-        fs::read(&self.path.inner).await
+        let mut buf = Vec::new();
+        self.read_into(&mut buf).await?;
+        Ok(buf)
+    }
+    /// Reads all bytes from the pipe, appending them to `buf`.
+    /// The returned Future will resolve when something is written to the pipe.
+    ///
+    /// Returns the number of bytes read; reusing `buf` across calls avoids
+    /// allocating a new buffer for every message.
+    pub async fn read_into(&self, buf: &mut Vec<u8>) -> io::Result<usize> {
+        traced("read", self.path.as_path(), self._read_into(buf)).await
+    }
+    async fn _read_into(&self, buf: &mut Vec<u8>) -> io::Result<usize> {
+        let mut reader = self.open().await?;
+        let probe = reader.probe.clone();
+        let limit = match self.max_message_size {
+            Some(limit) => limit,
+            None => {
+                let n = reader.read_to_end(buf).await?;
+                probe.message_read(n);
+                return Ok(n);
+            }
+        };
+        // Read one byte more than allowed to tell a message of exactly
+        // `limit` bytes from a larger one.
+        let n = reader.take(limit as u64 + 1).read_to_end(buf).await?;
+        if n > limit {
+            buf.truncate(buf.len() - n);
+            return Err(MessageTooLarge { limit }.into());
+        }
+        probe.message_read(n);
+        Ok(n)
+    }
+    /// Reads whatever is in the pipe right now.
+    ///
+    /// Unlike `read`, this neither waits for a writer nor for the writers to
+    /// close the pipe, so it returns right away, with an empty buffer if
+    /// nothing was written. Meant for polling a pipe periodically and for
+    /// picking up leftovers on shutdown. The data in a pipe is only kept
+    /// while some process has it open, so this only finds anything while a
+    /// writer is attached. Only what's in the pipe when draining starts is
+    /// taken, and more than the
+    /// [`max_message_size`](#method.max_message_size) fails with an
+    /// [`error::MessageTooLarge`](error/struct.MessageTooLarge.html).
+    pub async fn drain(&self) -> io::Result<Vec<u8>> {
+        traced("drain", self.path.as_path(), async {
+            let mut reader = self.open().await?;
+            let data = reader.drain()?;
+            reader.probe.message_read(data.len());
+            Ok(data)
+        })
+        .await
+    }
+    /// Reads from the pipe into `buf` until it is full or the writer closes.
+    /// The returned Future will resolve when something is written to the pipe.
+    ///
+    /// Returns the number of bytes read. If the writer sends more than fits
+    /// into `buf`, fails with an
+    /// [`error::MessageTooLarge`](error/struct.MessageTooLarge.html) with
+    /// `buf`'s length as the limit, rather than silently dropping the rest.
+    pub async fn read_buf(&self, buf: &mut [u8]) -> io::Result<usize> {
+        traced("read", self.path.as_path(), async {
+            let mut reader = self.open().await?;
+            let mut filled = 0;
+            loop {
+                let read = if filled < buf.len() {
+                    reader.read(&mut buf[filled..]).await
+                } else {
+                    // Full, so anything but EOF means the message doesn't fit
+                    reader.read(&mut [0]).await
+                };
+                match read {
+                    Ok(0) => break,
+                    Ok(_) if filled == buf.len() => {
+                        let limit = buf.len();
+                        return Err(MessageTooLarge { limit }.into());
+                    }
+                    Ok(n) => filled += n,
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            reader.probe.message_read(filled);
+            Ok(filled)
+        })
+        .await
+    }
+    /// Reads all bytes from the pipe into a `Bytes` buffer.
+    /// The returned Future will resolve when something is written to the pipe.
+    #[cfg(feature = "bytes")]
+    pub async fn read_bytes(&self) -> io::Result<bytes::Bytes> {
+        self.read().await.map(bytes::Bytes::from)
+    }
+    /// Reads all bytes from the pipe, appending them to `buf`.
+    /// The returned Future will resolve when something is written to the pipe.
+    ///
+    /// Returns the number of bytes read. `buf` only grows if the message
+    /// doesn't fit into its spare capacity.
+    #[cfg(feature = "bytes")]
+    pub async fn read_into_bytes(&self, buf: &mut bytes::BytesMut) -> io::Result<usize> {
+        let mut reader = self.open().await?;
+        let start = buf.len();
+        let result = loop {
+            if buf.capacity() == buf.len() {
+                buf.reserve(8 * 1024);
+            }
+            // Read straight into the spare capacity; a `u8` can be zeroed
+            // cheaply, which keeps this free of uninitialized memory.
+            let len = buf.len();
+            let mut spare = buf.capacity() - len;
+            if let Some(limit) = self.max_message_size {
+                // One byte more than allowed tells a full message from a
+                // larger one
+                spare = spare.min(limit + 1 - (len - start));
+            }
+            buf.resize(len + spare, 0);
+            let read = reader.read(&mut buf[len..]).await;
+            buf.truncate(len + *read.as_ref().unwrap_or(&0));
+            match read {
+                Ok(0) => break Ok(len - start),
+                Ok(_) => match self.max_message_size {
+                    Some(limit) if buf.len() - start > limit => {
+                        break Err(MessageTooLarge { limit }.into())
+                    }
+                    _ => {}
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => break Err(e),
+            }
+        };
+        match result {
+            Ok(n) => {
+                reader.probe.message_read(n);
+                Ok(n)
+            }
+            Err(e) => {
+                buf.truncate(start);
+                Err(e)
+            }
+        }
     }
     /// Reads a String from the pipe.
     /// The returned Future will resolve when something is written to the pipe.
     pub async fn read_string(&self) -> io::Result<String> {
-        fs::read_to_string(&self.path.inner).await
+        let buf = self.read().await?;
+        String::from_utf8(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+    /// Reads all bytes from the pipe, blocking the current thread until
+    /// something is written to the pipe and the writer closes it.
+    ///
+    /// Works without an async runtime and honors `max_message_size` like
+    /// `read`. As with `read`, the EOF policy and shutdown token only apply
+    /// to streaming readers. Fails with `InvalidInput` if the reader is set
+    /// to [`hold_open`](#method.hold_open), as it would never see EOF.
+    #[cfg(feature = "blocking")]
+    pub fn read_blocking(&self) -> io::Result<Vec<u8>> {
+        use std::io::Read as _;
+        if self.hold_open {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "blocking reads can't hold the pipe open",
+            ));
+        }
+        let file = self.path.open_file(OFlag::O_RDONLY)?;
+        let mut buf = Vec::new();
+        match self.max_message_size {
+            Some(limit) => {
+                // See `read_into` for why this reads one byte more
+                let n = file.take(limit as u64 + 1).read_to_end(&mut buf)?;
+                if n > limit {
+                    return Err(MessageTooLarge { limit }.into());
+                }
+            }
+            None => {
+                let mut file = file;
+                file.read_to_end(&mut buf)?;
+            }
+        }
+        Ok(buf)
+    }
+    /// Reads a String from the pipe, blocking the current thread until
+    /// something is written to the pipe and the writer closes it.
+    #[cfg(feature = "blocking")]
+    pub fn read_string_blocking(&self) -> io::Result<String> {
+        let buf = self.read_blocking()?;
+        String::from_utf8(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+    /// Reads a string from the pipe, replacing invalid UTF-8 sequences
+    /// with `U+FFFD REPLACEMENT CHARACTER` instead of failing.
+    /// The returned Future will resolve when something is written to the pipe.
+    pub async fn read_string_lossy(&self) -> io::Result<String> {
+        let buf = self.read().await?;
+        Ok(String::from_utf8_lossy(&buf).into_owned())
+    }
+    /// Reads a string in the given encoding from the pipe, e.g.
+    /// `encoding_rs::UTF_16LE`.
+    /// The returned Future will resolve when something is written to the pipe.
+    ///
+    /// Malformed sequences are replaced with `U+FFFD REPLACEMENT CHARACTER`.
+    /// A byte order mark at the start of the data takes precedence over
+    /// `encoding`. Note that `encoding_rs::WINDOWS_1252` is the closest
+    /// thing to Latin-1 and decodes it correctly except for the C1
+    /// control characters.
+    #[cfg(feature = "encoding")]
+    pub async fn read_string_with(
+        &self,
+        encoding: &'static encoding_rs::Encoding,
+    ) -> io::Result<String> {
+        let buf = self.read().await?;
+        let (decoded, _, _) = encoding.decode(&buf);
+        Ok(decoded.into_owned())
+    }
+    /// Returns a stream of chunks read from the pipe, each at most
+    /// `chunk_size` bytes long.
+    ///
+    /// Unlike `read`, chunks are yielded as soon as they arrive, so large
+    /// transfers don't have to fit in memory. The stream ends when the
+    /// writer closes the pipe.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk_size` is 0.
+    pub fn chunks(&self, chunk_size: usize) -> Chunks {
+        Chunks::new(self.clone(), chunk_size)
+    }
+    /// Streams everything written to the pipe into the given writer.
+    ///
+    /// Data is forwarded in chunks as it arrives, so the payload never has
+    /// to fit in memory; a slow `writer` slows down reading from the pipe.
+    /// Resolves with the number of bytes copied once the writing end closes.
+    pub async fn copy_to<W: io::Write + Unpin>(&self, mut writer: W) -> io::Result<u64> {
+        traced("copy_to", self.path.as_path(), async {
+            let mut reader = self.open().await?;
+            let n = io::copy(&mut reader, &mut writer).await?;
+            record_bytes(n);
+            Ok(n)
+        })
+        .await
     }
 }
 
@@ -82,33 +818,73 @@ pub struct NamedPipeWriter {
 
 impl NamedPipeWriter {
     async fn _write(&self, data: &[u8]) -> io::Result<()> {
-        use async_std::io::Write;
-        let mut file = async_std::fs::OpenOptions::new()
-            .write(true)
-            .create(false)
-            .open(&self.path.inner)
-            .await?;
-        file.write_all(data).await
+        let mut writer = self.open().await?;
+        writer.write_all(data).await?;
+        writer.probe.message_written(data.len());
+        Ok(())
     }
     pub fn from_path(source: &NamedPipePath) -> Self {
         Self {
             path: source.clone(),
         }
     }
+    /// Retries operations that fail with transient errors, like the pipe
+    /// not existing yet, according to `policy`.
+    pub fn with_retry(&self, policy: RetryPolicy) -> Retry<'_, Self> {
+        Retry::new(self, policy)
+    }
     /// Checks if the named pipe actually exists and tries to create it if it doesn't.
     pub fn ensure_pipe_exists(&self) -> nix::Result<&Self> {
         self.path.ensure_exists()?;
         Ok(self)
     }
+    /// Opens the writing end of the pipe.
+    /// The returned Future will resolve when the pipe has a reader.
+    ///
+    /// The returned handle can be used with any `async_std::io::Write` API.
+    pub async fn open(&self) -> io::Result<OpenWriter> {
+        let open = OpenWriter::open(&self.path);
+        traced("open_write", self.path.as_path(), open).await
+    }
     /// Writes byte data to the pipe.
     /// The returned Future will resolve when the bytes are read from the pipe.
     pub async fn write(&self, data: &[u8]) -> io::Result<()> {
-        self._write(data).await
+        traced("write", self.path.as_path(), self._write(data)).await
     }
     /// Writes &str data to the pipe.
     /// The returned Future will resolve when the string is read from the pipe.
     pub async fn write_str(&self, data: &str) -> io::Result<()> {
-        self._write(data.as_bytes()).await
+        traced("write", self.path.as_path(), self._write(data.as_bytes())).await
+    }
+    /// Writes byte data to the pipe, blocking the current thread until the
+    /// pipe has a reader and the data is written.
+    ///
+    /// Works without an async runtime.
+    #[cfg(feature = "blocking")]
+    pub fn write_blocking(&self, data: &[u8]) -> io::Result<()> {
+        use std::io::Write as _;
+        let mut file = self.path.open_file(OFlag::O_WRONLY)?;
+        file.write_all(data)
+    }
+    /// Writes &str data to the pipe, blocking the current thread until the
+    /// pipe has a reader and the string is written.
+    #[cfg(feature = "blocking")]
+    pub fn write_str_blocking(&self, data: &str) -> io::Result<()> {
+        self.write_blocking(data.as_bytes())
+    }
+    /// Streams everything from the given reader into the pipe.
+    ///
+    /// Data is forwarded in chunks, waiting for the reading end to consume
+    /// it before pulling more from `reader`.
+    /// Resolves with the number of bytes copied once `reader` hits EOF.
+    pub async fn copy_from<R: io::Read + Unpin>(&self, mut reader: R) -> io::Result<u64> {
+        traced("copy_from", self.path.as_path(), async {
+            let mut writer = self.open().await?;
+            let n = io::copy(&mut reader, &mut writer).await?;
+            record_bytes(n);
+            Ok(n)
+        })
+        .await
     }
 }
 
@@ -187,4 +963,369 @@ mod tests {
             pipe.delete().await
         })
     }
+    #[test]
+    fn copy_to_and_from() -> io::Result<()> {
+        block_on(async {
+            let pipe = super::NamedPipePath::new("./test_pipe_7");
+            pipe.ensure_exists().unwrap();
+            let writer = pipe.open_write();
+            let reader = pipe.open_read();
+            let data_to_send = "Hello pipe".repeat(1000);
+            let source = io::Cursor::new(data_to_send.clone().into_bytes());
+            let t1 = task::spawn(async move { writer.copy_from(source).await });
+            let t2 = task::spawn(async move {
+                let mut sink = Vec::new();
+                reader.copy_to(&mut sink).await.map(|_| sink)
+            });
+            assert_eq!(t1.await?, data_to_send.len() as u64);
+            let read_result = t2.await?;
+            assert_eq!(read_result, data_to_send.as_bytes());
+            pipe.delete().await
+        })
+    }
+    #[test]
+    fn read_into_buffers() -> io::Result<()> {
+        block_on(async {
+            let pipe = super::NamedPipePath::new("./test_pipe_11");
+            pipe.ensure_exists().unwrap();
+            let writer = pipe.open_write();
+            let reader = pipe.open_read();
+            let t1 = task::spawn(async move {
+                writer.write_str("Hello pipe").await?;
+                io::Result::Ok(writer)
+            });
+            let mut buf = Vec::with_capacity(64);
+            assert_eq!(reader.read_into(&mut buf).await?, 10);
+            assert_eq!(buf, b"Hello pipe");
+            let writer = t1.await?;
+            let t2 = task::spawn(async move {
+                writer.write_str("Hello").await?;
+                io::Result::Ok(writer)
+            });
+            let mut buf = [0; 64];
+            let n = reader.read_buf(&mut buf).await?;
+            assert_eq!(&buf[..n], b"Hello");
+            let writer = t2.await?;
+            let t3 = task::spawn(async move { writer.write_str("Hello pipe").await });
+            let mut buf = [0; 5];
+            let err = reader.read_buf(&mut buf).await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            // Depending on timing, the writer may see the reader close early
+            let _ = t3.await;
+            pipe.delete().await
+        })
+    }
+    #[test]
+    fn hold_open_survives_writer_close() -> io::Result<()> {
+        use async_std::prelude::*;
+        block_on(async {
+            let pipe = super::NamedPipePath::new("./test_pipe_15");
+            pipe.ensure_exists().unwrap();
+            let writer = pipe.open_write();
+            let mut chunks = pipe.open_read().hold_open(true).chunks(64);
+            let t1 = task::spawn(async move {
+                writer.write_str("Hello").await?;
+                writer.write_str(" pipe").await
+            });
+            let mut read_result = Vec::new();
+            while read_result.len() < 10 {
+                read_result.extend(chunks.next().await.unwrap()?);
+            }
+            t1.await?;
+            assert_eq!(read_result, b"Hello pipe");
+            pipe.delete().await
+        })
+    }
+    #[test]
+    fn read_invalid_utf8() -> io::Result<()> {
+        let pipe = super::NamedPipePath::new("./test_pipe_26");
+        pipe.ensure_exists().unwrap();
+        let reader = pipe.open_read();
+        let writer = pipe.open_write();
+        block_on(async {
+            let t_write = task::spawn(async move { writer.write(b"caf\xe9").await });
+            let read_result = reader.read_string_lossy().await?;
+            t_write.await?;
+            assert_eq!(read_result, "caf\u{fffd}");
+            #[cfg(feature = "encoding")]
+            {
+                let writer = pipe.open_write();
+                let t_write = task::spawn(async move { writer.write(b"caf\xe9").await });
+                let read_result = reader.read_string_with(encoding_rs::WINDOWS_1252).await?;
+                t_write.await?;
+                assert_eq!(read_result, "caf\u{e9}");
+            }
+            pipe.delete().await
+        })
+    }
+    #[test]
+    fn max_message_size() -> io::Result<()> {
+        use crate::error::MessageTooLarge;
+        let pipe = super::NamedPipePath::new("./test_pipe_27");
+        pipe.ensure_exists().unwrap();
+        let reader = pipe.open_read().max_message_size(5);
+        block_on(async {
+            let writer = pipe.open_write();
+            let t_write = task::spawn(async move { writer.write(b"Hello").await });
+            assert_eq!(reader.read().await?, b"Hello");
+            t_write.await?;
+            let writer = pipe.open_write();
+            let t_write = task::spawn(async move { writer.write(b"Hello pipe").await });
+            let err = reader.read().await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            let err = err.get_ref().unwrap().downcast_ref::<MessageTooLarge>();
+            assert_eq!(err, Some(&MessageTooLarge { limit: 5 }));
+            // Depending on timing, the writer may see the reader close early
+            let _ = t_write.await;
+            pipe.delete().await
+        })
+    }
+    #[test]
+    fn introspection() -> io::Result<()> {
+        use nix::sys::stat::Mode;
+        block_on(async {
+            let pipe = super::NamedPipePath::new("./test_pipe_24");
+            assert!(!pipe.is_fifo());
+            crate::create_pipe(pipe.as_path(), Some(Mode::from_bits_truncate(0o640))).unwrap();
+            assert!(pipe.is_fifo());
+            assert_eq!(pipe.permissions()?, Mode::from_bits_truncate(0o640));
+            let (uid, gid) = pipe.owner()?;
+            assert_eq!(uid, nix::unistd::geteuid());
+            assert_eq!(gid, nix::unistd::getegid());
+            assert!(crate::util::is_fifo(&pipe.metadata()?));
+            pipe.delete().await
+        })
+    }
+    #[test]
+    fn delete_and_recreate() -> io::Result<()> {
+        block_on(async {
+            let pipe = super::NamedPipePath::new("./test_pipe_40");
+            pipe.ensure_exists().unwrap();
+            // Keep the old pipe open to tell it apart from the new one
+            let old = pipe.open_read().open().await?;
+            pipe.recreate().await?;
+            assert!(pipe.is_fifo());
+            let reader = pipe.open_read();
+            let writer = pipe.open_write();
+            let t1 = task::spawn(async move { writer.write_str("Hello pipe").await });
+            assert_eq!(reader.read_string().await?, "Hello pipe");
+            t1.await?;
+            drop(old);
+            pipe.delete_in_place().await?;
+            assert!(!pipe.exists());
+            pipe.delete().await
+        })
+    }
+    #[test]
+    fn create_with_parents() -> io::Result<()> {
+        use nix::sys::stat::Mode;
+        block_on(async {
+            let pipe = super::NamedPipePath::new("./test_dir_41/nested/test_pipe_41");
+            pipe.ensure_exists_with_parents(Mode::from_bits_truncate(0o700))?;
+            assert!(pipe.is_fifo());
+            // Existing directories are fine
+            let dir = std::fs::File::open("./test_dir_41")?;
+            let sibling = super::NamedPipePath::at(&dir, "nested/more/test_pipe_41")?;
+            sibling.ensure_exists_with_parents(Mode::from_bits_truncate(0o700))?;
+            assert!(sibling.is_fifo());
+            std::fs::remove_dir_all("./test_dir_41")
+        })
+    }
+    #[test]
+    fn replace_atomic() -> io::Result<()> {
+        use nix::sys::stat::Mode;
+        block_on(async {
+            let pipe = super::NamedPipePath::new("./test_pipe_42");
+            std::fs::write("./test_pipe_42", b"not a pipe")?;
+            pipe.replace_atomic(Mode::from_bits_truncate(0o602)).await?;
+            assert!(pipe.is_fifo());
+            assert_eq!(pipe.permissions()?, Mode::from_bits_truncate(0o602));
+            pipe.delete().await
+        })
+    }
+    #[test]
+    fn set_owner() -> io::Result<()> {
+        block_on(async {
+            let pipe = super::NamedPipePath::new("./test_pipe_43");
+            pipe.ensure_exists().unwrap();
+            // Giving a file to another user needs privileges, but setting
+            // the owners we already have doesn't.
+            let uid = nix::unistd::geteuid();
+            let gid = nix::unistd::getegid();
+            pipe.set_owner(Some(uid), None)?;
+            pipe.set_owner(None, Some(gid))?;
+            assert_eq!(pipe.owner()?, (uid, gid));
+            // Symlinks are refused
+            std::os::unix::fs::symlink("test_pipe_43", "./test_pipe_59")?;
+            let link = super::NamedPipePath::new("./test_pipe_59");
+            assert!(link.set_owner(Some(uid), None).is_err());
+            std::fs::remove_file("./test_pipe_59")?;
+            pipe.delete().await
+        })
+    }
+    #[test]
+    fn runtime_dir() -> io::Result<()> {
+        let dir = std::env::current_dir()?.join("test_runtime_dir");
+        std::env::set_var("XDG_RUNTIME_DIR", &dir);
+        let pipe = super::NamedPipePath::in_runtime_dir("myapp/control")?;
+        assert_eq!(pipe.as_path(), dir.join("myapp/control"));
+        assert!(dir.join("myapp").is_dir());
+        std::fs::remove_dir_all(&dir)
+    }
+    #[test]
+    fn relative_to_dir() -> io::Result<()> {
+        block_on(async {
+            let dir = std::fs::File::open(".")?;
+            let pipe = super::NamedPipePath::at(&dir, "test_pipe_23")?;
+            drop(dir);
+            pipe.ensure_exists().unwrap();
+            assert!(pipe.exists());
+            assert!(std::path::Path::new("./test_pipe_23").exists());
+            let writer = pipe.open_write();
+            let reader = pipe.open_read();
+            let t1 = task::spawn(async move { writer.write_str("Hello pipe").await });
+            assert_eq!(reader.read_string().await?, "Hello pipe");
+            t1.await?;
+            pipe.delete().await?;
+            assert!(!std::path::Path::new("./test_pipe_23").exists());
+            Ok(())
+        })
+    }
+    #[test]
+    fn wait_until_exists() -> io::Result<()> {
+        block_on(async {
+            let pipe = super::NamedPipePath::new("./test_pipe_46");
+            let t_create = task::spawn({
+                let pipe = pipe.clone();
+                async move {
+                    task::sleep(std::time::Duration::from_millis(20)).await;
+                    pipe.ensure_exists().unwrap();
+                }
+            });
+            pipe.wait_until_exists().await?;
+            assert!(pipe.exists());
+            t_create.await;
+            // Resolves right away once the pipe is there
+            pipe.wait_until_exists().await?;
+            pipe.delete().await
+        })
+    }
+    #[test]
+    fn strict() -> io::Result<()> {
+        use crate::error::InsecurePipe;
+        use std::os::unix::fs::PermissionsExt;
+        let insecurity = |result: io::Result<crate::OpenReader>| {
+            let err = result.err().expect("opened an insecure pipe");
+            *err.get_ref()
+                .unwrap()
+                .downcast_ref::<InsecurePipe>()
+                .unwrap()
+        };
+        block_on(async {
+            let pipe = super::NamedPipePath::new("./test_pipe_49").strict();
+            pipe.ensure_exists().unwrap();
+            pipe.open_read().open().await?;
+            let other = nix::unistd::Uid::from_raw(nix::unistd::geteuid().as_raw() + 1);
+            let foreign = pipe.clone().strict_owner(other);
+            match insecurity(foreign.open_read().open().await) {
+                InsecurePipe::WrongOwner { expected, .. } => assert_eq!(expected, other),
+                e => panic!("unexpected {:?}", e),
+            }
+            std::fs::set_permissions("./test_pipe_49", std::fs::Permissions::from_mode(0o662))?;
+            match insecurity(pipe.open_read().open().await) {
+                InsecurePipe::WorldWritable { mode } => assert_eq!(mode.bits() & 0o777, 0o662),
+                e => panic!("unexpected {:?}", e),
+            }
+
+            std::os::unix::fs::symlink("test_pipe_49", "./test_link_49")?;
+            let link = super::NamedPipePath::new("./test_link_49");
+            link.open_read().open().await?;
+            let link = link.strict();
+            assert_eq!(
+                insecurity(link.open_read().open().await),
+                InsecurePipe::Symlink
+            );
+            // Nothing else follows it either
+            assert!(link.set_owner(None, None).is_err());
+            link.delete_in_place().await?;
+            assert!(pipe.exists());
+            std::os::unix::fs::symlink("test_nowhere_49", "./test_link_49")?;
+            link.ensure_exists().unwrap();
+            assert!(!std::path::Path::new("./test_nowhere_49").exists());
+            link.delete().await?;
+            std::fs::write("./test_file_49", b"not a pipe")?;
+            let file = super::NamedPipePath::new("./test_file_49").strict();
+            assert_eq!(
+                insecurity(file.open_read().open().await),
+                InsecurePipe::NotAFifo
+            );
+            std::fs::remove_file("./test_file_49")?;
+            pipe.delete().await
+        })
+    }
+    #[test]
+    fn drain() -> io::Result<()> {
+        use async_std::prelude::*;
+        block_on(async {
+            let pipe = super::NamedPipePath::new("./test_pipe_50");
+            pipe.ensure_exists().unwrap();
+            let reader = pipe.open_read();
+            // No writer, so there's nothing to wait for
+            assert!(reader.drain().await?.is_empty());
+
+            let _keep_reader = reader.open().await?;
+            let mut writer = pipe.open_write().open().await?;
+            writer.write_all(b"Hello pipe").await?;
+            assert_eq!(reader.drain().await?, b"Hello pipe");
+            // The writer is still attached, but there's nothing left
+            assert!(reader.drain().await?.is_empty());
+            writer.write_all(b"Hello pipe").await?;
+            let limited = reader.clone().max_message_size(5);
+            let err = limited.drain().await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            assert_eq!(reader.max_message_size(10).drain().await?, b"Hello pipe");
+            pipe.delete().await
+        })
+    }
+    #[test]
+    #[cfg(feature = "blocking")]
+    fn write_and_read_blocking() -> io::Result<()> {
+        use std::thread;
+        let pipe = super::NamedPipePath::new("./test_pipe_39");
+        pipe.ensure_exists().unwrap();
+        let writer = pipe.open_write();
+        let t_write = thread::spawn(move || writer.write_str_blocking("Hello pipe"));
+        let read_result = pipe.open_read().read_string_blocking()?;
+        t_write.join().unwrap()?;
+        assert_eq!(read_result, "Hello pipe");
+        let held_open = pipe.open_read().hold_open(true);
+        let err = held_open.read_blocking().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        std::fs::remove_file("./test_pipe_39")
+    }
+    #[test]
+    #[cfg(feature = "bytes")]
+    fn read_into_bytes() -> io::Result<()> {
+        block_on(async {
+            let pipe = super::NamedPipePath::new("./test_pipe_61");
+            pipe.ensure_exists().unwrap();
+            let reader = pipe.open_read().max_message_size(10);
+            let writer = pipe.open_write();
+            let t_write = task::spawn(async move {
+                writer.write_str("Hello pipe").await?;
+                io::Result::Ok(writer)
+            });
+            let mut buf = bytes::BytesMut::with_capacity(4);
+            buf.extend_from_slice(b">");
+            assert_eq!(reader.read_into_bytes(&mut buf).await?, 10);
+            assert_eq!(&buf[..], b">Hello pipe");
+            let writer = t_write.await?;
+            let t_write = task::spawn(async move { writer.write_str("Hello pipe!").await });
+            let err = reader.read_into_bytes(&mut buf).await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            assert_eq!(&buf[..], b">Hello pipe");
+            let _ = t_write.await;
+            pipe.delete().await
+        })
+    }
 }
@@ -0,0 +1,253 @@
+//! Spreading messages over a pool of worker pipes.
+//!
+//! A [`PipePool`](struct.PipePool.html) sends every message as a
+//! [frame](../frame/index.html) to one of several pipes, each read by a
+//! worker process:
+//!
+//! ```no_run
+//! # fn main() -> async_std::io::Result<()> { async_std::task::block_on(async {
+//! use unix_fifo_async::pool::{PipePool, Routing};
+//!
+//! // Creates ./workers/worker-0 to ./workers/worker-3
+//! let mut pool = PipePool::numbered("./workers", "worker", 4)?.routing(Routing::LeastBacklog);
+//! let worker = pool.dispatch(b"resize image.png").await?;
+//! println!("sent to worker {}", worker);
+//! # Ok(())
+//! # })}
+//! ```
+//!
+//! Workers that aren't reading their pipe are skipped, so a worker that
+//! crashed doesn't hold up the pool; it gets new messages as soon as it
+//! opens its pipe again. Messages that were in its pipe when it went away
+//! are lost.
+use crate::{frame::FramedWriter, NamedPipePath, OpenWriter};
+use async_std::io;
+use nix::{fcntl::OFlag, sys::stat::Mode};
+use std::os::unix::io::IntoRawFd;
+use std::path::{Path, PathBuf};
+
+/// How a [`PipePool`](struct.PipePool.html) picks the worker for a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Routing {
+    /// Every worker in turn.
+    #[default]
+    RoundRobin,
+    /// The worker with the fewest unread bytes in its pipe, taking turns
+    /// among equally busy workers.
+    LeastBacklog,
+}
+
+/// Whether a worker in a [`PipePool`](struct.PipePool.html) is reachable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Health {
+    /// Nothing was sent to the worker yet.
+    Unknown,
+    /// The worker's pipe has a reader.
+    Up,
+    /// The worker's pipe had no reader, or writing to it failed.
+    Down,
+}
+
+/// The state of one worker in a [`PipePool`](struct.PipePool.html).
+#[derive(Debug, Clone)]
+pub struct WorkerStatus {
+    /// The path of the worker's pipe.
+    pub path: PathBuf,
+    /// Whether the worker could be reached the last time it was tried.
+    pub health: Health,
+    /// How many times in a row the worker couldn't be reached.
+    pub failures: u32,
+    /// How many messages were sent to the worker.
+    pub dispatched: u64,
+}
+
+struct Worker {
+    pipe: NamedPipePath,
+    writer: Option<FramedWriter>,
+    health: Health,
+    failures: u32,
+    dispatched: u64,
+}
+
+impl Worker {
+    /// Opens the pipe if it isn't open yet, returning whether it has a
+    /// reader.
+    fn attach(&mut self) -> bool {
+        if self.writer.is_some() {
+            return true;
+        }
+        let opened = self
+            .pipe
+            .open_file(OFlag::O_WRONLY | OFlag::O_NONBLOCK)
+            // Safety: the file was just opened and is handed over.
+            .and_then(|file| unsafe { OpenWriter::from_raw_fd(file.into_raw_fd()) });
+        match opened {
+            Ok(writer) => {
+                self.writer = Some(FramedWriter::new(writer));
+                true
+            }
+            Err(_) => {
+                self.failed();
+                false
+            }
+        }
+    }
+    /// Returns how many bytes in the pipe are still unread.
+    fn backlog(&self) -> usize {
+        self.writer
+            .as_ref()
+            .and_then(|writer| writer.get_ref().bytes_available().ok())
+            .unwrap_or(usize::MAX)
+    }
+    fn failed(&mut self) {
+        self.writer = None;
+        self.health = Health::Down;
+        self.failures = self.failures.saturating_add(1);
+    }
+}
+
+/// Sends messages to one of several worker pipes.
+pub struct PipePool {
+    workers: Vec<Worker>,
+    routing: Routing,
+    /// Where the next round starts.
+    next: usize,
+}
+
+impl PipePool {
+    /// Creates a pool of the given pipes, which have to exist.
+    pub fn new<I: IntoIterator<Item = NamedPipePath>>(pipes: I) -> Self {
+        let workers = pipes
+            .into_iter()
+            .map(|pipe| Worker {
+                pipe,
+                writer: None,
+                health: Health::Unknown,
+                failures: 0,
+                dispatched: 0,
+            })
+            .collect();
+        Self {
+            workers,
+            routing: Routing::default(),
+            next: 0,
+        }
+    }
+    /// Creates a pool of `count` pipes named `{name}-0`, `{name}-1` and so
+    /// on in `dir`, creating the pipes if they don't exist.
+    ///
+    /// Missing directories are created with permission bits `0o700`.
+    pub fn numbered<P: AsRef<Path>>(dir: P, name: &str, count: usize) -> io::Result<Self> {
+        let pipes: Vec<_> = (0..count)
+            .map(|i| NamedPipePath::new(dir.as_ref().join(format!("{}-{}", name, i))))
+            .collect();
+        for pipe in &pipes {
+            pipe.ensure_exists_with_parents(Mode::S_IRWXU)?;
+        }
+        Ok(Self::new(pipes))
+    }
+    /// Sets how workers are picked; the default is
+    /// [`Routing::RoundRobin`](enum.Routing.html#variant.RoundRobin).
+    pub fn routing(mut self, routing: Routing) -> Self {
+        self.routing = routing;
+        self
+    }
+    /// Returns the number of workers.
+    pub fn len(&self) -> usize {
+        self.workers.len()
+    }
+    /// Checks if there are no workers.
+    pub fn is_empty(&self) -> bool {
+        self.workers.is_empty()
+    }
+    /// Returns the state of every worker, in the order they were added.
+    pub fn status(&self) -> Vec<WorkerStatus> {
+        self.workers
+            .iter()
+            .map(|worker| WorkerStatus {
+                path: worker.pipe.as_path().to_path_buf(),
+                health: worker.health,
+                failures: worker.failures,
+                dispatched: worker.dispatched,
+            })
+            .collect()
+    }
+    /// Sends `message` to a worker and returns its index.
+    ///
+    /// Workers that can't be reached are skipped. If none can, this fails
+    /// with `NotConnected`. Waits for room if the chosen worker's pipe is
+    /// full.
+    pub async fn dispatch(&mut self, message: &[u8]) -> io::Result<usize> {
+        let count = self.workers.len();
+        let mut order: Vec<usize> = (0..count).map(|i| (self.next + i) % count).collect();
+        if self.routing == Routing::LeastBacklog {
+            order.retain(|&i| self.workers[i].attach());
+            // Stable, so equally busy workers still take turns
+            order.sort_by_key(|&i| self.workers[i].backlog());
+        }
+        for i in order {
+            let worker = &mut self.workers[i];
+            if !worker.attach() {
+                continue;
+            }
+            if let Some(writer) = &mut worker.writer {
+                if writer.send(message).await.is_err() {
+                    worker.failed();
+                    continue;
+                }
+            }
+            worker.health = Health::Up;
+            worker.failures = 0;
+            worker.dispatched += 1;
+            self.next = (i + 1) % count;
+            return Ok(i);
+        }
+        Err(io::Error::new(
+            io::ErrorKind::NotConnected,
+            "no worker is reading its pipe",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Health, PipePool, Routing};
+    use crate::{frame::FramedReader, NamedPipePath};
+    use async_std::{io, task};
+    #[test]
+    fn dispatch_to_workers() -> io::Result<()> {
+        task::block_on(async {
+            let dir = "./test_dir_53";
+            let mut pool = PipePool::numbered(dir, "worker", 2)?;
+            let err = pool.dispatch(b"nobody").await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::NotConnected);
+
+            let open = |i| async move {
+                let pipe = NamedPipePath::new(format!("{}/worker-{}", dir, i));
+                io::Result::Ok(FramedReader::new(pipe.open_read().open().await?))
+            };
+            let mut second = open(1).await?;
+            assert_eq!(pool.dispatch(b"a").await?, 1);
+            let mut first = open(0).await?;
+            assert_eq!(pool.dispatch(b"b").await?, 0);
+            assert_eq!(pool.dispatch(b"c").await?, 1);
+
+            // The second worker has more to catch up on
+            let mut pool = pool.routing(Routing::LeastBacklog);
+            assert_eq!(pool.dispatch(b"d").await?, 0);
+            assert_eq!(first.recv().await?, Some(b"b".to_vec()));
+            assert_eq!(first.recv().await?, Some(b"d".to_vec()));
+            drop(first);
+            assert_eq!(pool.dispatch(b"e").await?, 1);
+            let status = pool.status();
+            assert_eq!(status[0].health, Health::Down);
+            assert_eq!(status[1].health, Health::Up);
+            assert_eq!(status[1].dispatched, 3);
+            for expected in &[&b"a"[..], b"c", b"e"] {
+                assert_eq!(second.recv().await?.as_deref(), Some(*expected));
+            }
+            drop(second);
+            std::fs::remove_dir_all(dir)
+        })
+    }
+}
@@ -0,0 +1,171 @@
+//! Hooks for the `metrics` and `tracing` features; these do nothing
+//! without them.
+//!
+//! Recorded metrics, all labelled with the pipe's `path`:
+//!
+//! - `unix_fifo_bytes_read_total`, `unix_fifo_bytes_written_total`
+//!   (counters)
+//! - `unix_fifo_messages_read_total`, `unix_fifo_messages_written_total`
+//!   (counters), for whole-message reads and writes
+//! - `unix_fifo_open_seconds` (histogram, labelled with the `end` being
+//!   opened), how long opening took
+//! - `unix_fifo_peer_wait_seconds` (histogram, labelled with `end`), how
+//!   long an end waited for the other side to show up
+//!
+//! Traced operations run in a `fifo` span at debug level, with the `op`,
+//! the pipe's `path` and the number of `bytes` transferred as fields. The
+//! other side attaching and EOF are logged as debug events.
+//!
+//! Handles adopted from raw file descriptors aren't recorded.
+
+#[cfg(feature = "metrics")]
+use metrics::{counter, histogram};
+use std::future::Future;
+use std::path::Path;
+#[cfg(any(feature = "metrics", feature = "tracing"))]
+use std::sync::Arc;
+use std::time::Duration;
+#[cfg(feature = "metrics")]
+use std::time::Instant;
+
+/// Records metrics and events for one open pipe end.
+#[derive(Clone, Default)]
+pub(crate) struct Probe {
+    #[cfg(any(feature = "metrics", feature = "tracing"))]
+    path: Option<Arc<str>>,
+    #[cfg(feature = "metrics")]
+    opened_at: Option<Instant>,
+}
+
+#[cfg_attr(
+    not(all(feature = "metrics", feature = "tracing")),
+    allow(unused_variables)
+)]
+impl Probe {
+    /// Creates a probe for the pipe at `path`, starting its clock for
+    /// `peer_attached`.
+    pub(crate) fn new(path: &Path) -> Self {
+        Self {
+            #[cfg(any(feature = "metrics", feature = "tracing"))]
+            path: Some(path.to_string_lossy().into()),
+            #[cfg(feature = "metrics")]
+            opened_at: Some(Instant::now()),
+        }
+    }
+    pub(crate) fn opened(&self, end: &'static str, latency: Duration) {
+        #[cfg(feature = "metrics")]
+        {
+            if let Some(path) = &self.path {
+                histogram!(
+                    "unix_fifo_open_seconds", latency,
+                    "path" => path.to_string(), "end" => end
+                );
+            }
+        }
+    }
+    /// Records how long this end waited for the other side since it was
+    /// opened.
+    pub(crate) fn peer_attached(&self, end: &'static str) {
+        #[cfg(feature = "metrics")]
+        {
+            if let (Some(path), Some(opened_at)) = (&self.path, self.opened_at) {
+                histogram!(
+                    "unix_fifo_peer_wait_seconds", opened_at.elapsed(),
+                    "path" => path.to_string(), "end" => end
+                );
+            }
+        }
+        #[cfg(feature = "tracing")]
+        {
+            if let Some(path) = &self.path {
+                tracing::debug!(path = &**path, end, "peer attached");
+            }
+        }
+    }
+    /// Records time spent waiting for the other side before this end could
+    /// be opened at all.
+    pub(crate) fn peer_waited(&self, end: &'static str, waited: Duration) {
+        #[cfg(feature = "metrics")]
+        {
+            if let Some(path) = &self.path {
+                histogram!(
+                    "unix_fifo_peer_wait_seconds", waited,
+                    "path" => path.to_string(), "end" => end
+                );
+            }
+        }
+        #[cfg(feature = "tracing")]
+        {
+            if let Some(path) = &self.path {
+                tracing::debug!(path = &**path, end, ?waited, "peer attached");
+            }
+        }
+    }
+    pub(crate) fn bytes_read(&self, n: usize) {
+        #[cfg(feature = "metrics")]
+        {
+            if let Some(path) = &self.path {
+                counter!("unix_fifo_bytes_read_total", n as u64, "path" => path.to_string());
+            }
+        }
+        #[cfg(feature = "tracing")]
+        {
+            match &self.path {
+                Some(path) if n == 0 => tracing::debug!(path = &**path, "eof"),
+                _ => {}
+            }
+        }
+    }
+    pub(crate) fn bytes_written(&self, n: usize) {
+        #[cfg(feature = "metrics")]
+        {
+            if let Some(path) = &self.path {
+                counter!("unix_fifo_bytes_written_total", n as u64, "path" => path.to_string());
+            }
+        }
+    }
+    /// Records a whole message of `n` bytes being read.
+    pub(crate) fn message_read(&self, n: usize) {
+        #[cfg(feature = "metrics")]
+        {
+            if let Some(path) = &self.path {
+                counter!("unix_fifo_messages_read_total", 1, "path" => path.to_string());
+            }
+        }
+        record_bytes(n as u64);
+    }
+    /// Records a whole message of `n` bytes being written.
+    pub(crate) fn message_written(&self, n: usize) {
+        #[cfg(feature = "metrics")]
+        {
+            if let Some(path) = &self.path {
+                counter!("unix_fifo_messages_written_total", 1, "path" => path.to_string());
+            }
+        }
+        record_bytes(n as u64);
+    }
+}
+
+/// Runs `fut` in a span for the operation `op` on the pipe at `path`.
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+pub(crate) async fn traced<F: Future>(op: &'static str, path: &Path, fut: F) -> F::Output {
+    #[cfg(feature = "tracing")]
+    let fut = {
+        use tracing::Instrument;
+        let span = tracing::debug_span!(
+            "fifo",
+            op,
+            path = %path.display(),
+            bytes = tracing::field::Empty
+        );
+        fut.instrument(span)
+    };
+    fut.await
+}
+
+/// Records the number of bytes transferred on the current operation's span.
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+pub(crate) fn record_bytes(n: u64) {
+    #[cfg(feature = "tracing")]
+    tracing::Span::current().record("bytes", n);
+}
@@ -0,0 +1,309 @@
+//! Publishing messages by topic to any number of subscribers.
+//!
+//! Publishers and subscribers agree on a directory. Every
+//! [`Subscription`](struct.Subscription.html) creates its own pipe in a
+//! subdirectory named after the topic, and the
+//! [`Publisher`](struct.Publisher.html) writes each message to all pipes it
+//! finds there:
+//!
+//! ```no_run
+//! # fn main() -> async_std::io::Result<()> { async_std::task::block_on(async {
+//! use unix_fifo_async::pubsub::{Publisher, Subscriber};
+//!
+//! // In every subscriber:
+//! let mut theme = Subscriber::new("/run/user/1000/desktop").subscribe("theme")?;
+//! // In the publisher:
+//! let publisher = Publisher::new("/run/user/1000/desktop");
+//! let reached = publisher.publish("theme", b"dark").await?;
+//! // Back in the subscriber:
+//! assert_eq!(theme.recv().await?, b"dark");
+//! # Ok(())
+//! # })}
+//! ```
+//!
+//! Messages are sent as chunked [frames](../frame/index.html), so several
+//! publishers can publish to the same topic at once. Messages published
+//! before a subscription was created aren't delivered to it, and neither
+//! are messages a subscriber is too slow to take in (see
+//! [`Publisher::send_timeout`](struct.Publisher.html#method.send_timeout)).
+use crate::{
+    frame::{FramedReader, FramedWriter},
+    util::nix_to_io,
+    NamedPipePath, OpenWriter,
+};
+use async_std::{io, stream::Stream, sync::Mutex};
+use nix::{
+    errno::Errno,
+    fcntl::OFlag,
+    libc,
+    sys::{signal, stat::Mode},
+    unistd::Pid,
+};
+use std::os::unix::{fs::FileTypeExt, io::IntoRawFd};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{
+    atomic::{AtomicU32, Ordering},
+    Arc,
+};
+use std::time::Duration;
+
+/// How long `publish` spends on one subscriber before skipping it.
+const DEFAULT_SEND_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Returns the directory for `topic`, which has to be a plain file name.
+fn topic_dir(dir: &Path, topic: &str) -> io::Result<PathBuf> {
+    if topic.is_empty() || topic.starts_with('.') || topic.contains('/') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "topic must be a file name that doesn't start with a dot",
+        ));
+    }
+    Ok(dir.join(topic))
+}
+
+/// Checks whether the hidden pipe `name` belongs to a subscription whose
+/// process died while setting it up.
+///
+/// Setting up only takes a moment, so a process that still exists is
+/// assumed to be about to finish. (If the PID was reused, the pipe is
+/// left alone until that process is gone too.)
+fn setup_abandoned(name: &str) -> bool {
+    let pid = name[1..].split('.').next().and_then(|pid| pid.parse().ok());
+    match pid {
+        Some(pid) => signal::kill(Pid::from_raw(pid), None) == Err(nix::Error::Sys(Errno::ESRCH)),
+        None => false,
+    }
+}
+
+/// Publishes messages to the subscribers of a directory.
+#[derive(Debug, Clone)]
+pub struct Publisher {
+    dir: PathBuf,
+    send_timeout: Duration,
+}
+
+impl Publisher {
+    /// Creates a publisher for the subscribers in `dir`.
+    pub fn new<T: Into<PathBuf>>(dir: T) -> Self {
+        Self {
+            dir: dir.into(),
+            send_timeout: DEFAULT_SEND_TIMEOUT,
+        }
+    }
+    /// Sets how long [`publish`](#method.publish) may spend sending to one
+    /// subscriber, e.g. while its pipe is full, before skipping it.
+    /// Defaults to one second.
+    ///
+    /// A skipped subscriber misses the message. If it already got some
+    /// chunks of it, it drops them as an incomplete message; chunks are
+    /// written atomically, so it never sees a torn frame.
+    pub fn send_timeout(mut self, timeout: Duration) -> Self {
+        self.send_timeout = timeout;
+        self
+    }
+    /// Sends `message` to every current subscriber of `topic` and returns
+    /// how many of them it reached.
+    ///
+    /// Subscribers are written to one after another, so one that doesn't
+    /// keep up holds up the rest for at most the
+    /// [send timeout](#method.send_timeout), after which it's skipped.
+    /// Pipes left behind by subscribers that went away without cleaning up
+    /// are deleted, including ones whose process died while setting them
+    /// up.
+    pub async fn publish(&self, topic: &str, message: &[u8]) -> io::Result<usize> {
+        let dir = topic_dir(&self.dir, topic)?;
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            // Nobody ever subscribed
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e),
+        };
+        let mut reached = 0;
+        for entry in entries {
+            let entry = entry?;
+            if !entry.file_type()?.is_fifo() {
+                continue;
+            }
+            // Subscriptions that are still being set up have hidden names
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if name.starts_with('.') {
+                if setup_abandoned(&name) {
+                    let _ = std::fs::remove_file(entry.path());
+                }
+                continue;
+            }
+            let pipe = NamedPipePath::new(entry.path());
+            let writer = match pipe.open_file(OFlag::O_WRONLY | OFlag::O_NONBLOCK) {
+                // Safety: the file was just opened and is handed over.
+                Ok(file) => unsafe { OpenWriter::from_raw_fd(file.into_raw_fd())? },
+                Err(e) if e.raw_os_error() == Some(libc::ENXIO) => {
+                    // Nobody is reading, so the subscriber is gone
+                    let _ = pipe.delete().await;
+                    continue;
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            let mut writer = FramedWriter::new(writer).chunked(true);
+            match io::timeout(self.send_timeout, writer.send(message)).await {
+                Ok(()) => reached += 1,
+                // The subscriber went away in the meantime
+                Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {}
+                // The subscriber doesn't keep up
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(reached)
+    }
+}
+
+/// Creates subscriptions in a directory shared with publishers.
+#[derive(Debug, Clone)]
+pub struct Subscriber {
+    dir: PathBuf,
+}
+
+impl Subscriber {
+    /// Creates a subscriber for the publishers of `dir`.
+    pub fn new<T: Into<PathBuf>>(dir: T) -> Self {
+        Self { dir: dir.into() }
+    }
+    /// Subscribes to `topic`, creating the directories and a pipe for this
+    /// subscription.
+    ///
+    /// Directories are created with permission bits `0o700`, so only the
+    /// current user can publish unless they were created beforehand.
+    pub fn subscribe(&self, topic: &str) -> io::Result<Subscription> {
+        static COUNTER: AtomicU32 = AtomicU32::new(0);
+        let dir = topic_dir(&self.dir, topic)?;
+        let name = format!(
+            "{}.{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        );
+        // Set up the pipe under a hidden name, so publishers don't mistake
+        // it for a stale one before it's opened
+        let setup = NamedPipePath::new(dir.join(format!(".{}", name)));
+        setup.create_parents(Mode::S_IRWXU)?;
+        setup.ensure_exists().map_err(nix_to_io)?;
+        let reader = match setup.open_read().hold_open(true).open_sync() {
+            Ok(reader) => reader,
+            Err(e) => {
+                let _ = std::fs::remove_file(setup.as_path());
+                return Err(e);
+            }
+        };
+        let path = dir.join(name);
+        std::fs::rename(setup.as_path(), &path)?;
+        Ok(Subscription {
+            reader: FramedReader::new(reader),
+            path: RemoveOnDrop(path),
+        })
+    }
+}
+
+/// A subscription to a topic, created by
+/// [`Subscriber::subscribe`](struct.Subscriber.html#method.subscribe).
+///
+/// Its pipe is deleted when this is dropped.
+pub struct Subscription {
+    reader: FramedReader,
+    path: RemoveOnDrop,
+}
+
+/// Deletes the pipe of a subscription when dropped.
+struct RemoveOnDrop(PathBuf);
+
+impl Drop for RemoveOnDrop {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+impl Subscription {
+    /// Returns the path of the pipe for this subscription.
+    pub fn path(&self) -> &Path {
+        &self.path.0
+    }
+    /// Rejects messages larger than `limit` bytes with an
+    /// [`error::MessageTooLarge`](../error/struct.MessageTooLarge.html).
+    pub fn max_message_size(mut self, limit: usize) -> Self {
+        self.reader = self.reader.max_message_size(limit);
+        self
+    }
+    /// Receives the next message published to the topic.
+    ///
+    /// This is cancel-safe, like
+    /// [`FramedReader::recv`](../frame/struct.FramedReader.html#method.recv).
+    pub async fn recv(&mut self) -> io::Result<Vec<u8>> {
+        // The pipe is held open, so it never reports EOF
+        self.reader
+            .recv()
+            .await?
+            .ok_or_else(|| io::ErrorKind::UnexpectedEof.into())
+    }
+    /// Turns the subscription into a never-ending stream of messages.
+    pub fn into_stream(self) -> Pin<Box<dyn Stream<Item = io::Result<Vec<u8>>> + Send>> {
+        // The stream only ever polls one of these futures at a time, so the
+        // lock is never contended.
+        let subscription = Arc::new(Mutex::new(self));
+        Box::pin(async_std::stream::from_fn(move || {
+            let subscription = subscription.clone();
+            async move { Some(subscription.lock().await.recv().await) }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Publisher, Subscriber};
+    use crate::NamedPipePath;
+    use async_std::{io, prelude::*, task};
+    use std::process::Command;
+    use std::time::Duration;
+    #[test]
+    fn publish_to_subscribers() -> io::Result<()> {
+        task::block_on(async {
+            let dir = "./test_dir_44";
+            let publisher = Publisher::new(dir);
+            assert_eq!(publisher.publish("news", b"nobody").await?, 0);
+            let subscriber = Subscriber::new(dir);
+            let mut first = subscriber.subscribe("news")?;
+            let second = subscriber.subscribe("news")?;
+            let mut other = subscriber.subscribe("weather")?;
+            // A pipe left behind by a subscriber that crashed
+            let stale = NamedPipePath::new("./test_dir_44/news/1.0");
+            stale.ensure_exists().unwrap();
+            assert!(Subscriber::new(dir).subscribe("../news").is_err());
+
+            assert_eq!(publisher.publish("news", b"hello").await?, 2);
+            assert!(!stale.exists());
+            assert_eq!(publisher.publish("weather", b"rain").await?, 1);
+            assert_eq!(first.recv().await?, b"hello");
+            assert_eq!(other.recv().await?, b"rain");
+            let mut second = second.into_stream();
+            assert_eq!(second.next().await.unwrap()?, b"hello");
+
+            drop(second);
+            assert_eq!(publisher.publish("news", b"again").await?, 1);
+            assert_eq!(first.recv().await?, b"again");
+
+            // A subscriber that doesn't read is skipped once its pipe is full
+            let publisher = publisher.send_timeout(Duration::from_millis(20));
+            assert_eq!(publisher.publish("news", &[0; 128 * 1024]).await?, 0);
+            // So is a subscription whose process died while setting it up
+            let mut child = Command::new("true").spawn()?;
+            let name = format!("./test_dir_44/news/.{}.0", child.id());
+            child.wait()?;
+            let abandoned = NamedPipePath::new(name);
+            abandoned.ensure_exists().unwrap();
+            publisher.publish("news", b"later").await?;
+            assert!(!abandoned.exists());
+            drop((first, other));
+            std::fs::remove_dir_all(dir)
+        })
+    }
+}
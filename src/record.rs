@@ -0,0 +1,303 @@
+//! Recording pipe traffic to a file and replaying it later.
+//!
+//! Wrap either end of a pipe in a [`Recorder`](struct.Recorder.html) to
+//! mirror everything that passes through it into a log, then feed the log
+//! back into a pipe with a [`Replayer`](struct.Replayer.html) to reproduce
+//! the session offline:
+//!
+//! ```no_run
+//! # fn main() -> async_std::io::Result<()> { async_std::task::block_on(async {
+//! use unix_fifo_async::record::{Recorder, Replayer};
+//! use unix_fifo_async::NamedPipePath;
+//! use async_std::prelude::*;
+//!
+//! let pipe = NamedPipePath::new("./requests");
+//! let mut reader = Recorder::new(pipe.open_read().open().await?, "./requests.rec")?;
+//! let mut request = Vec::new();
+//! reader.read_to_end(&mut request).await?;
+//! reader.finish()?;
+//!
+//! // ... later, with the same program reading `./requests` again:
+//! Replayer::load("./requests.rec").await?
+//!     .speed(10.0)
+//!     .replay(&pipe.open_write())
+//!     .await?;
+//! # Ok(())
+//! # })}
+//! ```
+//!
+//! The log starts with the magic bytes `FIFOREC1`, followed by one record
+//! per read or write: the time since the recording started in microseconds
+//! (`u64`), the length of the data (`u32`), both little-endian, and the
+//! data itself.
+use crate::NamedPipeWriter;
+use async_std::{io, prelude::*, task};
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{BufWriter, Write as _};
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+/// Identifies a recording and its format version.
+const MAGIC: &[u8; 8] = b"FIFOREC1";
+
+/// Mirrors everything read from or written to `T` into a log file.
+///
+/// Implements `Read` if `T` does and `Write` if `T` does, so it can stand in
+/// for [`OpenReader`](../struct.OpenReader.html) and
+/// [`OpenWriter`](../struct.OpenWriter.html). Writing the log blocks, which
+/// is fine for debugging but not meant for production use.
+///
+/// Failing to write the log doesn't fail the read or write it happened in,
+/// since the data already went through the pipe. Recording stops then, and
+/// the error is returned by the next read, flush or close, or by
+/// [`finish`](#method.finish).
+pub struct Recorder<T> {
+    inner: T,
+    log: BufWriter<File>,
+    start: Instant,
+    /// Set once writing the log failed, so the rest isn't appended to a
+    /// log that's missing something.
+    log_failed: bool,
+    /// Why writing the log failed, until it's reported.
+    log_error: Option<io::Error>,
+}
+
+impl<T> Recorder<T> {
+    /// Wraps `inner`, creating (or truncating) the log at `log_path`.
+    pub fn new<P: AsRef<Path>>(inner: T, log_path: P) -> io::Result<Self> {
+        let mut log = BufWriter::new(File::create(log_path)?);
+        log.write_all(MAGIC)?;
+        Ok(Self {
+            inner,
+            log,
+            start: Instant::now(),
+            log_failed: false,
+            log_error: None,
+        })
+    }
+    /// Gets a reference to the wrapped pipe end.
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+    /// Flushes the log and unwraps the pipe end.
+    pub fn finish(mut self) -> io::Result<T> {
+        self.flush_log()?;
+        Ok(self.inner)
+    }
+    /// Records `data`, keeping the error for later if that fails.
+    fn record(&mut self, data: &[u8]) {
+        if self.log_failed {
+            return;
+        }
+        if let Err(e) = self.write_record(data) {
+            self.log_failed = true;
+            self.log_error = Some(e);
+        }
+    }
+    fn write_record(&mut self, data: &[u8]) -> io::Result<()> {
+        let elapsed = self.start.elapsed().as_micros() as u64;
+        let len: u32 = data
+            .len()
+            .try_into()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "chunk too large"))?;
+        self.log.write_all(&elapsed.to_le_bytes())?;
+        self.log.write_all(&len.to_le_bytes())?;
+        self.log.write_all(data)
+    }
+    /// Flushes the log, or reports why writing it failed earlier.
+    fn flush_log(&mut self) -> io::Result<()> {
+        if let Some(e) = self.log_error.take() {
+            return Err(e);
+        }
+        if self.log_failed {
+            return Ok(());
+        }
+        self.log.flush()
+    }
+}
+
+impl<T: io::Read + Unpin> io::Read for Recorder<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if let Some(e) = this.log_error.take() {
+            return Poll::Ready(Err(e));
+        }
+        let n = task::ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        if n > 0 {
+            this.record(&buf[..n]);
+        }
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl<T: io::Write + Unpin> io::Write for Recorder<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let n = task::ready!(Pin::new(&mut this.inner).poll_write(cx, buf))?;
+        if n > 0 {
+            this.record(&buf[..n]);
+        }
+        Poll::Ready(Ok(n))
+    }
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        this.flush_log()?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        this.flush_log()?;
+        Pin::new(&mut this.inner).poll_close(cx)
+    }
+}
+
+/// Plays back a log written by a [`Recorder`](struct.Recorder.html).
+pub struct Replayer {
+    records: Vec<(Duration, Vec<u8>)>,
+    speed: f64,
+}
+
+impl Replayer {
+    /// Loads the recording at `log_path`.
+    ///
+    /// Fails with `InvalidData` if the file isn't a recording or is
+    /// truncated.
+    pub async fn load<P: AsRef<Path>>(log_path: P) -> io::Result<Self> {
+        let data = async_std::fs::read(log_path.as_ref()).await?;
+        Self::parse(&data)
+    }
+    fn parse(data: &[u8]) -> io::Result<Self> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg);
+        if !data.starts_with(MAGIC) {
+            return Err(invalid("not a pipe recording"));
+        }
+        let mut rest = &data[MAGIC.len()..];
+        let mut records = Vec::new();
+        while !rest.is_empty() {
+            if rest.len() < 12 {
+                return Err(invalid("truncated record header"));
+            }
+            let (header, tail) = rest.split_at(12);
+            let micros = u64::from_le_bytes(header[..8].try_into().unwrap());
+            let len = u32::from_le_bytes(header[8..].try_into().unwrap()) as usize;
+            if tail.len() < len {
+                return Err(invalid("truncated record data"));
+            }
+            let (chunk, tail) = tail.split_at(len);
+            records.push((Duration::from_micros(micros), chunk.to_vec()));
+            rest = tail;
+        }
+        Ok(Self {
+            records,
+            speed: 1.0,
+        })
+    }
+    /// Replays the recording `factor` times as fast as it was recorded.
+    ///
+    /// Use `f64::INFINITY` to send everything without any delays.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `factor` isn't positive.
+    pub fn speed(mut self, factor: f64) -> Self {
+        assert!(factor > 0.0, "speed must be positive");
+        self.speed = factor;
+        self
+    }
+    /// Returns the recorded chunks, with their offsets from the start of
+    /// the recording.
+    pub fn records(&self) -> &[(Duration, Vec<u8>)] {
+        &self.records
+    }
+    /// Writes the recording to `writer`, keeping the original timing
+    /// (adjusted by [`speed`](#method.speed)).
+    ///
+    /// Returns the number of bytes written.
+    pub async fn replay_into<W: io::Write + Unpin>(&self, writer: &mut W) -> io::Result<u64> {
+        let start = Instant::now();
+        let mut total = 0;
+        for (offset, data) in &self.records {
+            let due = offset.div_f64(self.speed);
+            if let Some(delay) = due.checked_sub(start.elapsed()) {
+                task::sleep(delay).await;
+            }
+            writer.write_all(data).await?;
+            total += data.len() as u64;
+        }
+        writer.flush().await?;
+        Ok(total)
+    }
+    /// Opens `pipe` and writes the recording to it, closing the pipe
+    /// afterwards.
+    pub async fn replay(&self, pipe: &NamedPipeWriter) -> io::Result<u64> {
+        let mut writer = pipe.open().await?;
+        self.replay_into(&mut writer).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Recorder, Replayer};
+    use crate::NamedPipePath;
+    use async_std::{io, prelude::*, task};
+    #[test]
+    fn record_and_replay() -> io::Result<()> {
+        task::block_on(async {
+            let pipe = NamedPipePath::new("./test_pipe_29");
+            pipe.ensure_exists().unwrap();
+            let reader = pipe.open_read();
+            let t_read = task::spawn(async move { reader.read_string().await });
+            let writer = pipe.open_write().open().await?;
+            let mut writer = Recorder::new(writer, "./test_pipe_29.rec")?;
+            writer.write_all(b"Hello ").await?;
+            writer.write_all(b"pipe").await?;
+            drop(writer.finish()?);
+            assert_eq!(t_read.await?, "Hello pipe");
+
+            let replayer = Replayer::load("./test_pipe_29.rec")
+                .await?
+                .speed(f64::INFINITY);
+            assert_eq!(replayer.records().len(), 2);
+            let reader = pipe.open_read();
+            let t_read = task::spawn(async move { reader.read_string().await });
+            assert_eq!(replayer.replay(&pipe.open_write()).await?, 10);
+            assert_eq!(t_read.await?, "Hello pipe");
+            async_std::fs::remove_file("./test_pipe_29.rec").await?;
+            pipe.delete().await
+        })
+    }
+    #[test]
+    fn log_failure_keeps_written_data() -> io::Result<()> {
+        use crate::{OpenReader, OpenWriter};
+        task::block_on(async {
+            let (read_fd, write_fd) = nix::unistd::pipe().unwrap();
+            let mut reader = unsafe { OpenReader::from_raw_fd(read_fd)? };
+            let writer = unsafe { OpenWriter::from_raw_fd(write_fd)? };
+            // Every write to /dev/full fails with ENOSPC; this is more than
+            // the log buffers, so recording it fails right away
+            let mut writer = Recorder::new(writer, "/dev/full")?;
+            let n = writer.write(&[1; 16 * 1024]).await?;
+            assert!(n > 0);
+            let err = writer.flush().await.unwrap_err();
+            assert_eq!(err.raw_os_error(), Some(nix::libc::ENOSPC));
+            // Reported once; the data still went through
+            writer.flush().await?;
+            drop(writer);
+            let mut received = Vec::new();
+            reader.read_to_end(&mut received).await?;
+            assert_eq!(received.len(), n);
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,196 @@
+//! Continuously forwarding data from one named pipe to another.
+//!
+//! ```no_run
+//! # fn main() -> async_std::io::Result<()> { async_std::task::block_on(async {
+//! use unix_fifo_async::relay::PipeRelay;
+//!
+//! let relay = PipeRelay::new("./requests", "./requests_upper")
+//!     .with_transform(|msg| msg.to_ascii_uppercase())
+//!     .spawn();
+//! // ... later:
+//! relay.shutdown().await?;
+//! # Ok(())
+//! # })}
+//! ```
+use crate::{
+    util::{is_transient, nix_to_io},
+    NamedPipePath, ShutdownToken,
+};
+use async_std::{
+    io,
+    task::{self, JoinHandle},
+};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Initial delay before the relay retries after an error.
+const RETRY_MIN: Duration = Duration::from_millis(10);
+/// Upper bound for the exponential backoff between retries.
+const RETRY_MAX: Duration = Duration::from_secs(1);
+
+type Transform = Arc<dyn Fn(Vec<u8>) -> Vec<u8> + Send + Sync>;
+
+/// Forwards every message written to a source pipe to a destination pipe.
+///
+/// A message is everything a writer sends before closing the source pipe.
+/// Both pipes are created if they don't exist, and transient errors (e.g. a
+/// pipe being deleted under the relay) are retried with a backoff until the
+/// relay is shut down. Other errors, like not being allowed to open a pipe,
+/// stop the relay and are returned by its
+/// [`RelayHandle`](struct.RelayHandle.html).
+pub struct PipeRelay {
+    source: NamedPipePath,
+    dest: NamedPipePath,
+    transform: Option<Transform>,
+    shutdown: ShutdownToken,
+}
+
+impl PipeRelay {
+    /// Creates a relay from the pipe at `source` to the pipe at `dest`.
+    pub fn new<S: Into<PathBuf>, D: Into<PathBuf>>(source: S, dest: D) -> Self {
+        Self {
+            source: NamedPipePath::new(source),
+            dest: NamedPipePath::new(dest),
+            transform: None,
+            shutdown: ShutdownToken::new(),
+        }
+    }
+    /// Stops the relay when `token` fires, in addition to
+    /// [`RelayHandle::shutdown`](struct.RelayHandle.html#method.shutdown).
+    pub fn with_shutdown(mut self, token: ShutdownToken) -> Self {
+        self.shutdown = token;
+        self
+    }
+    /// Applies `transform` to every message before forwarding it.
+    ///
+    /// Messages the transform turns into empty buffers are dropped.
+    pub fn with_transform<F>(mut self, transform: F) -> Self
+    where
+        F: Fn(Vec<u8>) -> Vec<u8> + Send + Sync + 'static,
+    {
+        self.transform = Some(Arc::new(transform));
+        self
+    }
+    /// Applies `transform` to every line of a message before forwarding it.
+    ///
+    /// Lines are split on `\n`, which is kept in the output. Invalid UTF-8 is
+    /// replaced with U+FFFD before the transform is called.
+    pub fn with_line_transform<F>(self, transform: F) -> Self
+    where
+        F: Fn(&str) -> String + Send + Sync + 'static,
+    {
+        self.with_transform(move |msg| {
+            let mut out = Vec::with_capacity(msg.len());
+            for line in msg.split_inclusive(|&b| b == b'\n') {
+                let (line, newline) = match line.split_last() {
+                    Some((b'\n', line)) => (line, true),
+                    _ => (line, false),
+                };
+                out.extend(transform(&String::from_utf8_lossy(line)).into_bytes());
+                if newline {
+                    out.push(b'\n');
+                }
+            }
+            out
+        })
+    }
+    /// Starts the relay in a new task.
+    pub fn spawn(self) -> RelayHandle {
+        let shutdown = self.shutdown.clone();
+        let task = task::spawn(self.run());
+        RelayHandle { shutdown, task }
+    }
+    async fn forward_one(&self) -> io::Result<()> {
+        self.source.ensure_exists().map_err(nix_to_io)?;
+        let msg = self.source.open_read().read().await?;
+        let msg = match &self.transform {
+            Some(transform) => transform(msg),
+            None => msg,
+        };
+        if msg.is_empty() {
+            return Ok(());
+        }
+        self.dest.ensure_exists().map_err(nix_to_io)?;
+        self.dest.open_write().write(&msg).await
+    }
+    async fn run(self) -> io::Result<()> {
+        let stop = &self.shutdown;
+        let mut delay = RETRY_MIN;
+        loop {
+            match stop.run_until(self.forward_one()).await {
+                None => return Ok(()),
+                Some(Ok(())) => delay = RETRY_MIN,
+                Some(Err(e)) if !is_transient(&e) => return Err(e),
+                Some(Err(_)) => {
+                    if stop.run_until(task::sleep(delay)).await.is_none() {
+                        return Ok(());
+                    }
+                    delay = (delay * 2).min(RETRY_MAX);
+                }
+            }
+        }
+    }
+}
+
+/// A handle to a running [`PipeRelay`](struct.PipeRelay.html).
+pub struct RelayHandle {
+    shutdown: ShutdownToken,
+    task: JoinHandle<io::Result<()>>,
+}
+
+impl RelayHandle {
+    /// Stops the relay, interrupting any read or write in progress.
+    ///
+    /// A message that was only partially forwarded is lost. Returns the
+    /// error the relay stopped at before, if any.
+    pub async fn shutdown(self) -> io::Result<()> {
+        self.shutdown.shutdown();
+        self.task.await
+    }
+    /// Waits for the relay to stop by itself, i.e. because of an error that
+    /// retrying won't fix or because the token passed to
+    /// [`PipeRelay::with_shutdown`](struct.PipeRelay.html#method.with_shutdown)
+    /// fired.
+    pub async fn join(self) -> io::Result<()> {
+        self.task.await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PipeRelay;
+    use crate::NamedPipePath;
+    use async_std::{io, task};
+    #[test]
+    fn relay_with_transform() -> io::Result<()> {
+        task::block_on(async {
+            let source = NamedPipePath::new("./test_pipe_13");
+            let dest = NamedPipePath::new("./test_pipe_14");
+            source.ensure_exists().unwrap();
+            dest.ensure_exists().unwrap();
+            let relay = PipeRelay::new("./test_pipe_13", "./test_pipe_14")
+                .with_line_transform(|line| line.to_uppercase())
+                .spawn();
+            let reader = dest.open_read();
+            let t_read = task::spawn(async move { reader.read_string().await });
+            source.open_write().write_str("hello\npipe\n").await?;
+            assert_eq!(t_read.await?, "HELLO\nPIPE\n");
+            relay.shutdown().await?;
+            source.delete().await?;
+            dest.delete().await
+        })
+    }
+    #[test]
+    fn relay_stops_on_permanent_error() -> io::Result<()> {
+        task::block_on(async {
+            // A directory can't be read like a pipe, no matter how often
+            // it's tried
+            let source = "./test_dir_56";
+            std::fs::create_dir_all(source)?;
+            let relay = PipeRelay::new(source, "./test_pipe_56").spawn();
+            assert!(relay.join().await.is_err());
+            std::fs::remove_dir(source)
+        })
+    }
+}
@@ -0,0 +1,215 @@
+//! Retrying pipe operations that fail with transient errors.
+//!
+//! Pipes tend to fail in ways that go away on their own: the pipe hasn't
+//! been created by the other process yet, a signal interrupted a syscall,
+//! and so on. A [`RetryPolicy`](struct.RetryPolicy.html) retries those
+//! failures with an exponential backoff:
+//!
+//! ```no_run
+//! # fn main() -> async_std::io::Result<()> { async_std::task::block_on(async {
+//! use std::time::Duration;
+//! use unix_fifo_async::retry::RetryPolicy;
+//! use unix_fifo_async::NamedPipePath;
+//!
+//! let policy = RetryPolicy::new()
+//!     .max_attempts(10)
+//!     .backoff(Duration::from_millis(50), Duration::from_secs(2));
+//! let writer = NamedPipePath::new("./created_by_someone_else").open_write();
+//! writer.with_retry(policy).write_str("Hello pipe").await?;
+//! # Ok(())
+//! # })}
+//! ```
+use crate::{NamedPipeReader, NamedPipeWriter, OpenReader, OpenWriter};
+use async_std::{io, task};
+use nix::libc;
+use std::future::Future;
+use std::time::Duration;
+
+/// Decides which failed operations are tried again, how often, and how
+/// long to wait in between.
+///
+/// By default, an operation is attempted up to 5 times, waiting 10ms
+/// before the first retry and doubling the delay up to 1s. `EINTR`
+/// (`Interrupted`), `ENOENT` (`NotFound`) and `ENXIO` (no reader on the
+/// other end) are retried; any other error is returned right away.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    kinds: Vec<io::ErrorKind>,
+    os_errors: Vec<i32>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_secs(1),
+            kinds: vec![io::ErrorKind::Interrupted, io::ErrorKind::NotFound],
+            os_errors: vec![libc::ENXIO],
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Creates the default policy.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Creates a policy that doesn't retry anything, to start from when
+    /// adding retryable errors with `retry_on` and `retry_on_os_error`.
+    pub fn none() -> Self {
+        Self {
+            kinds: Vec::new(),
+            os_errors: Vec::new(),
+            ..Self::default()
+        }
+    }
+    /// Sets how often an operation is attempted in total, including the
+    /// first attempt.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `attempts` is 0.
+    pub fn max_attempts(mut self, attempts: u32) -> Self {
+        assert!(attempts > 0, "max_attempts must be at least 1");
+        self.max_attempts = attempts;
+        self
+    }
+    /// Sets the delay before the first retry, which doubles with every
+    /// further retry up to `max`.
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max.max(initial);
+        self
+    }
+    /// Also retries errors of the given kind.
+    pub fn retry_on(mut self, kind: io::ErrorKind) -> Self {
+        self.kinds.push(kind);
+        self
+    }
+    /// Also retries errors with the given `errno`, e.g. `libc::EAGAIN`.
+    pub fn retry_on_os_error(mut self, errno: i32) -> Self {
+        self.os_errors.push(errno);
+        self
+    }
+    /// Checks whether `error` is worth another attempt.
+    pub fn is_retryable(&self, error: &io::Error) -> bool {
+        self.kinds.contains(&error.kind())
+            || error
+                .raw_os_error()
+                .is_some_and(|errno| self.os_errors.contains(&errno))
+    }
+    /// Runs `op` until it succeeds, fails with an error that isn't
+    /// retryable, or runs out of attempts.
+    ///
+    /// Returns the error of the last attempt on failure.
+    pub async fn run<T, F, Fut>(&self, mut op: F) -> io::Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = io::Result<T>>,
+    {
+        let mut delay = self.initial_backoff;
+        let mut attempt = 1;
+        loop {
+            match op().await {
+                Err(e) if attempt < self.max_attempts && self.is_retryable(&e) => {
+                    task::sleep(delay).await;
+                    delay = (delay * 2).min(self.max_backoff);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// A reader or writer whose operations are retried according to a
+/// [`RetryPolicy`](struct.RetryPolicy.html).
+///
+/// Created by `with_retry` on
+/// [`NamedPipeReader`](../struct.NamedPipeReader.html#method.with_retry) and
+/// [`NamedPipeWriter`](../struct.NamedPipeWriter.html#method.with_retry).
+/// Every retry starts the whole operation over, opening the pipe again.
+pub struct Retry<'a, T> {
+    inner: &'a T,
+    policy: RetryPolicy,
+}
+
+impl<'a, T> Retry<'a, T> {
+    pub(crate) fn new(inner: &'a T, policy: RetryPolicy) -> Self {
+        Self { inner, policy }
+    }
+    /// Returns the policy operations are retried with.
+    pub fn policy(&self) -> &RetryPolicy {
+        &self.policy
+    }
+}
+
+impl Retry<'_, NamedPipeReader> {
+    /// Opens the reading end of the pipe, like
+    /// [`NamedPipeReader::open`](../struct.NamedPipeReader.html#method.open).
+    pub async fn open(&self) -> io::Result<OpenReader> {
+        self.policy.run(|| self.inner.open()).await
+    }
+    /// Reads all bytes from the pipe, like
+    /// [`NamedPipeReader::read`](../struct.NamedPipeReader.html#method.read).
+    pub async fn read(&self) -> io::Result<Vec<u8>> {
+        self.policy.run(|| self.inner.read()).await
+    }
+    /// Reads a String from the pipe, like
+    /// [`NamedPipeReader::read_string`](../struct.NamedPipeReader.html#method.read_string).
+    pub async fn read_string(&self) -> io::Result<String> {
+        self.policy.run(|| self.inner.read_string()).await
+    }
+}
+
+impl Retry<'_, NamedPipeWriter> {
+    /// Opens the writing end of the pipe, like
+    /// [`NamedPipeWriter::open`](../struct.NamedPipeWriter.html#method.open).
+    pub async fn open(&self) -> io::Result<OpenWriter> {
+        self.policy.run(|| self.inner.open()).await
+    }
+    /// Writes byte data to the pipe, like
+    /// [`NamedPipeWriter::write`](../struct.NamedPipeWriter.html#method.write).
+    pub async fn write(&self, data: &[u8]) -> io::Result<()> {
+        self.policy.run(|| self.inner.write(data)).await
+    }
+    /// Writes &str data to the pipe, like
+    /// [`NamedPipeWriter::write_str`](../struct.NamedPipeWriter.html#method.write_str).
+    pub async fn write_str(&self, data: &str) -> io::Result<()> {
+        self.policy.run(|| self.inner.write_str(data)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RetryPolicy;
+    use crate::NamedPipePath;
+    use async_std::{io, task};
+    use std::time::Duration;
+    #[test]
+    fn retry_until_pipe_exists() -> io::Result<()> {
+        task::block_on(async {
+            let pipe = NamedPipePath::new("./test_pipe_35");
+            let policy = RetryPolicy::new()
+                .max_attempts(2)
+                .backoff(Duration::from_millis(1), Duration::from_millis(1));
+            let reader = pipe.open_read();
+            let err = reader.with_retry(policy).read().await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::NotFound);
+
+            let policy = RetryPolicy::new()
+                .max_attempts(100)
+                .backoff(Duration::from_millis(10), Duration::from_millis(10));
+            let t_read = task::spawn(async move { reader.with_retry(policy).read_string().await });
+            task::sleep(Duration::from_millis(50)).await;
+            pipe.ensure_exists().unwrap();
+            pipe.open_write().write_str("Hello pipe").await?;
+            assert_eq!(t_read.await?, "Hello pipe");
+            pipe.delete().await
+        })
+    }
+}
@@ -0,0 +1,215 @@
+use crate::{util::nix_to_io, OpenReader};
+use async_std::{future, io};
+use nix::poll::{poll, PollFd, PollFlags};
+use std::os::unix::io::AsRawFd;
+use std::task::{Context, Poll};
+
+struct Entry<K> {
+    key: K,
+    priority: i32,
+    reader: OpenReader,
+    /// How many times in a row this was ready but passed over.
+    skipped: u32,
+}
+
+/// Waits on several pipes at once, always serving the readable pipe with
+/// the highest priority first.
+///
+/// Unlike racing reads, which picks whichever pipe happens to be polled
+/// first, this checks all pipes before choosing one, so e.g. a control pipe
+/// is always served before a busy data pipe:
+///
+/// ```no_run
+/// # fn main() -> async_std::io::Result<()> { async_std::task::block_on(async {
+/// use unix_fifo_async::{NamedPipePath, PrioritySelect};
+///
+/// let mut select = PrioritySelect::new().starvation_limit(16);
+/// select.insert("control", 10, NamedPipePath::new("./control").open_read().open().await?);
+/// select.insert("data", 0, NamedPipePath::new("./data").open_read().open().await?);
+/// let (key, reader) = select.select().await?;
+/// let line = reader.read_until(b'\n').await?;
+/// println!("{}: {:?}", key, line);
+/// # Ok(())
+/// # })}
+/// ```
+///
+/// A pipe whose writers all went away stays readable, since reading from it
+/// reports EOF; [`remove`](#method.remove) it once that happens.
+pub struct PrioritySelect<K> {
+    entries: Vec<Entry<K>>,
+    starvation_limit: Option<u32>,
+}
+
+impl<K> PrioritySelect<K> {
+    /// Creates an empty set of pipes without starvation protection.
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            starvation_limit: None,
+        }
+    }
+    /// Serves a readable pipe anyway once it's been passed over for
+    /// higher-priority pipes `rounds` times in a row.
+    ///
+    /// Without this, a pipe is only served when no pipe with a higher
+    /// priority is readable.
+    pub fn starvation_limit(mut self, rounds: u32) -> Self {
+        self.starvation_limit = Some(rounds);
+        self
+    }
+    /// Adds `reader` under `key` with the given `priority`; higher numbers
+    /// are served first.
+    ///
+    /// Readable pipes with the same priority take turns.
+    pub fn insert(&mut self, key: K, priority: i32, reader: OpenReader) {
+        self.entries.push(Entry {
+            key,
+            priority,
+            reader,
+            skipped: 0,
+        });
+    }
+    /// Removes the pipe added under `key` and returns it.
+    pub fn remove(&mut self, key: &K) -> Option<OpenReader>
+    where
+        K: PartialEq,
+    {
+        let index = self.entries.iter().position(|entry| entry.key == *key)?;
+        Some(self.entries.remove(index).reader)
+    }
+    /// Returns the number of pipes.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    /// Checks if there are no pipes.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+    /// Waits until at least one of the pipes is readable and returns the
+    /// one to read from next, along with its key.
+    ///
+    /// Fails with `InvalidInput` if there are no pipes. This doesn't read
+    /// anything, so it's cancel-safe.
+    pub async fn select(&mut self) -> io::Result<(&K, &mut OpenReader)> {
+        let index = future::poll_fn(|cx| self.poll_select(cx)).await?;
+        let entry = &mut self.entries[index];
+        Ok((&entry.key, &mut entry.reader))
+    }
+    fn poll_select(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        if self.entries.is_empty() {
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no pipes to select from",
+            )));
+        }
+        let mut ready = self.ready_now()?;
+        if !ready.contains(&true) {
+            // Only now register for wakeups, since the reactor only reports
+            // the pipes that changed
+            for (entry, ready) in self.entries.iter_mut().zip(&mut ready) {
+                if let Poll::Ready(result) = entry.reader.poll_read_ready(cx) {
+                    result?;
+                    *ready = true;
+                }
+            }
+            if !ready.contains(&true) {
+                return Poll::Pending;
+            }
+        }
+        let limit = self.starvation_limit;
+        let (index, _) = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| ready[*i])
+            .max_by_key(|(i, entry)| {
+                let starved = limit.is_some_and(|limit| entry.skipped >= limit);
+                // Ties go to the pipe added first
+                (
+                    starved,
+                    entry.priority,
+                    entry.skipped,
+                    std::cmp::Reverse(*i),
+                )
+            })
+            .unwrap();
+        for (i, entry) in self.entries.iter_mut().enumerate() {
+            if i == index {
+                entry.skipped = 0;
+            } else if ready[i] {
+                entry.skipped = entry.skipped.saturating_add(1);
+            }
+        }
+        Poll::Ready(Ok(index))
+    }
+    /// Checks which pipes are readable right now.
+    fn ready_now(&self) -> io::Result<Vec<bool>> {
+        let mut fds: Vec<_> = self
+            .entries
+            .iter()
+            .map(|entry| PollFd::new(entry.reader.as_raw_fd(), PollFlags::POLLIN))
+            .collect();
+        poll(&mut fds, 0).map_err(nix_to_io)?;
+        let readable = PollFlags::POLLIN | PollFlags::POLLHUP | PollFlags::POLLERR;
+        Ok(self
+            .entries
+            .iter()
+            .zip(&fds)
+            .map(|(entry, fd)| {
+                !entry.reader.buffered().is_empty()
+                    || fd.revents().is_some_and(|r| r.intersects(readable))
+            })
+            .collect())
+    }
+}
+
+impl<K> Default for PrioritySelect<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PrioritySelect;
+    use crate::{OpenReader, OpenWriter};
+    use async_std::{io, prelude::*, task};
+    fn pipe() -> io::Result<(OpenReader, OpenWriter)> {
+        let (read_fd, write_fd) = nix::unistd::pipe().unwrap();
+        unsafe {
+            Ok((
+                OpenReader::from_raw_fd(read_fd)?,
+                OpenWriter::from_raw_fd(write_fd)?,
+            ))
+        }
+    }
+    #[test]
+    fn highest_priority_first() -> io::Result<()> {
+        task::block_on(async {
+            let (control, mut control_tx) = pipe()?;
+            let (bulk, mut bulk_tx) = pipe()?;
+            let mut select = PrioritySelect::new().starvation_limit(2);
+            select.insert("bulk", 0, bulk);
+            select.insert("control", 10, control);
+            bulk_tx.write_all(b"b").await?;
+            control_tx.write_all(b"cccc").await?;
+            let mut order = Vec::new();
+            for _ in 0..4 {
+                let (key, reader) = select.select().await?;
+                order.push(*key);
+                reader.read_exact(1).await?;
+            }
+            assert_eq!(order, ["control", "control", "bulk", "control"]);
+
+            // Nothing left but the last control byte
+            let (key, _) = select.select().await?;
+            assert_eq!(*key, "control");
+            assert!(select.remove(&"control").is_some());
+            let t_write = task::spawn(async move { bulk_tx.write_all(b"late").await });
+            let (key, reader) = select.select().await?;
+            assert_eq!(*key, "bulk");
+            assert_eq!(reader.read_exact(4).await?, b"late");
+            t_write.await
+        })
+    }
+}
@@ -0,0 +1,301 @@
+//! Handling every message written to a pipe in a background task.
+//!
+//! [`NamedPipeReader::serve`](../struct.NamedPipeReader.html#method.serve)
+//! reads messages from a pipe, one per writer session like
+//! [`read`](../struct.NamedPipeReader.html#method.read), and calls a handler
+//! for each of them:
+//!
+//! ```no_run
+//! # fn main() -> async_std::io::Result<()> { async_std::task::block_on(async {
+//! use unix_fifo_async::serve::{ErrorPolicy, ServeOptions};
+//! use unix_fifo_async::NamedPipePath;
+//!
+//! let options = ServeOptions::new()
+//!     .concurrency(4)
+//!     .error_policy(ErrorPolicy::Continue);
+//! let server = NamedPipePath::new("./jobs").open_read().serve(
+//!     |job| async move {
+//!         println!("running {:?}", job);
+//!         Ok(())
+//!     },
+//!     options,
+//! );
+//! // ... later:
+//! server.shutdown().await?;
+//! # Ok(())
+//! # })}
+//! ```
+use crate::{NamedPipeReader, ShutdownToken};
+use async_std::{
+    future, io,
+    task::{self, JoinHandle},
+};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::task::{Poll, Waker};
+use std::time::Duration;
+
+/// Initial delay before reading again after an error.
+const RETRY_MIN: Duration = Duration::from_millis(10);
+/// Upper bound for the exponential backoff between retries.
+const RETRY_MAX: Duration = Duration::from_secs(1);
+
+/// What [`NamedPipeReader::serve`](../struct.NamedPipeReader.html#method.serve)
+/// does when reading from the pipe or handling a message fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ErrorPolicy {
+    /// Stop serving and report the error through the
+    /// [`ServeHandle`](struct.ServeHandle.html).
+    #[default]
+    Stop,
+    /// Carry on with the next message. Reads are retried with a backoff.
+    Continue,
+}
+
+/// Settings for [`NamedPipeReader::serve`](../struct.NamedPipeReader.html#method.serve).
+#[derive(Clone)]
+pub struct ServeOptions {
+    concurrency: usize,
+    error_policy: ErrorPolicy,
+    shutdown: ShutdownToken,
+}
+
+impl ServeOptions {
+    /// Creates the default options: one message at a time, stopping at the
+    /// first error.
+    pub fn new() -> Self {
+        Self {
+            concurrency: 1,
+            error_policy: ErrorPolicy::default(),
+            shutdown: ShutdownToken::new(),
+        }
+    }
+    /// Sets how many messages may be handled at once.
+    ///
+    /// The next message is only read once a handler is free, so a slow
+    /// handler makes writers wait.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `limit` is 0.
+    pub fn concurrency(mut self, limit: usize) -> Self {
+        assert!(limit > 0, "concurrency must be at least 1");
+        self.concurrency = limit;
+        self
+    }
+    /// Sets what happens when reading or handling a message fails.
+    pub fn error_policy(mut self, policy: ErrorPolicy) -> Self {
+        self.error_policy = policy;
+        self
+    }
+    /// Stops serving when `token` fires, in addition to
+    /// [`ServeHandle::shutdown`](struct.ServeHandle.html#method.shutdown).
+    pub fn with_shutdown(mut self, token: ShutdownToken) -> Self {
+        self.shutdown = token;
+        self
+    }
+}
+
+impl Default for ServeOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Handlers that are running, and the first error one of them returned.
+#[derive(Default)]
+struct Running {
+    count: usize,
+    error: Option<io::Error>,
+    /// The serving task, waiting for a handler to finish.
+    waker: Option<Waker>,
+}
+
+type Shared = Arc<Mutex<Running>>;
+
+/// Runs `fut` until it completes, or until a handler failed.
+async fn until_failed<F: Future>(running: &Shared, fut: F) -> Option<F::Output> {
+    let mut fut = Box::pin(fut);
+    future::poll_fn(|cx| {
+        {
+            let mut running = running.lock().unwrap();
+            if running.error.is_some() {
+                return Poll::Ready(None);
+            }
+            running.waker = Some(cx.waker().clone());
+        }
+        fut.as_mut().poll(cx).map(Some)
+    })
+    .await
+}
+
+/// Waits until fewer than `limit` handlers are running.
+async fn below(running: &Shared, limit: usize) {
+    future::poll_fn(|cx| {
+        let mut running = running.lock().unwrap();
+        if running.count < limit {
+            Poll::Ready(())
+        } else {
+            running.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    })
+    .await
+}
+
+impl NamedPipeReader {
+    /// Calls `handler` for every message written to the pipe, in a new
+    /// task.
+    ///
+    /// A message is everything a writer sends before closing the pipe;
+    /// writers that close it without sending anything are ignored. Since
+    /// that takes the writer closing the pipe, this doesn't work with
+    /// [`hold_open`](#method.hold_open). When serving stops, handlers that
+    /// are still running are waited for, but no new messages are read.
+    pub fn serve<F, Fut>(&self, handler: F, options: ServeOptions) -> ServeHandle
+    where
+        F: Fn(Vec<u8>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = io::Result<()>> + Send + 'static,
+    {
+        let shutdown = options.shutdown.clone();
+        let task = task::spawn(run(self.clone(), Arc::new(handler), options));
+        ServeHandle { shutdown, task }
+    }
+}
+
+async fn run<F, Fut>(
+    reader: NamedPipeReader,
+    handler: Arc<F>,
+    options: ServeOptions,
+) -> io::Result<()>
+where
+    F: Fn(Vec<u8>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = io::Result<()>> + Send + 'static,
+{
+    let stop = &options.shutdown;
+    let stop_on_error = options.error_policy == ErrorPolicy::Stop;
+    let running = Shared::default();
+    let mut delay = RETRY_MIN;
+    let mut read_error = None;
+    loop {
+        let next = async {
+            below(&running, options.concurrency).await;
+            reader.read().await
+        };
+        let message = match stop.run_until(until_failed(&running, next)).await {
+            Some(Some(Ok(message))) => message,
+            Some(Some(Err(e))) if stop_on_error => {
+                read_error = Some(e);
+                break;
+            }
+            Some(Some(Err(_))) => {
+                if stop.run_until(task::sleep(delay)).await.is_none() {
+                    break;
+                }
+                delay = (delay * 2).min(RETRY_MAX);
+                continue;
+            }
+            // Shut down, or a handler failed
+            _ => break,
+        };
+        delay = RETRY_MIN;
+        if message.is_empty() {
+            continue;
+        }
+        running.lock().unwrap().count += 1;
+        let handler = handler.clone();
+        let running = running.clone();
+        task::spawn(async move {
+            let result = handler(message).await;
+            let mut running = running.lock().unwrap();
+            running.count -= 1;
+            if let Err(e) = result {
+                if stop_on_error && running.error.is_none() {
+                    running.error = Some(e);
+                }
+            }
+            if let Some(waker) = running.waker.take() {
+                waker.wake();
+            }
+        });
+    }
+    below(&running, 1).await;
+    let handler_error = running.lock().unwrap().error.take();
+    match read_error.or(handler_error) {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+/// A handle to a pipe being served by
+/// [`NamedPipeReader::serve`](../struct.NamedPipeReader.html#method.serve).
+pub struct ServeHandle {
+    shutdown: ShutdownToken,
+    task: JoinHandle<io::Result<()>>,
+}
+
+impl ServeHandle {
+    /// Stops reading messages and waits for the running handlers to finish.
+    ///
+    /// Returns the error serving stopped at before, if any.
+    pub async fn shutdown(self) -> io::Result<()> {
+        self.shutdown.shutdown();
+        self.task.await
+    }
+    /// Waits for serving to stop by itself, i.e. because of an error with
+    /// [`ErrorPolicy::Stop`](enum.ErrorPolicy.html#variant.Stop) or because
+    /// the token passed to
+    /// [`ServeOptions::with_shutdown`](struct.ServeOptions.html#method.with_shutdown)
+    /// fired.
+    pub async fn join(self) -> io::Result<()> {
+        self.task.await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ErrorPolicy, ServeOptions};
+    use crate::NamedPipePath;
+    use async_std::{io, task};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    #[test]
+    fn serve_messages() -> io::Result<()> {
+        task::block_on(async {
+            let pipe = NamedPipePath::new("./test_pipe_52");
+            pipe.ensure_exists().unwrap();
+            let received = Arc::new(Mutex::new(Vec::new()));
+            let handled = received.clone();
+            let options = ServeOptions::new()
+                .concurrency(2)
+                .error_policy(ErrorPolicy::Stop);
+            let server = pipe.open_read().serve(
+                move |message| {
+                    let handled = handled.clone();
+                    async move {
+                        if message == b"fail" {
+                            return Err(io::Error::new(io::ErrorKind::InvalidData, "bad job"));
+                        }
+                        handled.lock().unwrap().push(message);
+                        Ok(())
+                    }
+                },
+                options,
+            );
+            let writer = pipe.open_write();
+            for (i, message) in [b"one", b"two"].iter().enumerate() {
+                writer.write(*message).await?;
+                // Make sure the messages don't run into each other
+                while received.lock().unwrap().len() <= i {
+                    task::sleep(Duration::from_millis(5)).await;
+                }
+            }
+            assert_eq!(*received.lock().unwrap(), [b"one", b"two"]);
+
+            writer.write(b"fail").await?;
+            let err = server.join().await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            pipe.delete().await
+        })
+    }
+}
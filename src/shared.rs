@@ -0,0 +1,114 @@
+use crate::{
+    fair::{FairMutex, FairMutexGuard},
+    OpenReader,
+};
+use async_std::io;
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+
+/// The reading end of a pipe shared by several tasks.
+///
+/// Clones refer to the same open pipe, and tasks take turns reading from it
+/// in the order they asked for it. Created by
+/// [`OpenReader::into_shared`](struct.OpenReader.html#method.into_shared).
+///
+/// ```no_run
+/// # fn main() -> async_std::io::Result<()> { async_std::task::block_on(async {
+/// use async_std::task;
+/// use unix_fifo_async::NamedPipePath;
+///
+/// let reader = NamedPipePath::new("./jobs").open_read().open().await?.into_shared();
+/// for _ in 0..4 {
+///     let reader = reader.clone();
+///     task::spawn(async move {
+///         loop {
+///             let job = reader.read_until(b'\n').await?;
+///             if job.is_empty() {
+///                 return async_std::io::Result::Ok(());
+///             }
+///             // ...
+///         }
+///     });
+/// }
+/// # Ok(())
+/// # })}
+/// ```
+#[derive(Clone)]
+pub struct SharedReader {
+    inner: Arc<FairMutex<OpenReader>>,
+}
+
+impl SharedReader {
+    pub(crate) fn new(reader: OpenReader) -> Self {
+        Self {
+            inner: Arc::new(FairMutex::new(reader)),
+        }
+    }
+    /// Waits for this task's turn and gives it exclusive access to the
+    /// reader until the guard is dropped.
+    ///
+    /// Tasks get their turn in the order they called `lock`.
+    pub async fn lock(&self) -> SharedReaderGuard<'_> {
+        SharedReaderGuard(self.inner.lock().await)
+    }
+    /// Reads exactly `n` bytes on this task's turn, like
+    /// [`OpenReader::read_exact`](struct.OpenReader.html#method.read_exact).
+    pub async fn read_exact(&self, n: usize) -> io::Result<Vec<u8>> {
+        self.lock().await.read_exact(n).await
+    }
+    /// Reads up to and including the next `delimiter` on this task's turn,
+    /// like [`OpenReader::read_until`](struct.OpenReader.html#method.read_until).
+    pub async fn read_until(&self, delimiter: u8) -> io::Result<Vec<u8>> {
+        self.lock().await.read_until(delimiter).await
+    }
+}
+
+/// Exclusive access to a [`SharedReader`](struct.SharedReader.html).
+///
+/// The next task in line gets its turn when this is dropped.
+pub struct SharedReaderGuard<'a>(FairMutexGuard<'a, OpenReader>);
+
+impl Deref for SharedReaderGuard<'_> {
+    type Target = OpenReader;
+
+    fn deref(&self) -> &OpenReader {
+        &self.0
+    }
+}
+
+impl DerefMut for SharedReaderGuard<'_> {
+    fn deref_mut(&mut self) -> &mut OpenReader {
+        &mut self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{OpenReader, OpenWriter};
+    use async_std::{io, prelude::*, task};
+    use std::time::Duration;
+    #[test]
+    fn take_turns_in_order() -> io::Result<()> {
+        task::block_on(async {
+            let (read_fd, write_fd) = nix::unistd::pipe().unwrap();
+            let reader = unsafe { OpenReader::from_raw_fd(read_fd)? }.into_shared();
+            let mut writer = unsafe { OpenWriter::from_raw_fd(write_fd)? };
+            writer.write_all(b"first\nsecond\nthird\n").await?;
+            let guard = reader.lock().await;
+            let mut tasks = Vec::new();
+            for _ in 0..3 {
+                let reader = reader.clone();
+                tasks.push(task::spawn(async move { reader.read_until(b'\n').await }));
+                // Make sure the tasks queue up in order
+                task::sleep(Duration::from_millis(10)).await;
+            }
+            drop(guard);
+            let mut lines = Vec::new();
+            for t in tasks {
+                lines.push(t.await?);
+            }
+            assert_eq!(lines, [&b"first\n"[..], b"second\n", b"third\n"]);
+            Ok(())
+        })
+    }
+}
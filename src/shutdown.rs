@@ -0,0 +1,190 @@
+use async_std::future::{self, Future};
+use std::collections::HashMap;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+use std::task::{Context, Poll, Waker};
+
+/// A cloneable signal for shutting down long-running pipe operations.
+///
+/// Persistent readers and relays accept a token and stop cleanly once it
+/// fires, closing their pipe handles. Any other operation can be made
+/// interruptible with [`run_until`](#method.run_until).
+///
+/// ```no_run
+/// # fn main() -> async_std::io::Result<()> { async_std::task::block_on(async {
+/// use unix_fifo_async::{NamedPipePath, ShutdownToken};
+/// use async_std::task;
+///
+/// let token = ShutdownToken::new();
+/// let reader = NamedPipePath::new("./my_pipe").open_read();
+/// let t = task::spawn({
+///     let token = token.clone();
+///     async move { token.run_until(reader.read_string()).await }
+/// });
+/// token.shutdown();
+/// // The read was interrupted before anything was written.
+/// assert!(t.await.is_none());
+/// # Ok(())
+/// # })}
+/// ```
+#[derive(Clone, Default)]
+pub struct ShutdownToken {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    fired: AtomicBool,
+    waiters: Mutex<Waiters>,
+}
+
+/// The tasks waiting for a token to fire.
+#[derive(Default)]
+struct Waiters {
+    next_id: u64,
+    wakers: HashMap<u64, Waker>,
+}
+
+impl Waiters {
+    fn register(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id - 1
+    }
+}
+
+impl ShutdownToken {
+    /// Creates a new token that hasn't fired yet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Fires the token, waking everything waiting on it.
+    pub fn shutdown(&self) {
+        self.inner.fired.store(true, Ordering::SeqCst);
+        for (_, waker) in self.inner.waiters.lock().unwrap().wakers.drain() {
+            waker.wake();
+        }
+    }
+    /// Checks whether the token has fired.
+    pub fn is_shutdown(&self) -> bool {
+        self.inner.fired.load(Ordering::SeqCst)
+    }
+    /// Polls whether the token has fired, registering the current task to be
+    /// woken up when it does.
+    ///
+    /// The task stays registered until the token fires, though only once,
+    /// no matter how often it polls. Use [`wait`](#method.wait) or
+    /// [`run_until`](#method.run_until) where possible, which unregister
+    /// the task when they're done or dropped.
+    pub fn poll_shutdown(&self, cx: &mut Context<'_>) -> Poll<()> {
+        if self.is_shutdown() {
+            return Poll::Ready(());
+        }
+        let mut waiters = self.inner.waiters.lock().unwrap();
+        if !waiters.wakers.values().any(|w| w.will_wake(cx.waker())) {
+            let id = waiters.register();
+            waiters.wakers.insert(id, cx.waker().clone());
+        }
+        // `shutdown` might have run between the first check and taking the lock.
+        if self.is_shutdown() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+    /// Resolves once the token has fired.
+    pub async fn wait(&self) {
+        let mut waiter = Waiter::new(self.clone());
+        future::poll_fn(|cx| waiter.poll(cx)).await
+    }
+    /// Runs `fut` to completion unless the token fires first.
+    ///
+    /// Returns `None` if the token fired; `fut` is dropped in that case,
+    /// which closes any pipe it had open.
+    pub async fn run_until<F: Future>(&self, fut: F) -> Option<F::Output> {
+        let mut fut = Box::pin(fut);
+        let mut waiter = Waiter::new(self.clone());
+        future::poll_fn(|cx| {
+            if waiter.poll(cx).is_ready() {
+                return Poll::Ready(None);
+            }
+            fut.as_mut().poll(cx).map(Some)
+        })
+        .await
+    }
+}
+
+/// A task waiting for a token to fire, unregistered when dropped.
+pub(crate) struct Waiter {
+    token: ShutdownToken,
+    id: Option<u64>,
+}
+
+impl Waiter {
+    pub(crate) fn new(token: ShutdownToken) -> Self {
+        Self { token, id: None }
+    }
+    /// Like `ShutdownToken::poll_shutdown`, but replaces the waker this
+    /// registered before instead of adding another one.
+    pub(crate) fn poll(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        if self.token.is_shutdown() {
+            return Poll::Ready(());
+        }
+        let mut waiters = self.token.inner.waiters.lock().unwrap();
+        let id = *self.id.get_or_insert_with(|| waiters.register());
+        waiters.wakers.insert(id, cx.waker().clone());
+        // `shutdown` might have run between the first check and taking the lock.
+        if self.token.is_shutdown() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl Drop for Waiter {
+    fn drop(&mut self) {
+        if let Some(id) = self.id {
+            self.token.inner.waiters.lock().unwrap().wakers.remove(&id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ShutdownToken;
+    use async_std::task;
+    use std::time::Duration;
+    #[test]
+    fn interrupts_pending_read() {
+        task::block_on(async {
+            let pipe = crate::NamedPipePath::new("./test_pipe_18");
+            pipe.ensure_exists().unwrap();
+            let token = ShutdownToken::new();
+            let reader = pipe.open_read();
+            let t_read = task::spawn({
+                let token = token.clone();
+                async move { token.run_until(reader.read()).await }
+            });
+            token.shutdown();
+            assert!(t_read.await.is_none());
+            assert!(token.is_shutdown());
+            pipe.delete().await.unwrap();
+        })
+    }
+    #[test]
+    fn finished_waits_unregister() {
+        task::block_on(async {
+            let token = ShutdownToken::new();
+            for _ in 0..3 {
+                assert_eq!(
+                    token.run_until(task::sleep(Duration::from_millis(1))).await,
+                    Some(())
+                );
+            }
+            let waiting = token.inner.waiters.lock().unwrap().wakers.len();
+            assert_eq!(waiting, 0);
+        })
+    }
+}
@@ -0,0 +1,100 @@
+use crate::{NamedPipeReader, ShutdownToken};
+use async_std::io;
+use nix::sys::signal::Signal;
+use signal_hook::iterator::{Handle, Signals};
+use std::thread;
+
+/// Fires `token` when one of `signals` arrives, until the returned handle is closed.
+fn watch(token: ShutdownToken, signals: &[Signal]) -> io::Result<Handle> {
+    let mut signals = Signals::new(signals.iter().map(|&sig| sig as i32))?;
+    let handle = signals.handle();
+    thread::spawn(move || {
+        if signals.forever().next().is_some() {
+            token.shutdown();
+        }
+    });
+    Ok(handle)
+}
+
+/// Keeps a [`ShutdownToken`](struct.ShutdownToken.html) watching for
+/// signals; returned by
+/// [`ShutdownToken::trigger_on`](struct.ShutdownToken.html#method.trigger_on).
+///
+/// Dropping it stops watching and unregisters the signal handlers.
+#[must_use = "the signals are only watched until this is dropped"]
+pub struct SignalTrigger(Handle);
+
+impl Drop for SignalTrigger {
+    fn drop(&mut self) {
+        self.0.close();
+    }
+}
+
+impl ShutdownToken {
+    /// Fires this token when one of `signals` arrives, until the returned
+    /// [`SignalTrigger`](struct.SignalTrigger.html) is dropped.
+    ///
+    /// The signals are caught from then on, so their default action (e.g.
+    /// terminating the process) no longer applies. Dropping the trigger
+    /// unregisters its handlers, but can't restore the default action:
+    /// later signals are ignored unless something else handles them.
+    pub fn trigger_on(&self, signals: &[Signal]) -> io::Result<SignalTrigger> {
+        watch(self.clone(), signals).map(SignalTrigger)
+    }
+    /// Fires this token on SIGINT or SIGTERM, so Ctrl-C or a service manager
+    /// stopping the process shuts down pipe operations cleanly.
+    pub fn trigger_on_termination(&self) -> io::Result<SignalTrigger> {
+        self.trigger_on(&[Signal::SIGINT, Signal::SIGTERM])
+    }
+}
+
+impl NamedPipeReader {
+    /// Reads all bytes from the pipe, giving up when one of `signals` arrives.
+    /// The returned Future will resolve when something is written to the pipe.
+    ///
+    /// Returns `None` if a signal interrupted the read.
+    ///
+    /// Like with [`ShutdownToken::trigger_on`](struct.ShutdownToken.html#method.trigger_on),
+    /// the signals' default action (e.g. terminating the process on SIGINT)
+    /// no longer applies, even after the read is done and its handlers are
+    /// unregistered: later ones are ignored unless something else handles
+    /// them.
+    pub async fn read_until_signal(&self, signals: &[Signal]) -> io::Result<Option<Vec<u8>>> {
+        let token = ShutdownToken::new();
+        let _trigger = token.trigger_on(signals)?;
+        token.run_until(self.read()).await.transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ShutdownToken;
+    use async_std::{future, prelude::*, task};
+    use nix::sys::signal::{self, Signal};
+    use std::task::Poll;
+    #[test]
+    fn signal_fires_token() {
+        task::block_on(async {
+            let token = ShutdownToken::new();
+            let _trigger = token.trigger_on(&[Signal::SIGUSR1]).unwrap();
+            signal::raise(Signal::SIGUSR1).unwrap();
+            token.wait().await;
+            assert!(token.is_shutdown());
+        })
+    }
+    #[test]
+    fn signal_interrupts_read() {
+        task::block_on(async {
+            let pipe = crate::NamedPipePath::new("./test_pipe_20");
+            pipe.ensure_exists().unwrap();
+            let reader = pipe.open_read();
+            let mut read = Box::pin(reader.read_until_signal(&[Signal::SIGUSR2]));
+            // The first poll registers the handlers, then waits for a writer
+            let pending = future::poll_fn(|cx| Poll::Ready(read.as_mut().poll(cx).is_pending()));
+            assert!(pending.await);
+            signal::raise(Signal::SIGUSR2).unwrap();
+            assert!(read.await.unwrap().is_none());
+            pipe.delete().await.unwrap();
+        })
+    }
+}
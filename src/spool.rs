@@ -0,0 +1,297 @@
+//! Keeping messages on disk while a pipe has no reader.
+//!
+//! A [`SpoolingWriter`](struct.SpoolingWriter.html) sends messages as
+//! [frames](../frame/index.html) as long as the pipe has a reader. While it
+//! doesn't, e.g. because the consumer is restarting, messages are appended
+//! to a spool file instead of blocking or getting lost, and sent in order
+//! once a reader shows up again:
+//!
+//! ```no_run
+//! # fn main() -> async_std::io::Result<()> { async_std::task::block_on(async {
+//! use unix_fifo_async::spool::{Delivery, SpoolingWriter};
+//! use unix_fifo_async::NamedPipePath;
+//!
+//! let pipe = NamedPipePath::new("./events");
+//! let mut writer = SpoolingWriter::new(&pipe, "./events.spool");
+//! if writer.send(b"deployed").await? == Delivery::Spooled {
+//!     println!("consumer is down, keeping the event for later");
+//! }
+//! // Before shutting down, wait for the consumer to take the rest
+//! writer.drain().await?;
+//! # Ok(())
+//! # })}
+//! ```
+//!
+//! The spool file is left behind when the writer is dropped, and picked up
+//! by the next writer using it. Each message in it is stored with its
+//! length (`u32`, little-endian) in front.
+//!
+//! Spooled messages are delivered at least once, not exactly once: how far
+//! the spool file was sent is only kept in memory until all of it is, so
+//! the next writer sends the messages a crashed or dropped writer had
+//! already sent again. `send` and `drain` aren't cancel-safe either;
+//! dropping one while it's writing to the pipe can leave part of a frame
+//! there.
+use crate::{frame::FramedWriter, NamedPipePath, OpenWriter};
+use async_std::{fs, io, prelude::*};
+use nix::{fcntl::OFlag, libc};
+use std::convert::TryInto;
+use std::io::{SeekFrom, Write};
+use std::os::unix::{fs::FileExt, io::IntoRawFd};
+use std::path::{Path, PathBuf};
+
+/// Length of the length prefix of every spooled message.
+const LEN_PREFIX: usize = 4;
+
+/// What happened to a message passed to
+/// [`SpoolingWriter::send`](struct.SpoolingWriter.html#method.send).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delivery {
+    /// The message was written to the pipe.
+    Sent,
+    /// The pipe has no reader, so the message was added to the spool file.
+    Spooled,
+}
+
+/// Writes messages to a pipe, or to a spool file while the pipe has no
+/// reader.
+pub struct SpoolingWriter {
+    pipe: NamedPipePath,
+    spool: PathBuf,
+    /// How many bytes at the start of the spool file were sent already.
+    sent: u64,
+    /// Whether a message cut short at the end of the spool file was
+    /// removed already.
+    repaired: bool,
+    writer: Option<FramedWriter>,
+}
+
+impl SpoolingWriter {
+    /// Creates a writer for `pipe` that spools messages to the file at
+    /// `spool`.
+    ///
+    /// Messages still in the file from an earlier writer are sent before
+    /// any new ones.
+    pub fn new<T: Into<PathBuf>>(pipe: &NamedPipePath, spool: T) -> Self {
+        Self {
+            pipe: pipe.clone(),
+            spool: spool.into(),
+            sent: 0,
+            repaired: false,
+            writer: None,
+        }
+    }
+    /// Returns the path of the spool file.
+    pub fn spool_path(&self) -> &Path {
+        &self.spool
+    }
+    /// Checks if there are messages in the spool file that weren't sent
+    /// yet.
+    pub fn has_spooled(&self) -> bool {
+        std::fs::metadata(&self.spool)
+            .map(|meta| meta.len() > self.sent)
+            .unwrap_or(false)
+    }
+    /// Sends `message` to the pipe if it has a reader, after everything
+    /// that was spooled before; otherwise appends it to the spool file.
+    ///
+    /// Never waits for a reader, but may wait for one to make room in the
+    /// pipe.
+    pub async fn send(&mut self, message: &[u8]) -> io::Result<Delivery> {
+        if self.try_attach()? && self.forward().await? {
+            if let Some(writer) = &mut self.writer {
+                match writer.send(message).await {
+                    Ok(()) => return Ok(Delivery::Sent),
+                    Err(e) if e.kind() == io::ErrorKind::BrokenPipe => self.writer = None,
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        self.append(message)?;
+        Ok(Delivery::Spooled)
+    }
+    /// Waits for the pipe to have a reader and sends everything in the
+    /// spool file.
+    pub async fn drain(&mut self) -> io::Result<()> {
+        loop {
+            if self.writer.is_none() {
+                let writer = self.pipe.open_write().open().await?;
+                self.writer = Some(FramedWriter::new(writer));
+            }
+            if self.forward().await? {
+                return Ok(());
+            }
+        }
+    }
+    /// Opens the pipe if it isn't open yet, returning whether it has a
+    /// reader.
+    fn try_attach(&mut self) -> io::Result<bool> {
+        if self.writer.is_some() {
+            return Ok(true);
+        }
+        match self.pipe.open_file(OFlag::O_WRONLY | OFlag::O_NONBLOCK) {
+            Ok(file) => {
+                // Safety: the file was just opened and is handed over.
+                let writer = unsafe { OpenWriter::from_raw_fd(file.into_raw_fd())? };
+                self.writer = Some(FramedWriter::new(writer));
+                Ok(true)
+            }
+            Err(e) if e.raw_os_error() == Some(libc::ENXIO) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+    /// Sends the spooled messages to the open pipe, returning whether the
+    /// spool is empty afterwards.
+    ///
+    /// If the reader goes away in the middle, the messages that weren't
+    /// sent stay in the spool file, and the next call picks up where this
+    /// one stopped.
+    async fn forward(&mut self) -> io::Result<bool> {
+        let mut file = match fs::File::open(&self.spool).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                self.sent = 0;
+                return Ok(true);
+            }
+            Err(e) => return Err(e),
+        };
+        let writer = match &mut self.writer {
+            Some(writer) => writer,
+            None => return Ok(file.metadata().await?.len() <= self.sent),
+        };
+        file.seek(SeekFrom::Start(self.sent)).await?;
+        let mut spooled = io::BufReader::new(file);
+        // A message cut short by a crash while appending is dropped
+        while let Some(message) = next_spooled(&mut spooled).await? {
+            match writer.send(&message).await {
+                Ok(()) => self.sent += (LEN_PREFIX + message.len()) as u64,
+                Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {
+                    self.writer = None;
+                    return Ok(false);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        fs::remove_file(&self.spool).await?;
+        self.sent = 0;
+        Ok(true)
+    }
+    /// Appends `message` to the spool file.
+    ///
+    /// The first time, a message cut short at the end of the file by a
+    /// crash is removed, so it doesn't swallow the ones appended after it.
+    fn append(&mut self, message: &[u8]) -> io::Result<()> {
+        let len: u32 = message.len().try_into().map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidInput, "message too large to spool")
+        })?;
+        let mut record = Vec::with_capacity(LEN_PREFIX + message.len());
+        record.extend_from_slice(&len.to_le_bytes());
+        record.extend_from_slice(message);
+        let mut file = std::fs::OpenOptions::new()
+            .read(true)
+            .create(true)
+            .append(true)
+            .open(&self.spool)?;
+        if !self.repaired {
+            file.set_len(complete_len(&file)?)?;
+            self.repaired = true;
+        }
+        file.write_all(&record)
+    }
+}
+
+/// Returns the length of the complete messages at the start of the spool
+/// file.
+fn complete_len(file: &std::fs::File) -> io::Result<u64> {
+    let len = file.metadata()?.len();
+    let mut complete = 0;
+    let mut prefix = [0; LEN_PREFIX];
+    while complete + LEN_PREFIX as u64 <= len {
+        file.read_exact_at(&mut prefix, complete)?;
+        let end = complete + (LEN_PREFIX as u64) + u64::from(u32::from_le_bytes(prefix));
+        if end > len {
+            break;
+        }
+        complete = end;
+    }
+    Ok(complete)
+}
+
+/// Reads the next complete message from the spool file, or returns `None`
+/// at its end.
+async fn next_spooled<R: io::Read + Unpin>(spooled: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut prefix = [0; LEN_PREFIX];
+    match spooled.read_exact(&mut prefix).await {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let len = u32::from_le_bytes(prefix) as usize;
+    let mut message = Vec::new();
+    spooled.take(len as u64).read_to_end(&mut message).await?;
+    if message.len() < len {
+        return Ok(None);
+    }
+    Ok(Some(message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Delivery, SpoolingWriter};
+    use crate::{frame::FramedReader, NamedPipePath};
+    use async_std::{io, task};
+    #[test]
+    fn spool_without_reader() -> io::Result<()> {
+        task::block_on(async {
+            let pipe = NamedPipePath::new("./test_pipe_45");
+            pipe.ensure_exists().unwrap();
+            let mut writer = SpoolingWriter::new(&pipe, "./test_spool_45");
+            assert_eq!(writer.send(b"one").await?, Delivery::Spooled);
+            assert_eq!(writer.send(b"two").await?, Delivery::Spooled);
+            assert!(writer.has_spooled());
+
+            let mut reader = FramedReader::new(pipe.open_read().open().await?);
+            assert_eq!(writer.send(b"three").await?, Delivery::Sent);
+            assert!(!writer.has_spooled());
+            for expected in &[&b"one"[..], b"two", b"three"] {
+                assert_eq!(reader.recv().await?.as_deref(), Some(*expected));
+            }
+
+            // The consumer restarts
+            drop(reader);
+            assert_eq!(writer.send(b"four").await?, Delivery::Spooled);
+            let t_drain = task::spawn(async move { writer.drain().await });
+            let mut reader = FramedReader::new(pipe.open_read().open().await?);
+            assert_eq!(reader.recv().await?, Some(b"four".to_vec()));
+            t_drain.await?;
+            assert_eq!(reader.recv().await?, None);
+            pipe.delete().await
+        })
+    }
+    #[test]
+    fn pick_up_left_behind_spool() -> io::Result<()> {
+        task::block_on(async {
+            let pipe = NamedPipePath::new("./test_pipe_57");
+            pipe.ensure_exists().unwrap();
+            // Left behind by a writer that crashed while appending a second
+            // message
+            let mut spooled = 4u32.to_le_bytes().to_vec();
+            spooled.extend_from_slice(b"left");
+            spooled.extend_from_slice(&[9, 0]);
+            std::fs::write("./test_spool_57", spooled)?;
+
+            let mut writer = SpoolingWriter::new(&pipe, "./test_spool_57");
+            assert!(writer.has_spooled());
+            // Appended after the cut-off message is removed
+            assert_eq!(writer.send(b"new").await?, Delivery::Spooled);
+            let t_drain = task::spawn(async move { writer.drain().await });
+            let mut reader = FramedReader::new(pipe.open_read().open().await?);
+            assert_eq!(reader.recv().await?, Some(b"left".to_vec()));
+            assert_eq!(reader.recv().await?, Some(b"new".to_vec()));
+            t_drain.await?;
+            assert_eq!(reader.recv().await?, None);
+            assert!(!std::path::Path::new("./test_spool_57").exists());
+            pipe.delete().await
+        })
+    }
+}
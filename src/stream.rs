@@ -0,0 +1,190 @@
+use crate::{shutdown::Waiter, NamedPipeReader, OpenReader};
+use async_std::{io, prelude::*, task};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// What a streaming reader does when the last writer closes the pipe.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EofPolicy {
+    /// End the stream. This is the default.
+    #[default]
+    End,
+    /// Open the pipe again and wait for the next writer.
+    ///
+    /// Data written in the short window between closing and reopening the
+    /// pipe may be lost if its writer closes before the pipe is reopened.
+    /// Use `hold_open` on the reader if that's a concern.
+    Reopen,
+    /// Yield an `UnexpectedEof` error, then end the stream.
+    Error,
+}
+
+/// A stream of byte chunks read from a named pipe.
+///
+/// Created by [`NamedPipeReader::chunks`](struct.NamedPipeReader.html#method.chunks).
+/// Each item holds whatever was available in the pipe at the time, up to
+/// the configured chunk size. What happens when the writer closes the pipe
+/// is decided by the reader's [`EofPolicy`](enum.EofPolicy.html). If the
+/// reader has a [`ShutdownToken`](struct.ShutdownToken.html), the stream
+/// ends as soon as it fires.
+pub struct Chunks {
+    source: NamedPipeReader,
+    shutdown: Option<Waiter>,
+    reader: Option<OpenReader>,
+    chunk_size: usize,
+    buf: Vec<u8>,
+    done: bool,
+}
+
+impl Chunks {
+    pub(crate) fn new(source: NamedPipeReader, chunk_size: usize) -> Self {
+        assert!(chunk_size != 0, "chunk size must be non-zero");
+        Self {
+            shutdown: source.shutdown.clone().map(Waiter::new),
+            source,
+            reader: None,
+            chunk_size,
+            buf: vec![0; chunk_size],
+            done: false,
+        }
+    }
+}
+
+impl Stream for Chunks {
+    type Item = io::Result<Vec<u8>>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if this.done {
+                return Poll::Ready(None);
+            }
+            if let Some(waiter) = &mut this.shutdown {
+                if waiter.poll(cx).is_ready() {
+                    this.done = true;
+                    this.reader = None;
+                    return Poll::Ready(None);
+                }
+            }
+            if this.reader.is_none() {
+                match this.source.open_sync() {
+                    Ok(reader) => this.reader = Some(reader),
+                    Err(e) => {
+                        this.done = true;
+                        return Poll::Ready(Some(Err(e)));
+                    }
+                }
+            }
+            let reader = this.reader.as_mut().unwrap();
+            let result = task::ready!(Pin::new(reader).poll_read(cx, &mut this.buf));
+            return match result {
+                Ok(0) => {
+                    this.reader = None;
+                    match this.source.eof_policy {
+                        EofPolicy::End => {
+                            this.done = true;
+                            Poll::Ready(None)
+                        }
+                        // The next iteration opens the pipe again, which waits
+                        // for a new writer.
+                        EofPolicy::Reopen => continue,
+                        EofPolicy::Error => {
+                            this.done = true;
+                            Poll::Ready(Some(Err(io::ErrorKind::UnexpectedEof.into())))
+                        }
+                    }
+                }
+                Ok(n) => {
+                    let mut chunk = std::mem::replace(&mut this.buf, vec![0; this.chunk_size]);
+                    chunk.truncate(n);
+                    Poll::Ready(Some(Ok(chunk)))
+                }
+                Err(e) => {
+                    this.done = true;
+                    this.reader = None;
+                    Poll::Ready(Some(Err(e)))
+                }
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_std::{io, prelude::*, task};
+    #[test]
+    fn chunks_arrive_before_close() -> io::Result<()> {
+        task::block_on(async {
+            let pipe = crate::NamedPipePath::new("./test_pipe_12");
+            pipe.ensure_exists().unwrap();
+            let writer = pipe.open_write();
+            let mut chunks = pipe.open_read().chunks(4);
+            let t1 = task::spawn(async move { writer.write_str("Hello pipe").await });
+            let mut read_result = Vec::new();
+            while let Some(chunk) = chunks.next().await {
+                let chunk = chunk?;
+                assert!(chunk.len() <= 4);
+                read_result.extend(chunk);
+            }
+            t1.await?;
+            assert_eq!(read_result, b"Hello pipe");
+            pipe.delete().await
+        })
+    }
+    #[test]
+    fn chunks_reopen_on_eof() -> io::Result<()> {
+        task::block_on(async {
+            let pipe = crate::NamedPipePath::new("./test_pipe_16");
+            pipe.ensure_exists().unwrap();
+            let writer = pipe.open_write();
+            let mut chunks = pipe
+                .open_read()
+                .eof_policy(super::EofPolicy::Reopen)
+                .chunks(64);
+            let t1 = task::spawn(async move { writer.write_str("Hello").await });
+            assert_eq!(chunks.next().await.unwrap()?, b"Hello");
+            t1.await?;
+            let writer = pipe.open_write();
+            let t2 = task::spawn(async move { writer.write_str("pipe").await });
+            assert_eq!(chunks.next().await.unwrap()?, b"pipe");
+            t2.await?;
+            pipe.delete().await
+        })
+    }
+    #[test]
+    fn chunks_end_on_shutdown() -> io::Result<()> {
+        task::block_on(async {
+            let pipe = crate::NamedPipePath::new("./test_pipe_19");
+            pipe.ensure_exists().unwrap();
+            let token = crate::ShutdownToken::new();
+            let mut chunks = pipe
+                .open_read()
+                .hold_open(true)
+                .with_shutdown(token.clone())
+                .chunks(64);
+            let t_read = task::spawn(async move { chunks.next().await.is_none() });
+            token.shutdown();
+            assert!(t_read.await);
+            pipe.delete().await
+        })
+    }
+    #[test]
+    fn chunks_error_on_eof() -> io::Result<()> {
+        task::block_on(async {
+            let pipe = crate::NamedPipePath::new("./test_pipe_17");
+            pipe.ensure_exists().unwrap();
+            let writer = pipe.open_write();
+            let mut chunks = pipe
+                .open_read()
+                .eof_policy(super::EofPolicy::Error)
+                .chunks(64);
+            let t1 = task::spawn(async move { writer.write_str("Hello").await });
+            assert_eq!(chunks.next().await.unwrap()?, b"Hello");
+            let err = chunks.next().await.unwrap().unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+            assert!(chunks.next().await.is_none());
+            t1.await?;
+            pipe.delete().await
+        })
+    }
+}
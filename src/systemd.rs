@@ -0,0 +1,144 @@
+//! Adopting pipe ends passed in by systemd.
+//!
+//! Units with `ListenFIFO=` entries pass their pipes to the service as open
+//! file descriptors, following the `sd_listen_fds(3)` protocol. This module
+//! picks those up, so the service doesn't need to know where the pipes live.
+//!
+//! ```no_run
+//! # fn main() -> async_std::io::Result<()> { async_std::task::block_on(async {
+//! use unix_fifo_async::systemd::{self, PipeEnd};
+//!
+//! for pipe in systemd::listen_fds(true)? {
+//!     if let PipeEnd::Reader(reader) = pipe.end {
+//!         // ...
+//!     }
+//! }
+//! # Ok(())
+//! # })}
+//! ```
+use crate::{
+    util::{is_fifo, nix_to_io},
+    OpenReader, OpenWriter,
+};
+use async_std::io;
+use nix::fcntl::{fcntl, FcntlArg, FdFlag, OFlag};
+use nix::sys::stat::fstat;
+use std::env;
+use std::os::unix::io::RawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// The first file descriptor passed by systemd.
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Set once the passed descriptors have been adopted, so they can't end up
+/// owned by two handles.
+static ADOPTED: AtomicBool = AtomicBool::new(false);
+
+/// A pipe end passed in by systemd.
+pub struct ListenedPipe {
+    /// The name assigned with `FileDescriptorName=`, if any.
+    pub name: Option<String>,
+    /// The adopted pipe end.
+    pub end: PipeEnd,
+}
+
+/// Which end of a pipe a passed file descriptor refers to.
+pub enum PipeEnd {
+    /// The descriptor was opened for reading.
+    ///
+    /// systemd opens `ListenFIFO=` pipes read-write, so these are treated as
+    /// readers that never see EOF.
+    Reader(OpenReader),
+    /// The descriptor was opened for writing only.
+    Writer(OpenWriter),
+}
+
+/// Adopts all FIFOs passed to this process by systemd.
+///
+/// Returns an empty list if the process wasn't started with passed file
+/// descriptors (`LISTEN_PID` doesn't match this process). Descriptors that
+/// aren't FIFOs, like sockets from the same unit, are left untouched.
+///
+/// The descriptors are adopted at most once per process; later calls
+/// return an empty list. With `unset_env`, the `LISTEN_*` variables are
+/// also removed from the environment, so they aren't inherited by child
+/// processes.
+pub fn listen_fds(unset_env: bool) -> io::Result<Vec<ListenedPipe>> {
+    let pid = env::var("LISTEN_PID").ok();
+    let fds = env::var("LISTEN_FDS").ok();
+    let names = env::var("LISTEN_FDNAMES").ok();
+    if unset_env {
+        env::remove_var("LISTEN_PID");
+        env::remove_var("LISTEN_FDS");
+        env::remove_var("LISTEN_FDNAMES");
+    }
+    let own_pid = nix::unistd::getpid().as_raw();
+    match pid.and_then(|pid| pid.parse::<i32>().ok()) {
+        Some(pid) if pid == own_pid => {}
+        _ => return Ok(Vec::new()),
+    }
+    let count = match fds {
+        Some(fds) => fds
+            .parse::<RawFd>()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+        None => return Ok(Vec::new()),
+    };
+    let end = match LISTEN_FDS_START.checked_add(count) {
+        Some(end) if count >= 0 => end,
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid LISTEN_FDS {}", count),
+            ))
+        }
+    };
+    if ADOPTED.swap(true, Ordering::SeqCst) {
+        return Ok(Vec::new());
+    }
+    let mut names = names
+        .as_ref()
+        .map(|names| names.split(':').map(String::from).collect::<Vec<_>>())
+        .unwrap_or_default()
+        .into_iter();
+
+    let mut pipes = Vec::new();
+    for fd in LISTEN_FDS_START..end {
+        let name = names.next().filter(|name| !name.is_empty());
+        let stat = fstat(fd).map_err(nix_to_io)?;
+        if !is_fifo(&stat) {
+            continue;
+        }
+        // Only for descriptors this adopts; the others may be meant to be
+        // inherited by child processes
+        fcntl(fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC)).map_err(nix_to_io)?;
+        let flags = fcntl(fd, FcntlArg::F_GETFL).map_err(nix_to_io)?;
+        // Safety: systemd hands ownership of the descriptors to this process,
+        // and `ADOPTED` makes sure they're only adopted once.
+        let end = if OFlag::from_bits_truncate(flags) & OFlag::O_ACCMODE == OFlag::O_WRONLY {
+            PipeEnd::Writer(unsafe { OpenWriter::from_raw_fd(fd)? })
+        } else {
+            PipeEnd::Reader(unsafe { OpenReader::from_raw_fd(fd)? })
+        };
+        pipes.push(ListenedPipe { name, end });
+    }
+    Ok(pipes)
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn ignores_other_pids() {
+        std::env::set_var("LISTEN_PID", "1");
+        std::env::set_var("LISTEN_FDS", "1");
+        assert!(super::listen_fds(true).unwrap().is_empty());
+        assert!(std::env::var("LISTEN_FDS").is_err());
+
+        let own_pid = std::process::id().to_string();
+        for count in &["-1", "2147483647"] {
+            std::env::set_var("LISTEN_PID", &own_pid);
+            std::env::set_var("LISTEN_FDS", count);
+            let err = super::listen_fds(true).err().unwrap();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        }
+    }
+}
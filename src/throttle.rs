@@ -0,0 +1,141 @@
+use crate::OpenWriter;
+use async_std::{io, prelude::*, task};
+use std::time::{Duration, Instant};
+
+/// A token bucket that allows bursts of up to one second's worth of tokens.
+struct TokenBucket {
+    /// Tokens added per second.
+    rate: f64,
+    /// Available tokens; negative while paying off a large request.
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: f64) -> Self {
+        Self {
+            rate,
+            tokens: rate,
+            last_refill: Instant::now(),
+        }
+    }
+    /// Takes `amount` tokens, waiting until the bucket has refilled enough.
+    ///
+    /// Requests larger than the bucket are let through after waiting for
+    /// the whole amount, rather than never.
+    async fn take(&mut self, amount: f64) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.last_refill = now;
+        self.tokens -= amount;
+        if self.tokens < 0.0 {
+            task::sleep(Duration::from_secs_f64(-self.tokens / self.rate)).await;
+        }
+    }
+}
+
+/// Limits how fast data is written to a pipe.
+///
+/// Both limits are enforced with a token bucket, so short bursts of up to
+/// one second's worth of data go through at full speed. Without any limits
+/// set, this behaves like the wrapped writer.
+///
+/// ```no_run
+/// # fn main() -> async_std::io::Result<()> { async_std::task::block_on(async {
+/// use unix_fifo_async::{NamedPipePath, ThrottledWriter};
+///
+/// let pipe = NamedPipePath::new("./slow_consumer");
+/// let writer = pipe.open_write().open().await?;
+/// let mut writer = ThrottledWriter::new(writer).messages_per_sec(10);
+/// for i in 0..100 {
+///     writer.write_message(format!("job {}\n", i).as_bytes()).await?;
+/// }
+/// # Ok(())
+/// # })}
+/// ```
+pub struct ThrottledWriter {
+    inner: OpenWriter,
+    bytes: Option<TokenBucket>,
+    messages: Option<TokenBucket>,
+}
+
+impl ThrottledWriter {
+    /// Wraps `inner` without any limits.
+    pub fn new(inner: OpenWriter) -> Self {
+        Self {
+            inner,
+            bytes: None,
+            messages: None,
+        }
+    }
+    /// Limits the throughput to `rate` bytes per second.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `rate` is 0.
+    pub fn bytes_per_sec(mut self, rate: u64) -> Self {
+        assert!(rate > 0, "rate must be non-zero");
+        self.bytes = Some(TokenBucket::new(rate as f64));
+        self
+    }
+    /// Limits the number of messages written per second.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `rate` is 0.
+    pub fn messages_per_sec(mut self, rate: u32) -> Self {
+        assert!(rate > 0, "rate must be non-zero");
+        self.messages = Some(TokenBucket::new(f64::from(rate)));
+        self
+    }
+    /// Writes `data` to the pipe as a single message, waiting first if that
+    /// would exceed one of the limits.
+    pub async fn write_message(&mut self, data: &[u8]) -> io::Result<()> {
+        if let Some(messages) = &mut self.messages {
+            messages.take(1.0).await;
+        }
+        if let Some(bytes) = &mut self.bytes {
+            bytes.take(data.len() as f64).await;
+        }
+        self.inner.write_all(data).await
+    }
+    /// Gets a mutable reference to the underlying pipe.
+    ///
+    /// Writing to it directly bypasses the limits.
+    pub fn get_mut(&mut self) -> &mut OpenWriter {
+        &mut self.inner
+    }
+    /// Unwraps the underlying pipe.
+    pub fn into_inner(self) -> OpenWriter {
+        self.inner
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ThrottledWriter;
+    use crate::NamedPipePath;
+    use async_std::{io, task};
+    use std::time::{Duration, Instant};
+    #[test]
+    fn limits_throughput() -> io::Result<()> {
+        task::block_on(async {
+            let pipe = NamedPipePath::new("./test_pipe_28");
+            pipe.ensure_exists().unwrap();
+            let reader = pipe.open_read();
+            let t_read = task::spawn(async move { reader.read().await });
+            let writer = pipe.open_write().open().await?;
+            let mut writer = ThrottledWriter::new(writer).bytes_per_sec(100);
+            let start = Instant::now();
+            // The first 100 bytes fit into the burst, the rest have to wait
+            for _ in 0..3 {
+                writer.write_message(&[b'x'; 50]).await?;
+            }
+            assert!(start.elapsed() >= Duration::from_millis(400));
+            drop(writer);
+            assert_eq!(t_read.await?.len(), 150);
+            pipe.delete().await
+        })
+    }
+}
@@ -0,0 +1,435 @@
+//! An abstraction over reading and writing whole messages, so code using
+//! pipes can be tested without touching the filesystem.
+//!
+//! Write your code against [`PipeSource`](trait.PipeSource.html),
+//! [`PipeSink`](trait.PipeSink.html) or, for both,
+//! [`PipeTransport`](trait.PipeTransport.html) instead of the concrete pipe
+//! types, pass it a [`NamedPipePath`](../struct.NamedPipePath.html),
+//! [`NamedPipeReader`](../struct.NamedPipeReader.html) or
+//! [`NamedPipeWriter`](../struct.NamedPipeWriter.html) in production and a
+//! [`MockPipe`](struct.MockPipe.html) in tests:
+//!
+//! ```
+//! # fn main() -> async_std::io::Result<()> { async_std::task::block_on(async {
+//! use async_std::io;
+//! use unix_fifo_async::transport::{MockPipe, PipeSink, PipeSource, PipeTransport};
+//!
+//! async fn echo_upper<T: PipeTransport>(pipe: &T) -> io::Result<()> {
+//!     let request = pipe.read_string().await?;
+//!     pipe.write_str(&request.to_uppercase()).await
+//! }
+//!
+//! let pipe = MockPipe::new().with_read("hello");
+//! echo_upper(&pipe).await?;
+//! assert_eq!(pipe.written(), vec![b"HELLO".to_vec()]);
+//! # Ok(())
+//! # })}
+//! ```
+//!
+//! Some filesystems, like certain NFS mounts and container overlays, can't
+//! hold named pipes. [`Endpoint`](enum.Endpoint.html) uses a
+//! [`SocketPipe`](struct.SocketPipe.html), a Unix socket at the same path,
+//! there instead, so code written against these traits runs on both. The
+//! fallback only covers whole-message reads and writes through the traits:
+//! there's no socket-backed `NamedPipeReader`, `NamedPipeWriter` or
+//! `OpenReader`, so streaming, framing and the other pipe-specific APIs
+//! still need a real named pipe.
+use crate::{util::nix_to_io, NamedPipePath, NamedPipeReader, NamedPipeWriter};
+use async_std::os::unix::net::{UnixListener, UnixStream};
+use async_std::{io, prelude::*, task};
+use nix::errno::Errno;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// How long to wait before trying to connect to a socket again.
+const CONNECT_RETRY_MIN: Duration = Duration::from_millis(1);
+/// Upper bound for the exponential backoff between connection attempts.
+const CONNECT_RETRY_MAX: Duration = Duration::from_millis(50);
+
+/// A boxed future, as returned by [`PipeSource`](trait.PipeSource.html) and
+/// [`PipeSink`](trait.PipeSink.html) methods.
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Reads whole messages, like
+/// [`NamedPipeReader::read`](../struct.NamedPipeReader.html#method.read).
+pub trait PipeSource: Send + Sync {
+    /// Reads a whole message.
+    fn read(&self) -> BoxFuture<'_, io::Result<Vec<u8>>>;
+    /// Reads a whole message as a string.
+    fn read_string(&self) -> BoxFuture<'_, io::Result<String>> {
+        Box::pin(async move {
+            let data = self.read().await?;
+            String::from_utf8(data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        })
+    }
+}
+
+/// Writes whole messages, like
+/// [`NamedPipeWriter::write`](../struct.NamedPipeWriter.html#method.write).
+pub trait PipeSink: Send + Sync {
+    /// Writes a whole message.
+    fn write<'a>(&'a self, data: &'a [u8]) -> BoxFuture<'a, io::Result<()>>;
+    /// Writes a string as a whole message.
+    fn write_str<'a>(&'a self, data: &'a str) -> BoxFuture<'a, io::Result<()>> {
+        self.write(data.as_bytes())
+    }
+}
+
+/// Reads and writes whole messages.
+///
+/// Implemented for everything that is both a
+/// [`PipeSource`](trait.PipeSource.html) and a
+/// [`PipeSink`](trait.PipeSink.html).
+pub trait PipeTransport: PipeSource + PipeSink {}
+
+impl<T: PipeSource + PipeSink> PipeTransport for T {}
+
+/// Reads from the pipe at this path, with default options.
+impl PipeSource for NamedPipePath {
+    fn read(&self) -> BoxFuture<'_, io::Result<Vec<u8>>> {
+        Box::pin(async move { self.open_read().read().await })
+    }
+}
+
+/// Writes to the pipe at this path, with default options.
+impl PipeSink for NamedPipePath {
+    fn write<'a>(&'a self, data: &'a [u8]) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async move { self.open_write().write(data).await })
+    }
+}
+
+/// Reads with this reader's options.
+impl PipeSource for NamedPipeReader {
+    fn read(&self) -> BoxFuture<'_, io::Result<Vec<u8>>> {
+        Box::pin(NamedPipeReader::read(self))
+    }
+}
+
+impl PipeSink for NamedPipeWriter {
+    fn write<'a>(&'a self, data: &'a [u8]) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(NamedPipeWriter::write(self, data))
+    }
+}
+
+/// A Unix stream socket at a path, used like a named pipe.
+///
+/// Reading binds the socket on first use and takes one message from each
+/// connection, read until the writer closes it. Writing connects, waiting
+/// for a reader to bind the socket first just like writing to a pipe waits
+/// for a reader. The socket file is removed when the reading side is
+/// dropped.
+pub struct SocketPipe {
+    path: PathBuf,
+    listener: async_std::sync::Mutex<Option<UnixListener>>,
+}
+
+impl SocketPipe {
+    /// Creates a handle for the socket at `path`, without binding it yet.
+    pub fn new<T: Into<PathBuf>>(path: T) -> Self {
+        Self {
+            path: path.into(),
+            listener: async_std::sync::Mutex::new(None),
+        }
+    }
+    /// Returns the path of the socket.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    async fn bind(&self) -> io::Result<UnixListener> {
+        match UnixListener::bind(&self.path).await {
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse => {
+                // Left behind by a reader that went away without cleaning up
+                match UnixStream::connect(&self.path).await {
+                    Err(c) if c.kind() == io::ErrorKind::ConnectionRefused => {
+                        std::fs::remove_file(&self.path)?;
+                        UnixListener::bind(&self.path).await
+                    }
+                    _ => Err(e),
+                }
+            }
+            result => result,
+        }
+    }
+    async fn connect(&self) -> io::Result<UnixStream> {
+        let mut delay = CONNECT_RETRY_MIN;
+        loop {
+            match UnixStream::connect(&self.path).await {
+                Err(e)
+                    if e.kind() == io::ErrorKind::NotFound
+                        || e.kind() == io::ErrorKind::ConnectionRefused =>
+                {
+                    task::sleep(delay).await;
+                    delay = (delay * 2).min(CONNECT_RETRY_MAX);
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+impl PipeSource for SocketPipe {
+    fn read(&self) -> BoxFuture<'_, io::Result<Vec<u8>>> {
+        Box::pin(async move {
+            let mut listener = self.listener.lock().await;
+            if listener.is_none() {
+                *listener = Some(self.bind().await?);
+            }
+            let (mut stream, _) = listener.as_ref().unwrap().accept().await?;
+            let mut data = Vec::new();
+            stream.read_to_end(&mut data).await?;
+            Ok(data)
+        })
+    }
+}
+
+impl PipeSink for SocketPipe {
+    fn write<'a>(&'a self, data: &'a [u8]) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            let mut stream = self.connect().await?;
+            stream.write_all(data).await
+        })
+    }
+}
+
+impl Drop for SocketPipe {
+    fn drop(&mut self) {
+        if self.listener.get_mut().is_some() {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// A named pipe, or a socket where the filesystem doesn't support those.
+///
+/// Only usable as a [`PipeSource`](trait.PipeSource.html) and
+/// [`PipeSink`](trait.PipeSink.html); match on it to get at the named pipe
+/// for anything else.
+pub enum Endpoint {
+    /// A named pipe.
+    Fifo(NamedPipePath),
+    /// A Unix socket at the path.
+    Socket(SocketPipe),
+}
+
+impl Endpoint {
+    /// Uses what's at `path`, or creates a named pipe there if nothing is.
+    ///
+    /// Falls back to a socket if the filesystem doesn't support named
+    /// pipes. Both sides should use this, so they agree on which one it is.
+    pub fn create<T: Into<PathBuf>>(path: T) -> io::Result<Self> {
+        let path = path.into();
+        match std::fs::symlink_metadata(&path) {
+            Ok(meta) if meta.file_type().is_fifo() => {
+                return Ok(Endpoint::Fifo(NamedPipePath::new(path)))
+            }
+            Ok(meta) if meta.file_type().is_socket() => {
+                return Ok(Endpoint::Socket(SocketPipe::new(path)))
+            }
+            Ok(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    "path is neither a named pipe nor a socket",
+                ))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        let pipe = NamedPipePath::new(path);
+        match pipe.ensure_exists() {
+            Ok(()) => Ok(Endpoint::Fifo(pipe)),
+            Err(nix::Error::Sys(Errno::EPERM))
+            | Err(nix::Error::Sys(Errno::EINVAL))
+            | Err(nix::Error::Sys(Errno::EOPNOTSUPP))
+            | Err(nix::Error::Sys(Errno::ENOSYS)) => {
+                Ok(Endpoint::Socket(SocketPipe::new(pipe.as_path().to_owned())))
+            }
+            Err(e) => Err(nix_to_io(e)),
+        }
+    }
+}
+
+impl PipeSource for Endpoint {
+    fn read(&self) -> BoxFuture<'_, io::Result<Vec<u8>>> {
+        match self {
+            Endpoint::Fifo(pipe) => pipe.read(),
+            Endpoint::Socket(socket) => socket.read(),
+        }
+    }
+}
+
+impl PipeSink for Endpoint {
+    fn write<'a>(&'a self, data: &'a [u8]) -> BoxFuture<'a, io::Result<()>> {
+        match self {
+            Endpoint::Fifo(pipe) => pipe.write(data),
+            Endpoint::Socket(socket) => socket.write(data),
+        }
+    }
+}
+
+#[derive(Default)]
+struct MockState {
+    reads: VecDeque<io::Result<Vec<u8>>>,
+    write_errors: VecDeque<io::ErrorKind>,
+    written: Vec<Vec<u8>>,
+}
+
+/// An in-memory [`PipeTransport`](trait.PipeTransport.html) with scripted
+/// reads and injectable failures.
+///
+/// Reads return the scripted messages and errors in order, and fail with
+/// `UnexpectedEof` once the script runs out. Writes are collected, unless
+/// a write error was queued with
+/// [`with_write_error`](#method.with_write_error).
+#[derive(Default)]
+pub struct MockPipe {
+    state: Mutex<MockState>,
+}
+
+impl MockPipe {
+    /// Creates a mock without any scripted reads.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Queues a message for the next unscripted read.
+    pub fn with_read<D: Into<Vec<u8>>>(self, data: D) -> Self {
+        self.lock().reads.push_back(Ok(data.into()));
+        self
+    }
+    /// Makes the next unscripted read fail with `kind`.
+    pub fn with_read_error(self, kind: io::ErrorKind) -> Self {
+        self.lock().reads.push_back(Err(kind.into()));
+        self
+    }
+    /// Makes the next write that hasn't been failed yet fail with `kind`.
+    pub fn with_write_error(self, kind: io::ErrorKind) -> Self {
+        self.lock().write_errors.push_back(kind);
+        self
+    }
+    /// Returns the messages written so far, in order.
+    pub fn written(&self) -> Vec<Vec<u8>> {
+        self.lock().written.clone()
+    }
+    fn lock(&self) -> std::sync::MutexGuard<'_, MockState> {
+        // A panicking test can't leave the state half-updated.
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl PipeSource for MockPipe {
+    fn read(&self) -> BoxFuture<'_, io::Result<Vec<u8>>> {
+        let next = self.lock().reads.pop_front();
+        Box::pin(async move {
+            next.unwrap_or_else(|| {
+                Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "no more scripted reads",
+                ))
+            })
+        })
+    }
+}
+
+impl PipeSink for MockPipe {
+    fn write<'a>(&'a self, data: &'a [u8]) -> BoxFuture<'a, io::Result<()>> {
+        let mut state = self.lock();
+        let result = match state.write_errors.pop_front() {
+            Some(kind) => Err(kind.into()),
+            None => {
+                state.written.push(data.to_vec());
+                Ok(())
+            }
+        };
+        Box::pin(async move { result })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Endpoint, MockPipe, PipeSink, PipeSource, SocketPipe};
+    use crate::NamedPipePath;
+    use async_std::{io, task};
+    use std::sync::Arc;
+    #[test]
+    fn mock_script() -> io::Result<()> {
+        task::block_on(async {
+            let pipe = MockPipe::new()
+                .with_read("first")
+                .with_read_error(io::ErrorKind::BrokenPipe)
+                .with_write_error(io::ErrorKind::WouldBlock);
+            assert_eq!(pipe.read_string().await?, "first");
+            assert_eq!(
+                pipe.read().await.unwrap_err().kind(),
+                io::ErrorKind::BrokenPipe
+            );
+            assert_eq!(
+                pipe.read().await.unwrap_err().kind(),
+                io::ErrorKind::UnexpectedEof
+            );
+            assert!(pipe.write(b"lost").await.is_err());
+            pipe.write_str("kept").await?;
+            assert_eq!(pipe.written(), vec![b"kept".to_vec()]);
+            Ok(())
+        })
+    }
+    #[test]
+    fn named_pipe_transport() -> io::Result<()> {
+        task::block_on(async {
+            let pipe = NamedPipePath::new("./test_pipe_30");
+            pipe.ensure_exists().unwrap();
+            let read_pipe = pipe.clone();
+            let t_read = task::spawn(async move { read_pipe.read_string().await });
+            PipeSink::write_str(&pipe, "Hello pipe").await?;
+            assert_eq!(t_read.await?, "Hello pipe");
+            pipe.delete().await
+        })
+    }
+    #[test]
+    fn reader_and_mock_agree() -> io::Result<()> {
+        async fn fetch<T: PipeSource>(source: &T) -> io::Result<String> {
+            Ok(source.read_string().await?.trim().to_owned())
+        }
+        task::block_on(async {
+            let mock = MockPipe::new().with_read(" from mock\n");
+            assert_eq!(fetch(&mock).await?, "from mock");
+
+            let pipe = NamedPipePath::new("./test_pipe_54");
+            pipe.ensure_exists().unwrap();
+            let writer = pipe.open_write();
+            let t_write =
+                task::spawn(async move { PipeSink::write_str(&writer, " from pipe\n").await });
+            assert_eq!(fetch(&pipe.open_read()).await?, "from pipe");
+            t_write.await?;
+            pipe.delete().await
+        })
+    }
+    #[test]
+    fn socket_fallback() -> io::Result<()> {
+        task::block_on(async {
+            let socket = Arc::new(SocketPipe::new("./test_sock_48"));
+            let writer = socket.clone();
+            let t_write = task::spawn(async move {
+                writer.write_str("first").await?;
+                writer.write_str("second").await
+            });
+            assert_eq!(socket.read_string().await?, "first");
+            assert_eq!(socket.read_string().await?, "second");
+            t_write.await?;
+            // Picked up as a socket, since there's one at the path now
+            match Endpoint::create("./test_sock_48")? {
+                Endpoint::Socket(_) => {}
+                Endpoint::Fifo(_) => panic!("expected a socket"),
+            }
+            drop(socket);
+            assert!(!std::path::Path::new("./test_sock_48").exists());
+            match Endpoint::create("./test_pipe_48")? {
+                Endpoint::Fifo(pipe) => pipe.delete().await,
+                Endpoint::Socket(_) => panic!("expected a named pipe"),
+            }
+        })
+    }
+}
@@ -1,15 +1,272 @@
 use async_std::fs;
-use nix::{sys::stat::Mode, NixPath};
-use std::path::Path;
+use nix::{
+    errno::Errno,
+    libc,
+    sys::stat::{FileStat, Mode, SFlag},
+    unistd::{Gid, Uid},
+    NixPath,
+};
+use std::collections::HashSet;
+use std::ffi::CString;
+use std::mem::MaybeUninit;
+use std::os::unix::{
+    fs::{FileTypeExt, MetadataExt, OpenOptionsExt},
+    io::RawFd,
+};
+use std::path::{Path, PathBuf};
+use std::ptr;
 
 /// Attempt to create a new Unix named pipe/FIFO on disk.
 pub fn create_pipe<P: ?Sized + NixPath>(path: &P, mode: Option<Mode>) -> nix::Result<()> {
     nix::unistd::mkfifo(path, mode.unwrap_or_else(|| Mode::from_bits_truncate(0o660)))
 }
 
+/// Attempt to create a new Unix named pipe/FIFO on disk, relative to the
+/// open directory `dirfd`.
+pub fn create_pipe_at<P: ?Sized + NixPath>(
+    dirfd: RawFd,
+    path: &P,
+    mode: Option<Mode>,
+) -> nix::Result<()> {
+    let mode = mode.unwrap_or_else(|| Mode::from_bits_truncate(0o660));
+    let res = path
+        .with_nix_path(|cstr| unsafe { nix::libc::mkfifoat(dirfd, cstr.as_ptr(), mode.bits()) })?;
+    Errno::result(res).map(drop)
+}
+
+/// Checks whether a `stat` result describes a named pipe.
+pub(crate) fn is_fifo(stat: &FileStat) -> bool {
+    SFlag::from_bits_truncate(stat.st_mode) & SFlag::S_IFMT == SFlag::S_IFIFO
+}
+
+/// Converts a `nix::Error` into the equivalent `io::Error`.
+pub(crate) fn nix_to_io(e: nix::Error) -> async_std::io::Error {
+    match e {
+        nix::Error::Sys(errno) => async_std::io::Error::from_raw_os_error(errno as i32),
+        e => async_std::io::Error::other(e),
+    }
+}
+
+/// Checks if reopening the pipe might get past `e`, e.g. because the other
+/// end went away or the pipe is being recreated.
+pub(crate) fn is_transient(e: &async_std::io::Error) -> bool {
+    use async_std::io::ErrorKind;
+    matches!(
+        e.kind(),
+        ErrorKind::BrokenPipe
+            | ErrorKind::NotFound
+            | ErrorKind::Interrupted
+            | ErrorKind::WouldBlock
+            | ErrorKind::TimedOut
+    )
+}
+
+/// Returns how many bytes are in the pipe `fd` belongs to and haven't
+/// been read yet (`FIONREAD`).
+pub(crate) fn bytes_available(fd: RawFd) -> async_std::io::Result<usize> {
+    let mut n: libc::c_int = 0;
+    // Safety: FIONREAD writes a single `c_int` to the pointer.
+    let res = unsafe { libc::ioctl(fd, libc::FIONREAD as _, &mut n) };
+    Errno::result(res).map_err(nix_to_io)?;
+    Ok(n as usize)
+}
+
 /// Attempt to delete a Unix named pipe/FIFO from disk.
 pub async fn remove_pipe<P: AsRef<Path>>(path: P) -> async_std::io::Result<()> {
-    fs::remove_file(&path).await
+    fs::remove_file(path.as_ref()).await
+}
+
+/// How [`cleanup_stale_pipes`](fn.cleanup_stale_pipes.html) tells whether
+/// a pipe is still in use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StaleCheck {
+    /// Looks for processes that have the pipe open, in `/proc`, without
+    /// disturbing them. This finds readers and writers alike, but only in
+    /// processes this one may inspect (as root, all of them; otherwise,
+    /// usually the ones of the same user), and not a reader that's still
+    /// waiting in `open` for its first writer. Fails where there's no
+    /// `/proc`.
+    OpenHandles,
+    /// Tries to open the pipe for writing without blocking, which only
+    /// works if it has a reader. This finds every reader, but wakes up one
+    /// that's waiting for its first writer, which then reads EOF as soon
+    /// as the probe closes the pipe again. Writers aren't found.
+    Probe,
+}
+
+/// What [`cleanup_stale_pipes`](fn.cleanup_stale_pipes.html) found.
+#[derive(Debug, Default)]
+pub struct CleanupReport {
+    /// Pipes that were removed.
+    pub removed: Vec<PathBuf>,
+    /// Pipes that matched the predicate, but are still in use.
+    pub in_use: Vec<PathBuf>,
+    /// Pipes that couldn't be checked or removed, with the reason.
+    pub failed: Vec<(PathBuf, async_std::io::Error)>,
+}
+
+/// Removes named pipes in `dir` that nobody reads from anymore, e.g. ones
+/// left behind by processes that crashed.
+///
+/// Only pipes for which `predicate` returns `true` are considered; use it
+/// to only remove pipes that haven't been modified for a while, based on
+/// their metadata. Of those, the ones that `check` doesn't find in use are
+/// stale; see [`StaleCheck`](enum.StaleCheck.html) for what each check
+/// misses. Neither sees a process that's about to open a pipe it just
+/// created, so such a pipe is only safe from removal if it's too young to
+/// match the predicate. Subdirectories aren't searched.
+pub fn cleanup_stale_pipes<P, F>(
+    dir: P,
+    check: StaleCheck,
+    mut predicate: F,
+) -> async_std::io::Result<CleanupReport>
+where
+    P: AsRef<Path>,
+    F: FnMut(&Path, &std::fs::Metadata) -> bool,
+{
+    let mut report = CleanupReport::default();
+    // Collected when the first pipe needs checking
+    let mut open_handles = None;
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let metadata = match std::fs::symlink_metadata(&path) {
+            Ok(metadata) => metadata,
+            // Removed by someone else in the meantime
+            Err(e) if e.kind() == async_std::io::ErrorKind::NotFound => continue,
+            Err(e) => {
+                report.failed.push((path, e));
+                continue;
+            }
+        };
+        if !metadata.file_type().is_fifo() || !predicate(&path, &metadata) {
+            continue;
+        }
+        let in_use = match check {
+            StaleCheck::OpenHandles => {
+                if open_handles.is_none() {
+                    open_handles = Some(open_handles_in_proc()?);
+                }
+                let handles = open_handles.as_ref().unwrap();
+                Ok(handles.contains(&(metadata.dev(), metadata.ino())))
+            }
+            StaleCheck::Probe => {
+                let probe = std::fs::OpenOptions::new()
+                    .write(true)
+                    .custom_flags(libc::O_NONBLOCK | libc::O_CLOEXEC)
+                    .open(&path);
+                match probe {
+                    Ok(_) => Ok(true),
+                    Err(e) if e.raw_os_error() == Some(libc::ENXIO) => Ok(false),
+                    Err(e) => Err(e),
+                }
+            }
+        };
+        match in_use {
+            Ok(true) => report.in_use.push(path),
+            Ok(false) => match std::fs::remove_file(&path) {
+                Ok(()) => report.removed.push(path),
+                Err(e) if e.kind() == async_std::io::ErrorKind::NotFound => {}
+                Err(e) => report.failed.push((path, e)),
+            },
+            Err(e) if e.kind() == async_std::io::ErrorKind::NotFound => {}
+            Err(e) => report.failed.push((path, e)),
+        }
+    }
+    Ok(report)
+}
+
+/// Returns the device and inode numbers of all files that processes have
+/// open, as far as `/proc` shows them to this process.
+fn open_handles_in_proc() -> async_std::io::Result<HashSet<(u64, u64)>> {
+    let mut handles = HashSet::new();
+    for process in std::fs::read_dir("/proc")? {
+        let process = process?;
+        let name = process.file_name();
+        if !name.to_string_lossy().bytes().all(|b| b.is_ascii_digit()) {
+            continue;
+        }
+        // Processes that exited or that we may not inspect are skipped
+        let fds = match std::fs::read_dir(process.path().join("fd")) {
+            Ok(fds) => fds,
+            Err(_) => continue,
+        };
+        for fd in fds.flatten() {
+            if let Ok(target) = std::fs::metadata(fd.path()) {
+                handles.insert((target.dev(), target.ino()));
+            }
+        }
+    }
+    Ok(handles)
+}
+
+/// Looks up the ID of the user called `name` in the user database.
+///
+/// Fails with `NotFound` if there's no such user.
+pub fn user_id(name: &str) -> async_std::io::Result<Uid> {
+    let name = to_cstring(name)?;
+    let mut buf: Vec<libc::c_char> = vec![0; 1024];
+    loop {
+        let mut entry = MaybeUninit::<libc::passwd>::uninit();
+        let mut result = ptr::null_mut();
+        // Safety: all pointers are valid, and `buf.len()` is the size of `buf`.
+        let ret = unsafe {
+            libc::getpwnam_r(
+                name.as_ptr(),
+                entry.as_mut_ptr(),
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+        match ret {
+            0 if result.is_null() => return Err(not_found("user", &name)),
+            // Safety: `entry` was filled in, as `result` points to it.
+            0 => return Ok(Uid::from_raw(unsafe { entry.assume_init() }.pw_uid)),
+            libc::ERANGE => buf.resize(buf.len() * 2, 0),
+            errno => return Err(async_std::io::Error::from_raw_os_error(errno)),
+        }
+    }
+}
+
+/// Looks up the ID of the group called `name` in the group database.
+///
+/// Fails with `NotFound` if there's no such group.
+pub fn group_id(name: &str) -> async_std::io::Result<Gid> {
+    let name = to_cstring(name)?;
+    let mut buf: Vec<libc::c_char> = vec![0; 1024];
+    loop {
+        let mut entry = MaybeUninit::<libc::group>::uninit();
+        let mut result = ptr::null_mut();
+        // Safety: all pointers are valid, and `buf.len()` is the size of `buf`.
+        let ret = unsafe {
+            libc::getgrnam_r(
+                name.as_ptr(),
+                entry.as_mut_ptr(),
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+        match ret {
+            0 if result.is_null() => return Err(not_found("group", &name)),
+            // Safety: `entry` was filled in, as `result` points to it.
+            0 => return Ok(Gid::from_raw(unsafe { entry.assume_init() }.gr_gid)),
+            libc::ERANGE => buf.resize(buf.len() * 2, 0),
+            errno => return Err(async_std::io::Error::from_raw_os_error(errno)),
+        }
+    }
+}
+
+fn to_cstring(name: &str) -> async_std::io::Result<CString> {
+    CString::new(name)
+        .map_err(|e| async_std::io::Error::new(async_std::io::ErrorKind::InvalidInput, e))
+}
+
+fn not_found(what: &str, name: &CString) -> async_std::io::Error {
+    async_std::io::Error::new(
+        async_std::io::ErrorKind::NotFound,
+        format!("no {} called {:?}", what, name),
+    )
 }
 
 #[cfg(test)]
@@ -41,4 +298,41 @@ mod tests {
         // Custom mode
         assert_stats_eq(Some(Mode::from_bits_truncate(0o644)));
     }
+    #[test]
+    fn look_up_names() {
+        assert_eq!(super::user_id("root").unwrap().as_raw(), 0);
+        // Group 0 isn't called "root" everywhere, e.g. it's "wheel" on BSDs
+        let group = unsafe { nix::libc::getgrgid(0) };
+        assert!(!group.is_null());
+        let name = unsafe { std::ffi::CStr::from_ptr((*group).gr_name) };
+        assert_eq!(super::group_id(name.to_str().unwrap()).unwrap().as_raw(), 0);
+        let err = super::user_id("no-such-user-here").unwrap_err();
+        assert_eq!(err.kind(), async_std::io::ErrorKind::NotFound);
+    }
+    #[test]
+    fn cleanup_stale() -> std::io::Result<()> {
+        use super::StaleCheck;
+        use std::os::unix::fs::OpenOptionsExt;
+        let dir = std::path::Path::new("./test_dir_47");
+        std::fs::create_dir_all(dir)?;
+        for name in &["busy", "young"] {
+            super::create_pipe(dir.join(name).as_path(), None).unwrap();
+        }
+        std::fs::write(dir.join("file"), b"not a pipe")?;
+        let _reader = std::fs::OpenOptions::new()
+            .read(true)
+            .custom_flags(nix::libc::O_NONBLOCK)
+            .open(dir.join("busy"))?;
+        for &check in &[StaleCheck::OpenHandles, StaleCheck::Probe] {
+            super::create_pipe(dir.join("stale").as_path(), None).unwrap();
+            let report =
+                super::cleanup_stale_pipes(dir, check, |path, _| !path.ends_with("young"))?;
+            assert_eq!(report.removed, [dir.join("stale")]);
+            assert_eq!(report.in_use, [dir.join("busy")]);
+            assert!(report.failed.is_empty());
+        }
+        assert!(dir.join("young").exists());
+        assert!(dir.join("file").exists());
+        std::fs::remove_dir_all(dir)
+    }
 }
@@ -0,0 +1,89 @@
+use async_std::io;
+use std::path::Path;
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+mod inotify {
+    use crate::util::nix_to_io;
+    use async_io::Async;
+    use async_std::io;
+    use nix::errno::Errno;
+    use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
+    use std::os::unix::io::{AsRawFd, RawFd};
+    use std::path::Path;
+
+    /// An inotify instance that's closed on drop.
+    pub(super) struct Instance(Inotify);
+
+    impl AsRawFd for Instance {
+        fn as_raw_fd(&self) -> RawFd {
+            self.0.as_raw_fd()
+        }
+    }
+
+    impl Drop for Instance {
+        fn drop(&mut self) {
+            let _ = nix::unistd::close(self.0.as_raw_fd());
+        }
+    }
+
+    pub(super) fn watch(dir: &Path) -> io::Result<Async<Instance>> {
+        let inotify =
+            Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC).map_err(nix_to_io)?;
+        let instance = Instance(inotify);
+        let flags = AddWatchFlags::IN_CREATE | AddWatchFlags::IN_MOVED_TO;
+        instance.0.add_watch(dir, flags).map_err(nix_to_io)?;
+        Async::new(instance)
+    }
+
+    pub(super) async fn changed(instance: &Async<Instance>) -> io::Result<()> {
+        loop {
+            instance.readable().await?;
+            match instance.get_ref().0.read_events() {
+                Ok(_) => return Ok(()),
+                Err(nix::Error::Sys(Errno::EAGAIN)) => {}
+                Err(e) => return Err(nix_to_io(e)),
+            }
+        }
+    }
+}
+
+/// Notifies about entries being created in a directory.
+pub(crate) struct DirWatch {
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    inner: async_io::Async<inotify::Instance>,
+}
+
+impl DirWatch {
+    /// Starts watching `dir`.
+    ///
+    /// Fails if `dir` doesn't exist, or if there's no way to watch it on
+    /// this system.
+    pub(crate) fn new(dir: &Path) -> io::Result<Self> {
+        #[cfg(any(target_os = "android", target_os = "linux"))]
+        {
+            Ok(Self {
+                inner: inotify::watch(dir)?,
+            })
+        }
+        #[cfg(not(any(target_os = "android", target_os = "linux")))]
+        {
+            let _ = dir;
+            Err(io::Error::new(
+                io::ErrorKind::Other,
+                "watching directories isn't supported on this system",
+            ))
+        }
+    }
+    /// Waits until something was created in or moved into the directory
+    /// since the last call.
+    pub(crate) async fn changed(&self) -> io::Result<()> {
+        #[cfg(any(target_os = "android", target_os = "linux"))]
+        {
+            inotify::changed(&self.inner).await
+        }
+        #[cfg(not(any(target_os = "android", target_os = "linux")))]
+        {
+            unreachable!("DirWatch can't be created on this system")
+        }
+    }
+}